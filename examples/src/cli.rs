@@ -9,7 +9,11 @@ use std::{
 use tokio::io::AsyncReadExt;
 use tracing::{info, trace};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use tsp::{cesr::Part, AsyncStore, Error, ExportVid, OwnedVid, ReceivedTspMessage, VerifiedVid};
+use tsp::{
+    cesr::{Node, NodeKind, Part},
+    definitions::CipherSuite,
+    AsyncStore, Error, Evidence, ExportVid, OwnedVid, ReceivedTspMessage, VerifiedVid,
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "tsp")]
@@ -53,6 +57,13 @@ enum Commands {
         username: String,
         #[arg(short, long)]
         alias: Option<String>,
+        #[arg(
+            long,
+            default_value = "HPKE-4",
+            value_parser = parse_cipher_suite,
+            help = "Cipher suite to receive messages with (HPKE-4, HPKE-3 or HPKE-0)"
+        )]
+        cipher_suite: CipherSuite,
     },
     #[command(arg_required_else_help = true)]
     CreatePeer { alias: String },
@@ -75,6 +86,8 @@ enum Commands {
         receiver_vid: String,
         #[arg(short, long)]
         non_confidential_data: Option<String>,
+        #[arg(short, long, help = "Send the message in the CESR text domain")]
+        text: bool,
     },
     #[command(arg_required_else_help = true)]
     Receive {
@@ -82,10 +95,16 @@ enum Commands {
         #[arg(short, long)]
         one: bool,
     },
+    #[command(arg_required_else_help = true)]
+    VerifyEvidence { file: PathBuf },
 }
 
 type Aliases = HashMap<String, String>;
 
+fn parse_cipher_suite(name: &str) -> Result<CipherSuite, String> {
+    CipherSuite::from_name(name).ok_or_else(|| format!("unknown cipher suite {name}"))
+}
+
 #[derive(Serialize, Deserialize)]
 struct DatabaseContents {
     data: Vec<ExportVid>,
@@ -164,10 +183,44 @@ fn print_message(message: &[u8]) {
     color_print_part(Some(parts.sender), 35);
     color_print_part(parts.receiver, 34);
     color_print_part(parts.nonconfidential_data, 32);
+    color_print_part(parts.plaintext, 32);
     color_print_part(parts.ciphertext, 33);
     color_print_part(Some(parts.signature), 36);
 
     println!();
+
+    let mut data = message.to_vec();
+    let Some(data) = tsp::cesr::to_binary(&mut data) else {
+        return;
+    };
+
+    let Ok(tree) = tsp::cesr::inspect_message(data) else {
+        eprintln!("Could not inspect encoded message");
+        return;
+    };
+
+    println!("CESR message structure:");
+
+    tree.walk(&mut |node: &Node, depth| {
+        print!(
+            "{:>6}  {:indent$}\x1b[1m{}\x1b[0m {} ({} bytes)",
+            node.range.start,
+            "",
+            node.code,
+            node.kind,
+            node.data.len(),
+            indent = 2 * depth
+        );
+
+        if matches!(
+            node.kind,
+            NodeKind::Sender | NodeKind::Receiver | NodeKind::Recipient | NodeKind::Hop
+        ) {
+            print!(": {}", String::from_utf8_lossy(&data[node.data.clone()]));
+        }
+
+        println!();
+    });
 }
 
 async fn run() -> Result<(), Error> {
@@ -213,7 +266,11 @@ async fn run() -> Result<(), Error> {
 
             print!("{vid}");
         }
-        Commands::Create { username, alias } => {
+        Commands::Create {
+            username,
+            alias,
+            cipher_suite,
+        } => {
             let did = format!("did:web:{server}:user:{username}");
 
             if let Some(alias) = alias {
@@ -224,7 +281,7 @@ async fn run() -> Result<(), Error> {
             let url = format!("https://{server}/user/{username}");
             let transport = url::Url::parse(&url).unwrap();
 
-            let private_vid = OwnedVid::bind(&did, transport);
+            let private_vid = OwnedVid::bind_with_cipher_suite(&did, transport, cipher_suite);
             info!("created identity {}", private_vid.identifier());
 
             reqwest::Client::new()
@@ -300,6 +357,7 @@ async fn run() -> Result<(), Error> {
             sender_vid,
             receiver_vid,
             non_confidential_data,
+            text,
         } => {
             let sender_vid = aliases.get(&sender_vid).unwrap_or(&sender_vid);
             let receiver_vid = aliases.get(&receiver_vid).unwrap_or(&receiver_vid);
@@ -312,10 +370,18 @@ async fn run() -> Result<(), Error> {
                 .await
                 .expect("Could not read message from stdin");
 
-            let cesr_message = match vid_database
-                .send(sender_vid, receiver_vid, non_confidential_data, &message)
-                .await
-            {
+            let result = if text {
+                vid_database
+                    .send_text(sender_vid, receiver_vid, non_confidential_data, &message)
+                    .await
+                    .map(String::into_bytes)
+            } else {
+                vid_database
+                    .send(sender_vid, receiver_vid, non_confidential_data, &message)
+                    .await
+            };
+
+            let cesr_message = match result {
                 Ok(m) => m,
                 Err(e) => {
                     tracing::error!(
@@ -344,14 +410,24 @@ async fn run() -> Result<(), Error> {
             while let Some(Ok(message)) = messages.next().await {
                 match message {
                     ReceivedTspMessage::GenericMessage {
-                        sender,
-                        nonconfidential_data: _,
-                        message,
-                        message_type: _,
+                        sender, message, ..
                     } => {
                         info!("received message ({} bytes) from {}", message.len(), sender,);
                         println!("{}", String::from_utf8_lossy(&message),);
                     }
+                    ReceivedTspMessage::MulticastMessage {
+                        sender, message, ..
+                    } => {
+                        info!(
+                            "received multicast message ({} bytes) from {}",
+                            message.len(),
+                            sender
+                        );
+                        println!("{}", String::from_utf8_lossy(&message),);
+                    }
+                    ReceivedTspMessage::CompressedMessage { sender, .. } => {
+                        info!("received compressed message from {}", sender);
+                    }
                     ReceivedTspMessage::RequestRelationship {
                         sender,
                         thread_id: _,
@@ -362,9 +438,33 @@ async fn run() -> Result<(), Error> {
                     ReceivedTspMessage::AcceptRelationship { sender } => {
                         info!("received accept relationship from {}", sender);
                     }
+                    ReceivedTspMessage::RejectRelationship { sender } => {
+                        info!("received reject relationship from {}", sender);
+                    }
                     ReceivedTspMessage::CancelRelationship { sender } => {
                         info!("received cancel relationship from {}", sender);
                     }
+                    ReceivedTspMessage::Receipt {
+                        sender,
+                        message_digest,
+                    } => {
+                        info!(
+                            "received receipt from {} for message {}",
+                            sender,
+                            Base64UrlUnpadded::encode_string(&message_digest)
+                        );
+                    }
+                    ReceivedTspMessage::StreamChunk {
+                        sender,
+                        index,
+                        data,
+                        ..
+                    } => {
+                        info!(
+                            "received stream chunk {index} ({} bytes) from {sender}",
+                            data.len()
+                        );
+                    }
                     ReceivedTspMessage::ForwardRequest {
                         sender, next_hop, ..
                     } => {
@@ -380,6 +480,32 @@ async fn run() -> Result<(), Error> {
                 }
             }
         }
+        Commands::VerifyEvidence { file } => {
+            let contents = tokio::fs::read_to_string(&file)
+                .await
+                .expect("Could not read evidence file");
+
+            let evidence: Evidence =
+                serde_json::from_str(&contents).expect("Could not deserialize evidence");
+
+            // the sender VID in the evidence is not trusted: check it against the sender VID
+            // that was verified when it was added to the database, without using the network
+            let sender = evidence.sender().identifier();
+            let nonconfidential_data = vid_database.verify_evidence(&evidence)?;
+
+            if args.pretty_print {
+                print_message(evidence.message());
+            }
+
+            info!(
+                "message from {sender} is authentic (evidence created at {})",
+                evidence.created()
+            );
+
+            if let Some(data) = nonconfidential_data {
+                println!("{}", String::from_utf8_lossy(data));
+            }
+        }
     }
 
     Ok(())
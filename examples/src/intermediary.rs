@@ -68,6 +68,12 @@ async fn new_message(
     Path(name): Path<String>,
     body: Bytes,
 ) -> Response {
+    // messages in the CESR text domain are routed in the binary domain
+    let mut body = body.to_vec();
+    let Some(body) = tsp::cesr::to_binary(&mut body) else {
+        return (StatusCode::BAD_REQUEST, "invalid message").into_response();
+    };
+
     let message: Vec<u8> = body.to_vec();
 
     tracing::debug!("{} received message inteded for {name}", state.domain);
@@ -263,6 +263,7 @@ fn open_message(message: &[u8], payload: Option<&[u8]>) -> Option<serde_json::Va
         "sender": format_part("Sender", &parts.sender, None),
         "receiver": parts.receiver.map(|v| format_part("Receiver", &v, None)),
         "nonconfidentialData": parts.nonconfidential_data.map(|v| format_part("Non-confidential data", &v, None)),
+        "plaintext": parts.plaintext.map(|v| format_part("Plaintext", &v, None)),
         "ciphertext": parts.ciphertext.map(|v| format_part("Ciphertext", &v, payload)),
         "signature": format_part("Signature", &parts.signature, None),
     }))
@@ -278,8 +279,14 @@ struct SendMessageForm {
 }
 
 async fn route_message(State(state): State<Arc<AppState>>, body: Bytes) -> Response {
-    let message: Vec<u8> = body.to_vec();
-    let Ok((sender, Some(receiver))) = tsp::cesr::get_sender_receiver(&message) else {
+    let mut message: Vec<u8> = body.to_vec();
+
+    // messages in the CESR text domain are forwarded in the binary domain
+    let Some(message) = tsp::cesr::to_binary(&mut message) else {
+        return (StatusCode::BAD_REQUEST, "invalid message").into_response();
+    };
+
+    let Ok((sender, Some(receiver))) = tsp::cesr::get_sender_receiver(message) else {
         return (StatusCode::BAD_REQUEST, "invalid message").into_response();
     };
 
@@ -289,7 +296,7 @@ async fn route_message(State(state): State<Arc<AppState>>, body: Bytes) -> Respo
     tracing::debug!("forwarded message {sender} {receiver}");
 
     // insert message in queue
-    let _ = state.tx.send((sender, receiver, body.to_vec()));
+    let _ = state.tx.send((sender, receiver, message.to_vec()));
 
     StatusCode::OK.into_response()
 }
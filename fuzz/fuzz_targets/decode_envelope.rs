@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tsp::cesr::decode_envelope::<&[u8]>(data);
+    let _ = tsp::cesr::get_sender_receiver(data);
+    let _ = tsp::cesr::get_multicast_recipients(data);
+    let _ = tsp::cesr::message_length(data);
+    let _ = tsp::cesr::open_message_into_parts(data);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buffer = data.to_vec();
+    let _ = tsp::cesr::probe(&mut buffer);
+
+    let mut buffer = data.to_vec();
+    if let Ok(view) = tsp::cesr::decode_envelope_mut(&mut buffer) {
+        let _ = view.as_challenge();
+        let _ = view.into_opened::<&[u8]>();
+    }
+});
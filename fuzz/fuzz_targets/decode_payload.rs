@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tsp::cesr::decode_payload::<&[u8]>(data);
+
+    let mut data = data.to_vec();
+    let _ = tsp::cesr::decode_payload_mut::<&[u8]>(&mut data);
+});
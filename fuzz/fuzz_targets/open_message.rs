@@ -0,0 +1,30 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+use tsp::{OwnedVid, Store};
+
+/// A store that knows the private VIDs of the test users, so that the fuzzer can get past
+/// the lookup of the sender and receiver of a message
+fn store() -> &'static Store {
+    static STORE: OnceLock<Store> = OnceLock::new();
+
+    STORE.get_or_init(|| {
+        let store = Store::new();
+
+        for vid in [
+            include_str!("../../examples/test/alice.json"),
+            include_str!("../../examples/test/bob.json"),
+        ] {
+            let vid: OwnedVid = serde_json::from_str(vid).unwrap();
+            store.add_private_vid(vid).unwrap();
+        }
+
+        store
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut message = data.to_vec();
+    let _ = store().open_message(&mut message);
+});
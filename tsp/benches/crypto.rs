@@ -0,0 +1,81 @@
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use tsp::{definitions::Payload, OwnedVid};
+
+/// Message sizes, from a few bytes to a few megabytes
+const SIZES: [usize; 5] = [16, 1024, 64 * 1024, 1024 * 1024, 4 * 1024 * 1024];
+
+fn new_vid() -> OwnedVid {
+    OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap())
+}
+
+/// Compare sealing into a fresh buffer for every message with reusing a single buffer
+fn seal(c: &mut Criterion) {
+    let alice = new_vid();
+    let bob = new_vid();
+
+    let mut group = c.benchmark_group("seal");
+
+    for size in SIZES {
+        let message = vec![0x42; size];
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("allocating", size),
+            &message,
+            |b, message| {
+                b.iter(|| {
+                    tsp::crypto::seal(&alice, &bob, None, Payload::Content(black_box(message)))
+                        .unwrap()
+                })
+            },
+        );
+
+        group.bench_with_input(BenchmarkId::new("reusing", size), &message, |b, message| {
+            let mut buffer = Vec::new();
+
+            b.iter(|| {
+                buffer.clear();
+                tsp::crypto::seal_into(
+                    &alice,
+                    &bob,
+                    None,
+                    Payload::Content(black_box(message)),
+                    &mut buffer,
+                )
+                .unwrap();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn open(c: &mut Criterion) {
+    let alice = new_vid();
+    let bob = new_vid();
+
+    let mut group = c.benchmark_group("open");
+
+    for size in SIZES {
+        let message = vec![0x42; size];
+        let sealed = tsp::crypto::seal(&alice, &bob, None, Payload::Content(&message)).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &sealed, |b, sealed| {
+            b.iter_batched_ref(
+                || sealed.clone(),
+                |sealed| {
+                    tsp::crypto::open(&bob, &alice, sealed).unwrap();
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, seal, open);
+criterion_main!(benches);
@@ -0,0 +1,217 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use tsp::{OwnedVid, ReceivedTspMessage, Store, VerifiedVid};
+
+/// Message sizes, from a few bytes to a few megabytes
+const SIZES: [usize; 5] = [16, 1024, 64 * 1024, 1024 * 1024, 4 * 1024 * 1024];
+
+fn new_vid() -> OwnedVid {
+    OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap())
+}
+
+/// A store that contains all parties of a direct, a nested and a routed relationship
+struct Setup {
+    store: Store,
+    alice: OwnedVid,
+    bob: OwnedVid,
+    nested_alice: OwnedVid,
+    nested_bob: OwnedVid,
+    intermediary: OwnedVid,
+    dropoff: OwnedVid,
+    routed_bob: OwnedVid,
+}
+
+impl Setup {
+    fn new() -> Self {
+        let setup = Setup {
+            store: Store::new(),
+            alice: new_vid(),
+            bob: new_vid(),
+            nested_alice: new_vid(),
+            nested_bob: new_vid(),
+            intermediary: new_vid(),
+            dropoff: new_vid(),
+            routed_bob: new_vid(),
+        };
+
+        let store = &setup.store;
+        for vid in [
+            &setup.alice,
+            &setup.bob,
+            &setup.nested_alice,
+            &setup.nested_bob,
+            &setup.intermediary,
+            &setup.dropoff,
+            &setup.routed_bob,
+        ] {
+            store.add_private_vid(vid.clone()).unwrap();
+        }
+
+        // nested mode
+        store
+            .set_parent_for_vid(
+                setup.nested_alice.identifier(),
+                Some(setup.alice.identifier()),
+            )
+            .unwrap();
+        store
+            .set_parent_for_vid(setup.nested_bob.identifier(), Some(setup.bob.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(
+                setup.nested_bob.identifier(),
+                Some(setup.nested_alice.identifier()),
+            )
+            .unwrap();
+
+        // routed mode: alice -> intermediary -> drop-off -> routed bob
+        store
+            .set_route_for_vid(
+                setup.routed_bob.identifier(),
+                &[setup.intermediary.identifier(), setup.dropoff.identifier()],
+            )
+            .unwrap();
+        store
+            .set_relation_for_vid(
+                setup.routed_bob.identifier(),
+                Some(setup.alice.identifier()),
+            )
+            .unwrap();
+        store
+            .set_relation_for_vid(
+                setup.intermediary.identifier(),
+                Some(setup.alice.identifier()),
+            )
+            .unwrap();
+        store
+            .set_relation_for_vid(
+                setup.dropoff.identifier(),
+                Some(setup.routed_bob.identifier()),
+            )
+            .unwrap();
+
+        setup
+    }
+
+    /// The sender and receiver for each mode
+    fn modes(&self) -> [(&'static str, &str, &str); 3] {
+        [
+            ("direct", self.alice.identifier(), self.bob.identifier()),
+            (
+                "nested",
+                self.nested_alice.identifier(),
+                self.nested_bob.identifier(),
+            ),
+            (
+                "routed",
+                self.alice.identifier(),
+                self.routed_bob.identifier(),
+            ),
+        ]
+    }
+}
+
+fn seal_message(c: &mut Criterion) {
+    let setup = Setup::new();
+    let mut group = c.benchmark_group("seal_message");
+
+    for size in SIZES {
+        let message = vec![0x42; size];
+        group.throughput(Throughput::Bytes(size as u64));
+
+        for (mode, sender, receiver) in setup.modes() {
+            group.bench_with_input(BenchmarkId::new(mode, size), &message, |b, message| {
+                b.iter(|| {
+                    setup
+                        .store
+                        .seal_message(sender, receiver, None, message)
+                        .unwrap()
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn open_message(c: &mut Criterion) {
+    let setup = Setup::new();
+    let mut group = c.benchmark_group("open_message");
+
+    for size in SIZES {
+        let message = vec![0x42; size];
+        group.throughput(Throughput::Bytes(size as u64));
+
+        // a routed message is opened by the intermediary, see `route_message`
+        for (mode, sender, receiver) in &setup.modes()[..2] {
+            let (_, sealed) = setup
+                .store
+                .seal_message(sender, receiver, None, &message)
+                .unwrap();
+
+            group.bench_with_input(BenchmarkId::new(*mode, size), &sealed, |b, sealed| {
+                b.iter_batched_ref(
+                    || sealed.clone(),
+                    |sealed| {
+                        setup
+                            .store
+                            .open_message_borrowed(sealed)
+                            .unwrap()
+                            .into_owned()
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn route_message(c: &mut Criterion) {
+    let setup = Setup::new();
+    let mut group = c.benchmark_group("route_message");
+
+    for size in SIZES {
+        let message = vec![0x42; size];
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let (_, sealed) = setup
+            .store
+            .seal_message(
+                setup.alice.identifier(),
+                setup.routed_bob.identifier(),
+                None,
+                &message,
+            )
+            .unwrap();
+
+        // make sure that a successful forward is measured
+        let ReceivedTspMessage::ForwardRequest { .. } =
+            setup.store.open_message(&mut sealed.clone()).unwrap()
+        else {
+            panic!("expected a forward request");
+        };
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &sealed, |b, sealed| {
+            b.iter_batched_ref(
+                || sealed.clone(),
+                |sealed| {
+                    setup
+                        .store
+                        .route_message(
+                            setup.alice.identifier(),
+                            setup.intermediary.identifier(),
+                            sealed,
+                        )
+                        .unwrap()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, seal_message, open_message, route_message);
+criterion_main!(benches);
@@ -1,12 +1,26 @@
 use crate::{
+    crypto::RatchetState,
     definitions::{Digest, Payload, ReceivedTspMessage, TSPStream, VerifiedVid},
     error::Error,
-    store::{ExportVid, RelationshipStatus, Store},
+    evidence::Evidence,
+    relationship::{RelationshipDecision, RelationshipEvent, RelationshipPolicy},
+    store::{ExportVid, Store},
+    stream::{read_chunk, PayloadReader, CHUNK_SIZE},
     PrivateVid,
 };
 use futures::StreamExt;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{io::AsyncRead, sync::oneshot};
 use url::Url;
 
+/// Receipts that are awaited, by the digest of the message they refer to; with the VID that
+/// should send the receipt
+type PendingReceipts = Arc<Mutex<HashMap<Digest, (String, oneshot::Sender<()>)>>>;
+
 /// Holds private ands verified VIDs
 /// A Store contains verified VIDs, our relationship status to them,
 /// as well as the private VIDs that this application has control over.
@@ -38,6 +52,40 @@ use url::Url;
 #[derive(Default)]
 pub struct AsyncStore {
     inner: Store,
+    pending_receipts: PendingReceipts,
+    relationship_policy: Arc<Mutex<RelationshipPolicy>>,
+}
+
+/// A receipt that was requested using [AsyncStore::send_with_receipt]
+pub struct PendingReceipt {
+    digest: Digest,
+    receipt: oneshot::Receiver<()>,
+    pending_receipts: PendingReceipts,
+}
+
+impl PendingReceipt {
+    /// The digest of the message that the receipt refers to
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    /// Wait until the receipt arrives, or fail if it did not arrive within `timeout`.
+    /// Receipts are only noticed while the messages for the sender are received
+    /// using [AsyncStore::receive].
+    pub async fn wait(mut self, timeout: Duration) -> Result<(), Error> {
+        match tokio::time::timeout(timeout, &mut self.receipt).await {
+            Ok(Ok(())) => Ok(()),
+            _ => Err(Error::MissingReceipt),
+        }
+    }
+}
+
+impl Drop for PendingReceipt {
+    fn drop(&mut self) {
+        if let Ok(mut pending_receipts) = self.pending_receipts.lock() {
+            pending_receipts.remove(&self.digest);
+        }
+    }
 }
 
 impl AsyncStore {
@@ -61,15 +109,6 @@ impl AsyncStore {
         self.inner.set_relation_for_vid(vid, relation_vid)
     }
 
-    /// Sets the relationship status for a VID
-    pub(super) fn set_relation_status_for_vid(
-        &self,
-        vid: &str,
-        relation_status: RelationshipStatus,
-    ) -> Result<(), Error> {
-        self.inner.set_relation_status_for_vid(vid, relation_status)
-    }
-
     /// Adds a route to an already existing vid, making it a nested Vid
     pub fn set_route_for_vid(&self, vid: &str, route: &[&str]) -> Result<(), Error> {
         self.inner.set_route_for_vid(vid, route)
@@ -163,6 +202,226 @@ impl AsyncStore {
         Ok(message)
     }
 
+    /// Send a TSP message like [AsyncStore::send], but encoded in the CESR text domain, so it
+    /// can be passed through text-only channels. Returns the text-encoded message.
+    #[cfg(feature = "cesr-t")]
+    pub async fn send_text(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<String, Error> {
+        let (endpoint, message) =
+            self.inner
+                .seal_message(sender, receiver, nonconfidential_data, message)?;
+        let message = crate::cesr::to_text(&message);
+
+        tracing::info!("sending text-encoded message to {endpoint}");
+
+        crate::transport::send_text_message(&endpoint, &message).await?;
+
+        Ok(message)
+    }
+
+    /// Send a TSP message like [AsyncStore::send], but compress the message before it is
+    /// encrypted (see [Store::seal_compressed_message])
+    pub async fn send_compressed(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let (endpoint, message) =
+            self.inner
+                .seal_compressed_message(sender, receiver, nonconfidential_data, message)?;
+
+        tracing::info!("sending compressed message to {endpoint}");
+
+        crate::transport::send_message(&endpoint, &message).await?;
+
+        Ok(message)
+    }
+
+    /// Send a TSP message like [AsyncStore::send], but hide the sender from anyone but the
+    /// receiver (see [Store::seal_message_hidden_sender])
+    pub async fn send_hidden_sender(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let (endpoint, message) = self.inner.seal_message_hidden_sender(
+            sender,
+            receiver,
+            nonconfidential_data,
+            message,
+        )?;
+
+        tracing::info!("sending message with a hidden sender to {endpoint}");
+
+        crate::transport::send_message(&endpoint, &message).await?;
+
+        Ok(message)
+    }
+
+    /// Send a TSP message like [AsyncStore::send], but add a unique message identifier and
+    /// the current time, so the receiver can reject replayed or stale messages
+    /// (see [Store::seal_fresh_message])
+    pub async fn send_fresh(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let (endpoint, message) =
+            self.inner
+                .seal_fresh_message(sender, receiver, nonconfidential_data, message)?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        crate::transport::send_message(&endpoint, &message).await?;
+
+        Ok(message)
+    }
+
+    /// Send a TSP message like [AsyncStore::send], and request a receipt from the receiver.
+    /// The receiver automatically sends a signed receipt when it receives the message
+    /// using [AsyncStore::receive]; the returned [PendingReceipt] can be used to wait for it.
+    pub async fn send_with_receipt(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<PendingReceipt, Error> {
+        let (endpoint, message, digest) = self.inner.seal_message_with_receipt(
+            sender,
+            receiver,
+            nonconfidential_data,
+            message,
+        )?;
+
+        let (notify, receipt) = oneshot::channel();
+        self.pending_receipts
+            .lock()?
+            .insert(digest, (receiver.to_string(), notify));
+
+        let pending_receipt = PendingReceipt {
+            digest,
+            receipt,
+            pending_receipts: self.pending_receipts.clone(),
+        };
+
+        tracing::info!("sending message to {endpoint}");
+
+        crate::transport::send_message(&endpoint, &message).await?;
+
+        Ok(pending_receipt)
+    }
+
+    /// Send a TSP message like [AsyncStore::send], as part of the conversation `thread_id`;
+    /// optionally as a reply to the message with digest `reply_to` (see [crate::MessageThread]).
+    /// Returns the digest of the message, which a reply can refer to.
+    pub async fn send_threaded(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        thread_id: Digest,
+        reply_to: Option<Digest>,
+        message: &[u8],
+    ) -> Result<Digest, Error> {
+        let (endpoint, message, digest) = self.inner.seal_threaded_message(
+            sender,
+            receiver,
+            nonconfidential_data,
+            thread_id,
+            reply_to,
+            message,
+        )?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        crate::transport::send_message(&endpoint, &message).await?;
+
+        Ok(digest)
+    }
+
+    /// Send a (large) payload read from `payload` as a sequence of TSP messages, that each contain
+    /// a chunk of the payload, the identifier of the stream and the position of the chunk in it.
+    /// The receiver can reassemble the payload using [AsyncStore::receive_stream].
+    /// Returns the identifier of the stream.
+    ///
+    /// Streams can not be sent over a nested or ratcheted relationship.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender`               - A sender VID
+    /// * `receiver`             - A receiver VID
+    /// * `nonconfidential_data` - Optional extra non-confidential data, added to every chunk
+    /// * `payload`              - The reader that provides the payload
+    pub async fn send_stream(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        mut payload: impl AsyncRead + Unpin,
+    ) -> Result<Digest, Error> {
+        let stream_id: Digest = rand::random();
+
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut next_chunk = vec![0; CHUNK_SIZE];
+        let mut len = read_chunk(&mut payload, &mut chunk).await?;
+
+        for index in 0.. {
+            // read ahead, to find out whether this is the last chunk
+            let next_len = if len == CHUNK_SIZE {
+                read_chunk(&mut payload, &mut next_chunk).await?
+            } else {
+                0
+            };
+            let last = next_len == 0;
+
+            let (transport, tsp_message) = self.inner.seal_message_payload(
+                sender,
+                receiver,
+                nonconfidential_data,
+                Payload::StreamChunk {
+                    stream_id,
+                    index,
+                    last,
+                    data: &chunk[..len],
+                },
+            )?;
+
+            tracing::info!("sending stream chunk {index} to {transport}");
+
+            crate::transport::send_message(&transport, &tsp_message).await?;
+
+            if last {
+                break;
+            }
+
+            std::mem::swap(&mut chunk, &mut next_chunk);
+            len = next_len;
+        }
+
+        Ok(stream_id)
+    }
+
+    /// Receive a payload that was sent using [AsyncStore::send_stream] for the private VID
+    /// identified by `vid`. The returned reader yields the payload of the first stream that
+    /// arrives, and fails if the stream is incomplete or contains duplicate chunks.
+    pub async fn receive_stream(&self, vid: &str) -> Result<PayloadReader, Error> {
+        let messages = self.receive(vid).await?;
+
+        Ok(PayloadReader::new(messages))
+    }
+
     /// Request a direct relationship with a resolved VID using the TSP
     /// Encodes the control message, encrypts, signs and sends a TSP message
     ///
@@ -194,6 +453,50 @@ impl AsyncStore {
         sender: &str,
         receiver: &str,
         route: Option<&[&str]>,
+    ) -> Result<(), Error> {
+        self.request_relationship(sender, receiver, route, false)
+            .await
+    }
+
+    /// Request a direct relationship like [AsyncStore::send_relationship_request], and propose
+    /// to protect it with a ratchet: if the receiver accepts the ratchet, messages sent with
+    /// [AsyncStore::send] in this relationship are encrypted with keys that change for every
+    /// message, so that they stay confidential if the keys of the VIDs are compromised later.
+    ///
+    /// The state of the ratchet is only kept in memory; it is not exported. After an import,
+    /// messages can not be sent in the relationship until its ratchet is set up again with
+    /// [AsyncStore::reset_ratcheted_relationship].
+    pub async fn send_ratcheted_relationship_request(
+        &self,
+        sender: &str,
+        receiver: &str,
+        route: Option<&[&str]>,
+    ) -> Result<(), Error> {
+        self.request_relationship(sender, receiver, route, true)
+            .await
+    }
+
+    /// Form the relationship between `sender` and `receiver` again with a new ratchet, e.g.
+    /// because its ratchet was lost: the relationship is cancelled, and a ratcheted
+    /// relationship is requested (see [AsyncStore::send_ratcheted_relationship_request])
+    pub async fn reset_ratcheted_relationship(
+        &self,
+        sender: &str,
+        receiver: &str,
+        route: Option<&[&str]>,
+    ) -> Result<(), Error> {
+        self.send_relationship_cancel(sender, receiver).await?;
+
+        self.request_relationship(sender, receiver, route, true)
+            .await
+    }
+
+    async fn request_relationship(
+        &self,
+        sender: &str,
+        receiver: &str,
+        route: Option<&[&str]>,
+        ratchet: bool,
     ) -> Result<(), Error> {
         let sender = self.inner.get_private_vid(sender)?;
         let receiver = self.inner.get_verified_vid(receiver)?;
@@ -201,11 +504,22 @@ impl AsyncStore {
         let path = route;
         let route = route.map(|collection| collection.iter().map(|vid| vid.as_ref()).collect());
 
+        let (ratchet, ratchet_key) = match ratchet {
+            true => {
+                let (ratchet, ratchet_key) = RatchetState::propose();
+                (Some(ratchet), Some(ratchet_key))
+            }
+            false => (None, None),
+        };
+
         let (tsp_message, thread_id) = crate::crypto::seal_and_hash(
             &*sender,
             &*receiver,
             None,
-            Payload::RequestRelationship { route },
+            Payload::RequestRelationship {
+                route,
+                ratchet_key: ratchet_key.as_ref(),
+            },
         )?;
 
         if let Some(hop_list) = path {
@@ -215,16 +529,15 @@ impl AsyncStore {
             crate::transport::send_message(receiver.endpoint(), &tsp_message).await?;
         }
 
-        self.set_relation_status_for_vid(
-            receiver.identifier(),
-            RelationshipStatus::Unidirectional(thread_id),
-        )?;
+        self.inner
+            .request_relationship(receiver.identifier(), thread_id, ratchet)?;
 
         Ok(())
     }
 
     /// Accept a direct relationship between the resolved VIDs identifier by `sender` and `receiver`.
     /// `thread_id` must be the same as the one that was present in the relationship request.
+    /// If the request proposed a ratchet, it is accepted as well.
     /// Encodes the control message, encrypts, signs and sends a TSP message
     pub async fn send_relationship_accept(
         &self,
@@ -233,12 +546,9 @@ impl AsyncStore {
         thread_id: Digest,
         route: Option<&[&str]>,
     ) -> Result<(), Error> {
-        let (transport, tsp_message) = self.inner.seal_message_payload(
-            sender,
-            receiver,
-            None,
-            Payload::AcceptRelationship { thread_id },
-        )?;
+        let (transport, tsp_message, ratchet) = self
+            .inner
+            .seal_relationship_accept(sender, receiver, thread_id)?;
 
         if let Some(hop_list) = route {
             self.resolve_route_and_send(hop_list, &tsp_message).await?;
@@ -247,31 +557,50 @@ impl AsyncStore {
             crate::transport::send_message(&transport, &tsp_message).await?;
         }
 
-        self.set_relation_status_for_vid(receiver, RelationshipStatus::Bidirectional(thread_id))?;
+        self.inner
+            .accept_relationship(receiver, thread_id, ratchet)?;
 
         Ok(())
     }
 
-    /// Cancels a direct relationship between the resolved `sender` and `receiver` VIDs.
+    /// Decline the direct relationship that `receiver` requested from `sender` with `thread_id`,
+    /// so that the requester does not have to wait for an answer.
     /// Encodes the control message, encrypts, signs and sends a TSP message
-    pub async fn send_relationship_cancel(
+    pub async fn send_relationship_reject(
         &self,
         sender: &str,
         receiver: &str,
+        thread_id: Digest,
+        route: Option<&[&str]>,
     ) -> Result<(), Error> {
-        self.set_relation_status_for_vid(receiver, RelationshipStatus::Unrelated)?;
+        let (transport, message) = self
+            .inner
+            .seal_relationship_reject(sender, receiver, thread_id)?;
+
+        if let Some(hop_list) = route {
+            self.resolve_route_and_send(hop_list, &message).await?;
+        } else {
+            crate::transport::send_message(&transport, &message).await?;
+        }
 
-        let thread_id = Default::default(); // FNORD
+        self.inner.reject_relationship(receiver, thread_id)?;
 
-        let (transport, message) = self.inner.seal_message_payload(
-            sender,
-            receiver,
-            None,
-            Payload::CancelRelationship { thread_id },
-        )?;
+        Ok(())
+    }
+
+    /// Cancels a direct relationship between the resolved `sender` and `receiver` VIDs.
+    /// Encodes the control message, encrypts, signs and sends a TSP message
+    pub async fn send_relationship_cancel(
+        &self,
+        sender: &str,
+        receiver: &str,
+    ) -> Result<(), Error> {
+        let (transport, message) = self.inner.seal_relationship_cancel(sender, receiver)?;
 
         crate::transport::send_message(&transport, &message).await?;
 
+        self.inner.cancel_relationship(receiver)?;
+
         Ok(())
     }
 
@@ -297,20 +626,7 @@ impl AsyncStore {
         hop_list: &[&str],
         opaque_message: &[u8],
     ) -> Result<(), Error> {
-        let Some(next_hop) = hop_list.first() else {
-            return Err(Error::InvalidRoute(
-                "relationship route must not be empty".into(),
-            ));
-        };
-
-        let next_hop = self.inner.get_verified_vid(next_hop)?;
-        //TODO: can we avoid the allocation here?
-        let path = hop_list[1..].iter().map(|x| x.as_bytes()).collect();
-
-        self.forward_routed_message(next_hop.identifier(), path, opaque_message)
-            .await?;
-
-        Ok(())
+        resolve_route_and_send(&self.inner, hop_list, opaque_message).await
     }
 
     /// Pass along a in-transit routed TSP `opaque_message` that is not meant for us, given earlier resolved VIDs.
@@ -330,23 +646,201 @@ impl AsyncStore {
         Ok(transport)
     }
 
+    /// Set the policy that decides about relationship requests received with [AsyncStore::receive];
+    /// by default, all requests are left to the application
+    pub fn set_relationship_policy(&self, policy: RelationshipPolicy) -> Result<(), Error> {
+        *self.relationship_policy.lock()? = policy;
+
+        Ok(())
+    }
+
     /// Receive TSP messages for the private VID identified by `vid`, using the appropriate transport mechanism for it.
     /// Messages will be queued in a channel
     /// The returned channel contains a maximum of 16 messages
+    ///
+    /// Receipts that were requested by the sender of a message are sent automatically,
+    /// and received receipts complete the corresponding [PendingReceipt].
+    ///
+    /// Relationship requests are accepted or rejected automatically if the relationship policy
+    /// (see [AsyncStore::set_relationship_policy]) decides so, and are still returned afterwards;
+    /// requests that the policy ignores are not returned.
     pub async fn receive(&self, vid: &str) -> Result<TSPStream<ReceivedTspMessage, Error>, Error> {
         let receiver = self.inner.get_private_vid(vid)?;
         let messages = crate::transport::receive_messages(receiver.endpoint()).await?;
 
         let db = self.inner.clone();
-        Ok(Box::pin(messages.then(move |message| {
+        let pending_receipts = self.pending_receipts.clone();
+        let relationship_policy = self.relationship_policy.clone();
+        let messages = messages.then(move |message| {
             let db_inner = db.clone();
+            let pending_receipts = pending_receipts.clone();
+            let relationship_policy = relationship_policy.clone();
             async move {
+                // the relationship policy decides about a request before it changes the
+                // relationship; the decision is kept with the VID the request was addressed to
+                let (message, decision) = match message {
+                    Ok(mut m) => {
+                        let mut policy = relationship_policy.lock()?;
+                        let mut decision = None;
+                        let message = db_inner
+                            .open_message_deciding(&mut m, &mut |receiver, sender| {
+                                let decided = policy.decide(receiver, sender);
+                                decision = Some((receiver.to_string(), decided));
+
+                                decided
+                            })?
+                            .into_decompressed()?;
+
+                        (message, decision)
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
                 match message {
-                    Ok(mut m) => db_inner.clone().open_message(&mut m),
-                    Err(e) => Err(e.into()),
+                    ReceivedTspMessage::GenericMessage {
+                        ref sender,
+                        receiver: Some(ref receiver),
+                        receipt_requested: Some(digest),
+                        ..
+                    } => {
+                        if let Err(e) = send_receipt(&db_inner, receiver, sender, digest).await {
+                            tracing::warn!("could not send receipt to {sender}: {e}");
+                        }
+                    }
+                    ReceivedTspMessage::Receipt {
+                        ref sender,
+                        ref message_digest,
+                    } => {
+                        let mut pending_receipts = pending_receipts.lock()?;
+                        if pending_receipts
+                            .get(message_digest)
+                            .is_some_and(|(receiver, _)| receiver == sender)
+                        {
+                            if let Some((_, notify)) = pending_receipts.remove(message_digest) {
+                                let _ = notify.send(());
+                            }
+                        }
+                    }
+                    ReceivedTspMessage::RequestRelationship {
+                        ref sender,
+                        ref route,
+                        thread_id,
+                    } => {
+                        let (receiver, accept) = match decision {
+                            Some((receiver, RelationshipDecision::Accept)) => (receiver, true),
+                            Some((receiver, RelationshipDecision::Reject)) => (receiver, false),
+                            Some((_, RelationshipDecision::Ignore)) => return Ok(None),
+                            Some((_, RelationshipDecision::Defer)) | None => {
+                                return Ok(Some(message))
+                            }
+                        };
+
+                        if let Err(e) = answer_relationship_request(
+                            &db_inner,
+                            &receiver,
+                            sender,
+                            thread_id,
+                            route.as_deref(),
+                            accept,
+                        )
+                        .await
+                        {
+                            tracing::warn!(
+                                "could not answer relationship request of {sender}: {e}"
+                            );
+                        }
+                    }
+                    _ => {}
                 }
+
+                Ok(Some(message))
+            }
+        });
+
+        // messages that are ignored by the relationship policy are left out
+        Ok(Box::pin(
+            messages.filter_map(|message| async move { message.transpose() }),
+        ))
+    }
+
+    /// Let unanswered relationship requests expire (see [Store::expire_relationship_requests])
+    pub fn expire_relationship_requests(&self, timeout: Duration) -> Result<Vec<String>, Error> {
+        self.inner.expire_relationship_requests(timeout)
+    }
+
+    /// Subscribe to changes of the state of relationships (see [Store::relationship_events]),
+    /// which happen when relationship control messages are sent or received
+    pub fn relationship_events(&self) -> Result<TSPStream<RelationshipEvent, Error>, Error> {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.inner
+            .subscribe_relationships(Box::new(move |event| sender.send(event.clone()).is_ok()))?;
+
+        Ok(Box::pin(async_stream::stream! {
+            while let Some(event) = receiver.recv().await {
+                yield Ok(event);
             }
-        })))
+        }))
+    }
+
+    /// Send a confidential TSP message to a group of VIDs; the message is encrypted only once
+    /// and the same TSP message is delivered to every receiver
+    pub async fn send_multicast(
+        &self,
+        sender: &str,
+        receivers: impl IntoIterator<Item = impl AsRef<str>>,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let receivers = receivers
+            .into_iter()
+            .map(|vid| self.inner.get_verified_vid(vid.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tsp_message = self.inner.seal_multicast(
+            sender,
+            receivers.iter().map(|vid| vid.identifier()),
+            nonconfidential_data,
+            message,
+        )?;
+
+        for receiver in receivers {
+            crate::transport::send_message(receiver.endpoint(), &tsp_message).await?;
+        }
+
+        Ok(tsp_message)
+    }
+
+    /// Send a signed, but unencrypted, TSP message to `receiver`; the non-confidential data
+    /// and the message can be read by anyone, but only `receiver` accepts it
+    /// (see [Store::sign_message])
+    pub async fn send_signed(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let (endpoint, message) =
+            self.inner
+                .sign_message(sender, receiver, nonconfidential_data, message)?;
+
+        tracing::info!("sending signed message to {endpoint}");
+
+        crate::transport::send_message(&endpoint, &message).await?;
+
+        Ok(message)
+    }
+
+    /// Create archival evidence of who sent the raw TSP `message`
+    /// (see [Store::create_evidence])
+    pub fn create_evidence(&self, message: &[u8]) -> Result<Evidence, Error> {
+        self.inner.create_evidence(message)
+    }
+
+    /// Verify evidence against the VID of its sender in this database, without resolving it
+    /// again (see [Store::verify_evidence])
+    pub fn verify_evidence<'a>(&self, evidence: &'a Evidence) -> Result<Option<&'a [u8]>, Error> {
+        self.inner.verify_evidence(evidence)
     }
 
     /// Send TSP broadcast message to the specified VIDs
@@ -367,3 +861,85 @@ impl AsyncStore {
         Ok(())
     }
 }
+
+/// Send a message given a route, extracting the next hop and verifying it in the process
+async fn resolve_route_and_send(
+    db: &Store,
+    hop_list: &[&str],
+    opaque_message: &[u8],
+) -> Result<(), Error> {
+    let Some(next_hop) = hop_list.first() else {
+        return Err(Error::InvalidRoute(
+            "relationship route must not be empty".into(),
+        ));
+    };
+
+    let next_hop = db.get_verified_vid(next_hop)?;
+    //TODO: can we avoid the allocation here?
+    let path = hop_list[1..].iter().map(|x| x.as_bytes()).collect();
+
+    let (transport, message) =
+        db.forward_routed_message(next_hop.identifier(), path, opaque_message)?;
+    crate::transport::send_message(&transport, &message).await?;
+
+    Ok(())
+}
+
+/// Accept or reject the relationship `thread_id` that `receiver` requested from `sender`,
+/// along the `route` of the request like [AsyncStore::send_relationship_accept];
+/// the relationship only changes once the answer was sent
+async fn answer_relationship_request(
+    db: &Store,
+    sender: &str,
+    receiver: &str,
+    thread_id: Digest,
+    route: Option<&[Vec<u8>]>,
+    accept: bool,
+) -> Result<(), Error> {
+    let route = route
+        .map(|hops| {
+            hops.iter()
+                .map(|hop| std::str::from_utf8(hop))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+
+    if accept {
+        let (transport, message, ratchet) =
+            db.seal_relationship_accept(sender, receiver, thread_id)?;
+
+        if let Some(hop_list) = &route {
+            resolve_route_and_send(db, hop_list, &message).await?;
+            db.set_route_for_vid(receiver, hop_list)?;
+        } else {
+            crate::transport::send_message(&transport, &message).await?;
+        }
+
+        db.accept_relationship(receiver, thread_id, ratchet)
+    } else {
+        let (transport, message) = db.seal_relationship_reject(sender, receiver, thread_id)?;
+
+        if let Some(hop_list) = &route {
+            resolve_route_and_send(db, hop_list, &message).await?;
+        } else {
+            crate::transport::send_message(&transport, &message).await?;
+        }
+
+        db.reject_relationship(receiver, thread_id)
+    }
+}
+
+/// Send a receipt from `sender` for the message with digest `message_digest` it received
+/// from `receiver`
+async fn send_receipt(
+    db: &Store,
+    sender: &str,
+    receiver: &str,
+    message_digest: Digest,
+) -> Result<(), Error> {
+    let (transport, receipt) = db.seal_receipt(sender, receiver, message_digest)?;
+
+    crate::transport::send_message(&transport, &receipt).await?;
+
+    Ok(())
+}
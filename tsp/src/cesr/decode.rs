@@ -1,4 +1,4 @@
-use super::{bits, extract_triplet, header_match, mask, selector::*};
+use super::{bits, error::DecodeError, extract_triplet, header_match, mask, selector::*};
 
 /// Decode fixed size data with a known identifier
 pub fn decode_fixed_data<'a, const N: usize>(
@@ -64,6 +64,41 @@ pub fn decode_variable_data_index(
         None
     }
 }
+/// Decode the header of variable size data, returning its identifier and the total size of the
+/// encoded data (including the header); returns `Ok(None)` if more data is needed to determine this
+pub fn decode_variable_data_header(stream: &[u8]) -> Result<Option<(u32, usize)>, DecodeError> {
+    let Some(header) = stream.get(0..=2) else {
+        return Ok(None);
+    };
+    let input = extract_triplet(header.try_into().unwrap());
+    let selector = input >> 18;
+
+    let size;
+    let found_id;
+
+    match selector {
+        D4 | D5 | D6 => {
+            found_id = input >> 12 & mask(6);
+            size = input & mask(12);
+        }
+        D7 | D8 | D9 => {
+            let Some(size_bytes) = stream.get(3..6) else {
+                return Ok(None);
+            };
+            found_id = input & mask(18);
+            size = extract_triplet(size_bytes.try_into().unwrap());
+        }
+        _ => return Err(DecodeError::UnexpectedData),
+    };
+
+    let offset = (selector - D4) as usize;
+
+    Ok(Some((
+        found_id,
+        (offset + 1).next_multiple_of(3) + 3 * size as usize,
+    )))
+}
+
 pub fn decode_variable_data<'a>(identifier: u32, stream: &mut &'a [u8]) -> Option<&'a [u8]> {
     let range = decode_variable_data_index(identifier, stream)?;
     let slice = &stream[range.start..range.end];
@@ -1,18 +1,53 @@
+use super::error::DecodeError;
 use base64ct::{Base64UrlUnpadded, Encoding};
 
-pub fn to_binary(data: &mut [u8]) -> Option<&[u8]> {
+/// Convert CESR encoded data to the binary domain, decoding it in place if it is in the
+/// text domain; returns `None` if the data is in neither domain
+pub fn to_binary(data: &mut [u8]) -> Option<&mut [u8]> {
     let first_byte = data.first()?;
 
     match first_byte >> 5 {
-        0b001 => Base64UrlUnpadded::decode_in_place(data).ok(), // CESR in the T domain
-        0b111 => Some(data),                                    // CESR in the B domain
+        0b001 => {
+            // CESR in the T domain
+            let len = Base64UrlUnpadded::decode_in_place(data).ok()?.len();
+            Some(&mut data[..len])
+        }
+        0b111 => Some(data), // CESR in the B domain
         _ => None,
     }
 }
 
+/// Convert CESR encoded data in the binary domain to the text domain
+pub fn to_text(data: &[u8]) -> String {
+    Base64UrlUnpadded::encode_string(data)
+}
+
+/// Determine the length of the TSP message in the CESR text domain at the start of `stream`,
+/// like [super::message_length] does in the binary domain; returns `None` if more data is needed
+pub fn text_message_length(stream: &[u8]) -> Result<Option<usize>, DecodeError> {
+    let text_len = stream
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == b'-' || **c == b'_')
+        .count();
+
+    // only whole quadlets can be converted to the binary domain
+    let mut binary = stream[..text_len - text_len % 4].to_vec();
+    let binary =
+        Base64UrlUnpadded::decode_in_place(&mut binary).map_err(|_| DecodeError::UnexpectedData)?;
+
+    match super::message_length(binary)? {
+        // every triplet in the binary domain is a quadlet in the text domain
+        Some(length) if length % 3 == 0 => Ok(Some(length / 3 * 4)),
+        Some(_) => Err(DecodeError::UnexpectedData),
+        // the message can not be completed if it is followed by something that is not text
+        None if text_len < stream.len() => Err(DecodeError::UnexpectedData),
+        None => Ok(None),
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::to_binary;
+    use super::{text_message_length, to_binary, to_text};
     use base64ct::{Base64UrlUnpadded, Encoding};
 
     #[test]
@@ -21,8 +56,34 @@ mod test {
         let binary = Base64UrlUnpadded::decode_vec(std::str::from_utf8(&base64).unwrap()).unwrap();
         assert_eq!(to_binary(&mut binary.clone()).unwrap(), binary);
         assert_eq!(to_binary(&mut base64.clone()).unwrap(), binary);
+        assert_eq!(to_text(&binary).as_bytes(), base64);
+
+        assert!(to_binary(&mut b"AAAA".clone()).is_none());
+        assert!(to_binary(&mut [0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_text_message_length() {
+        let url = url::Url::parse("tcp://127.0.0.1:1337").unwrap();
+        let alice = crate::OwnedVid::bind("did:test:alice", url.clone());
+        let bob = crate::OwnedVid::bind("did:test:bob", url);
+
+        let message = crate::crypto::seal(
+            &alice,
+            &bob,
+            None,
+            crate::definitions::Payload::Content(b"hello world"),
+        )
+        .unwrap();
+        let text = to_text(&message);
 
-        assert!(to_binary(b"AAAA").is_none());
-        assert!(to_binary([0, 0, 0]).is_none());
+        // messages that follow each other are split, and incomplete messages wait for more data
+        let stream = format!("{text}{text}");
+        assert_eq!(
+            text_message_length(stream.as_bytes()).unwrap(),
+            Some(text.len())
+        );
+        assert_eq!(text_message_length(&text.as_bytes()[..20]).unwrap(), None);
+        assert!(text_message_length(format!("{}\0", &text[..20]).as_bytes()).is_err());
     }
 }
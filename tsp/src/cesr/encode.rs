@@ -42,14 +42,41 @@ pub fn encode_indexed_data(
     stream.extend(payload);
 }
 
+/// The size of encoded fixed size data of `len` bytes
+pub const fn fixed_data_size(len: usize) -> usize {
+    (len + 1).next_multiple_of(3)
+}
+
+/// The size of encoded variable size data of `len` bytes with a known identifier
+pub const fn variable_data_size(identifier: u32, len: usize) -> usize {
+    let padded_size = len.next_multiple_of(3);
+
+    if padded_size / 3 < 64 * 64 && identifier < 64 {
+        3 + padded_size
+    } else {
+        6 + padded_size
+    }
+}
+
 /// Encode variable size data with a known identifier
 pub fn encode_variable_data(
     identifier: u32,
     payload: &[u8],
     stream: &mut impl for<'a> Extend<&'a u8>,
 ) {
-    let padded_size = payload.len().next_multiple_of(3);
-    let lead_bytes = padded_size - payload.len();
+    encode_variable_data_header(identifier, payload.len(), stream);
+    stream.extend(payload);
+}
+
+/// Encode the header (including lead bytes) of variable size data of `len` bytes with a
+/// known identifier; the data itself has to be appended after this
+pub fn encode_variable_data_header(
+    identifier: u32,
+    len: usize,
+    stream: &mut impl for<'a> Extend<&'a u8>,
+) {
+    let padded_size = len.next_multiple_of(3);
+    let lead_bytes = padded_size - len;
 
     let selector = D4 + lead_bytes as u32;
     let size = (padded_size / 3) as u32;
@@ -64,7 +91,6 @@ pub fn encode_variable_data(
     }
 
     stream.extend(&<[u8; 2]>::default()[0..lead_bytes]);
-    stream.extend(payload);
 }
 
 /// Encode a frame with known identifier and count code
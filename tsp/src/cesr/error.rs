@@ -14,6 +14,7 @@ pub enum DecodeError {
     VidError,
     VersionMismatch,
     MissingHops,
+    TooDeeplyNested,
 }
 
 impl std::fmt::Display for EncodeError {
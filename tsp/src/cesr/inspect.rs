@@ -0,0 +1,618 @@
+use super::{
+    decode::{decode_count, decode_fixed_data, decode_variable_data_index},
+    error::DecodeError,
+    packet::*,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use std::ops::Range;
+
+/// The meaning of an element of a CESR-encoded TSP message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    EncryptedEnvelope,
+    SignedEnvelope,
+    HiddenSenderEnvelope,
+    Version,
+    Sender,
+    Receiver,
+    NonConfidentialData,
+    Ciphertext,
+    RecipientList,
+    Recipient,
+    WrappedKey,
+    Signature,
+    Payload,
+    MessageType,
+    HopList,
+    Hop,
+    MessageData,
+    Nonce,
+    MessageId,
+    Timestamp,
+    ThreadId,
+    ReplyTo,
+    StreamId,
+    ChunkIndex,
+    RatchetIndex,
+    Digest,
+    SigningKey,
+    EncryptionKey,
+}
+
+impl std::fmt::Display for NodeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            NodeKind::EncryptedEnvelope => "encrypted envelope",
+            NodeKind::SignedEnvelope => "signed envelope",
+            NodeKind::HiddenSenderEnvelope => "hidden sender envelope",
+            NodeKind::Version => "version",
+            NodeKind::Sender => "sender",
+            NodeKind::Receiver => "receiver",
+            NodeKind::NonConfidentialData => "non-confidential data",
+            NodeKind::Ciphertext => "ciphertext",
+            NodeKind::RecipientList => "recipient list",
+            NodeKind::Recipient => "recipient",
+            NodeKind::WrappedKey => "wrapped key",
+            NodeKind::Signature => "signature",
+            NodeKind::Payload => "payload",
+            NodeKind::MessageType => "message type",
+            NodeKind::HopList => "hop list",
+            NodeKind::Hop => "hop",
+            NodeKind::MessageData => "message data",
+            NodeKind::Nonce => "nonce",
+            NodeKind::MessageId => "message id",
+            NodeKind::Timestamp => "timestamp",
+            NodeKind::ThreadId => "thread id",
+            NodeKind::ReplyTo => "reply to",
+            NodeKind::StreamId => "stream id",
+            NodeKind::ChunkIndex => "chunk index",
+            NodeKind::RatchetIndex => "ratchet index",
+            NodeKind::Digest => "digest",
+            NodeKind::SigningKey => "signing key",
+            NodeKind::EncryptionKey => "encryption key",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// An annotated element of a CESR-encoded TSP message.
+///
+/// Ranges are byte offsets into the inspected (binary domain) data; framing codes
+/// (envelopes, payloads, hop and recipient lists) span all of their children.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    /// The CESR code of this element, in the text domain
+    pub code: String,
+    /// The bytes of this element, including its code
+    pub range: Range<usize>,
+    /// The bytes of the contents of this element, excluding its code
+    pub data: Range<usize>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// Visit this node and all of its descendants in depth-first order, with their depth
+    pub fn walk(&self, visit: &mut impl FnMut(&Node, usize)) {
+        self.walk_at(0, visit)
+    }
+
+    fn walk_at(&self, depth: usize, visit: &mut impl FnMut(&Node, usize)) {
+        visit(self, depth);
+        for child in &self.children {
+            child.walk_at(depth + 1, visit);
+        }
+    }
+}
+
+/// Walks over CESR data, producing nodes with offsets relative to `base`
+struct Cursor<'a> {
+    stream: &'a [u8],
+    pos: usize,
+    base: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(stream: &'a [u8], base: usize) -> Self {
+        Cursor {
+            stream,
+            pos: 0,
+            base,
+        }
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.stream[self.pos..]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.stream.len()
+    }
+
+    /// Produce a node for the next `size` bytes, of which the first `header` are the code
+    fn advance(&mut self, kind: NodeKind, header: usize, size: usize) -> Node {
+        let code_bytes = &self.rest()[..header.next_multiple_of(3).min(size)];
+        let mut code = Base64UrlUnpadded::encode_string(code_bytes);
+        code.truncate(header * 4 / 3);
+
+        let start = self.base + self.pos;
+        self.pos += size;
+
+        Node {
+            kind,
+            code,
+            range: start..start + size,
+            data: start + header..start + size,
+            children: Vec::new(),
+        }
+    }
+
+    fn count(&mut self, identifier: u16, kind: NodeKind) -> Option<(Node, u16)> {
+        let count = decode_count(identifier, &mut self.rest())?;
+
+        Some((self.advance(kind, 3, 3), count))
+    }
+
+    fn fixed<const N: usize>(&mut self, identifier: u32, kind: NodeKind) -> Option<Node> {
+        decode_fixed_data::<N>(identifier, &mut self.rest())?;
+        let size = (N + 1).next_multiple_of(3);
+
+        Some(self.advance(kind, size - N, size))
+    }
+
+    fn variable(&mut self, identifier: u32, kind: NodeKind) -> Option<Node> {
+        let range = decode_variable_data_index(identifier, self.rest())?;
+        // variable size data either has a 3 or 6 byte code, followed by lead bytes
+        let header = if range.start >= 6 { 6 } else { 3 };
+        let mut node = self.advance(kind, header, range.end);
+        node.data.start = node.range.start + range.start;
+
+        Some(node)
+    }
+
+    /// Extend a framing node to span the given children
+    fn frame(&self, mut node: Node, children: Vec<Node>) -> Node {
+        node.range.end = self.base + self.pos;
+        node.data.end = node.range.end;
+        node.children = children;
+
+        node
+    }
+
+    /// The bytes that are described by a node produced by this cursor
+    fn bytes(&self, node: &Node) -> &'a [u8] {
+        &self.stream[node.data.start - self.base..node.data.end - self.base]
+    }
+}
+
+/// Produce an annotated tree of a CESR-encoded TSP message (in the binary domain).
+/// The ciphertext of an encrypted message is not opened, but the payload of a
+/// signed message and the recipient list of a multicast message are inspected.
+pub fn inspect_message(message: &[u8]) -> Result<Node, DecodeError> {
+    let mut cursor = Cursor::new(message, 0);
+    let node = inspect_envelope(&mut cursor, 0)?;
+
+    if cursor.is_empty() {
+        Ok(node)
+    } else {
+        Err(DecodeError::TrailingGarbage)
+    }
+}
+
+/// Produce an annotated tree of a CESR-encoded TSP payload, i.e. the plaintext of an
+/// encrypted message; nested and routed messages are inspected recursively.
+pub fn inspect_payload(payload: &[u8]) -> Result<Node, DecodeError> {
+    inspect_payload_at(payload, 0, 0)
+}
+
+/// The number of nested or routed messages that are inspected inside each other before
+/// giving up with [DecodeError::TooDeeplyNested]
+const MAX_NESTING_DEPTH: usize = 16;
+
+/// Inspect the payload of a message, ignoring payloads that can not be decoded (they may
+/// just be non-confidential data), unless they are nested too deeply
+fn inspect_inner<T>(result: Result<T, DecodeError>) -> Result<Option<T>, DecodeError> {
+    match result {
+        Ok(node) => Ok(Some(node)),
+        Err(DecodeError::TooDeeplyNested) => Err(DecodeError::TooDeeplyNested),
+        Err(_) => Ok(None),
+    }
+}
+
+fn inspect_envelope(cursor: &mut Cursor, depth: usize) -> Result<Node, DecodeError> {
+    if let Some((envelope, 1)) =
+        cursor.count(TSP_HIDDEN_SENDER_WRAPPER, NodeKind::HiddenSenderEnvelope)
+    {
+        return inspect_hidden_sender_envelope(cursor, envelope);
+    }
+
+    let (envelope, encrypted) =
+        if let Some((node, 1)) = cursor.count(TSP_ETS_WRAPPER, NodeKind::EncryptedEnvelope) {
+            (node, true)
+        } else if let Some((node, 1)) = cursor.count(TSP_S_WRAPPER, NodeKind::SignedEnvelope) {
+            (node, false)
+        } else {
+            return Err(DecodeError::VersionMismatch);
+        };
+
+    let mut children = vec![cursor
+        .fixed::<2>(TSP_TYPECODE, NodeKind::Version)
+        .ok_or(DecodeError::VersionMismatch)?];
+
+    children.push(
+        cursor
+            .variable(TSP_DEVELOPMENT_VID, NodeKind::Sender)
+            .ok_or(DecodeError::VidError)?,
+    );
+
+    let receiver = cursor.variable(TSP_DEVELOPMENT_VID, NodeKind::Receiver);
+    let multicast = receiver.is_none();
+    children.extend(receiver);
+
+    let nonconfidential_data = cursor.variable(TSP_PLAINTEXT, NodeKind::NonConfidentialData);
+
+    // signed messages carry their payload as non-confidential data, unless they also
+    // have non-confidential data: then the payload follows it
+    let signed_payload = match encrypted {
+        true => None,
+        false => cursor.variable(TSP_PLAINTEXT, NodeKind::MessageData),
+    };
+
+    let inspect = |node: &mut Node| -> Result<(), DecodeError> {
+        let payload = inspect_payload_at(cursor.bytes(node), node.data.start, depth);
+        node.children.extend(inspect_inner(payload)?);
+
+        Ok(())
+    };
+
+    match (nonconfidential_data, signed_payload) {
+        (Some(mut payload), None) if !encrypted => {
+            inspect(&mut payload)?;
+            children.push(payload);
+        }
+        (nonconfidential_data, signed_payload) => {
+            children.extend(nonconfidential_data);
+            if let Some(mut payload) = signed_payload {
+                inspect(&mut payload)?;
+                children.push(payload);
+            }
+        }
+    }
+
+    if encrypted {
+        let cipher_suite = detect_cipher_suite(cursor.rest()).ok_or(DecodeError::UnexpectedData)?;
+        let mut ciphertext = cursor
+            .variable(ciphertext_code(cipher_suite), NodeKind::Ciphertext)
+            .ok_or(DecodeError::UnexpectedData)?;
+
+        if multicast {
+            let mut inner = Cursor::new(cursor.bytes(&ciphertext), ciphertext.data.start);
+            ciphertext.children.push(inspect_recipients(&mut inner)?);
+        }
+        children.push(ciphertext);
+    }
+
+    children.push(
+        cursor
+            .fixed::<64>(ED25519_SIGNATURE, NodeKind::Signature)
+            .ok_or(DecodeError::SignatureError)?,
+    );
+
+    Ok(cursor.frame(envelope, children))
+}
+
+/// The sender and signature of a message with a hidden sender are part of its ciphertext
+fn inspect_hidden_sender_envelope(
+    cursor: &mut Cursor,
+    envelope: Node,
+) -> Result<Node, DecodeError> {
+    let mut children = vec![cursor
+        .fixed::<2>(TSP_TYPECODE, NodeKind::Version)
+        .ok_or(DecodeError::VersionMismatch)?];
+
+    children.push(
+        cursor
+            .variable(TSP_DEVELOPMENT_VID, NodeKind::Receiver)
+            .ok_or(DecodeError::VidError)?,
+    );
+    children.extend(cursor.variable(TSP_PLAINTEXT, NodeKind::NonConfidentialData));
+
+    let cipher_suite = detect_cipher_suite(cursor.rest()).ok_or(DecodeError::UnexpectedData)?;
+    children.push(
+        cursor
+            .variable(ciphertext_code(cipher_suite), NodeKind::Ciphertext)
+            .ok_or(DecodeError::UnexpectedData)?,
+    );
+
+    Ok(cursor.frame(envelope, children))
+}
+
+fn inspect_recipients(cursor: &mut Cursor) -> Result<Node, DecodeError> {
+    let (list, count) = cursor
+        .count(TSP_RECIPIENT_LIST, NodeKind::RecipientList)
+        .ok_or(DecodeError::UnexpectedData)?;
+
+    let mut children = Vec::with_capacity(2 * count as usize);
+    for _ in 0..count {
+        children.push(
+            cursor
+                .variable(TSP_DEVELOPMENT_VID, NodeKind::Recipient)
+                .ok_or(DecodeError::UnexpectedData)?,
+        );
+        children.push(
+            cursor
+                .variable(TSP_CIPHERTEXT, NodeKind::WrappedKey)
+                .ok_or(DecodeError::UnexpectedData)?,
+        );
+    }
+
+    Ok(cursor.frame(list, children))
+}
+
+fn inspect_hops(cursor: &mut Cursor) -> Result<Option<Node>, DecodeError> {
+    let Some((list, count)) = cursor.count(TSP_HOP_LIST, NodeKind::HopList) else {
+        return Ok(None);
+    };
+
+    let children = (0..count)
+        .map(|_| {
+            cursor
+                .variable(TSP_DEVELOPMENT_VID, NodeKind::Hop)
+                .ok_or(DecodeError::UnexpectedData)
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(cursor.frame(list, children)))
+}
+
+fn inspect_payload_at(payload: &[u8], base: usize, depth: usize) -> Result<Node, DecodeError> {
+    if depth > MAX_NESTING_DEPTH {
+        return Err(DecodeError::TooDeeplyNested);
+    }
+
+    let mut cursor = Cursor::new(payload, base);
+    let cursor = &mut cursor;
+
+    let Some((node, 1)) = cursor.count(TSP_PAYLOAD, NodeKind::Payload) else {
+        return Err(DecodeError::VersionMismatch);
+    };
+
+    let msgtype_node = cursor
+        .fixed::<2>(TSP_TYPECODE, NodeKind::MessageType)
+        .ok_or(DecodeError::UnexpectedData)?;
+    let msgtype: [u8; 2] = cursor.bytes(&msgtype_node).try_into().unwrap();
+
+    let mut children = vec![msgtype_node];
+
+    let fixed = |cursor: &mut Cursor, identifier, kind| {
+        match identifier {
+            TSP_TIMESTAMP | TSP_CHUNK_INDEX => cursor.fixed::<8>(identifier, kind),
+            _ => cursor.fixed::<32>(identifier, kind),
+        }
+        .ok_or(DecodeError::UnexpectedData)
+    };
+
+    let data = |cursor: &mut Cursor| {
+        cursor
+            .variable(TSP_PLAINTEXT, NodeKind::MessageData)
+            .ok_or(DecodeError::UnexpectedData)
+    };
+
+    // the data of nested and routed messages is itself a TSP message
+    let message_data = |cursor: &mut Cursor| -> Result<Node, DecodeError> {
+        let mut node = data(cursor)?;
+        let mut inner = Cursor::new(cursor.bytes(&node), node.data.start);
+        node.children
+            .extend(inspect_inner(inspect_envelope(&mut inner, depth + 1))?);
+
+        Ok(node)
+    };
+
+    match msgtype {
+        msgtype::GEN_MSG | msgtype::COMP_MSG => {
+            children.push(data(cursor)?);
+        }
+        msgtype::ACK_MSG => {
+            children.push(fixed(cursor, TSP_NONCE, NodeKind::Nonce)?);
+            children.push(data(cursor)?);
+        }
+        msgtype::FRESH_MSG => {
+            children.push(fixed(cursor, TSP_NONCE, NodeKind::MessageId)?);
+            children.push(fixed(cursor, TSP_TIMESTAMP, NodeKind::Timestamp)?);
+            children.push(data(cursor)?);
+        }
+        msgtype::THREAD_MSG => {
+            children.push(fixed(cursor, TSP_NONCE, NodeKind::Nonce)?);
+            children.push(fixed(cursor, TSP_NONCE, NodeKind::ThreadId)?);
+            children.extend(fixed(cursor, TSP_SHA256, NodeKind::ReplyTo).ok());
+            children.push(data(cursor)?);
+        }
+        msgtype::NEST_MSG => {
+            children.push(message_data(cursor)?);
+        }
+        msgtype::ROUTE_MSG => {
+            children.push(inspect_hops(cursor)?.ok_or(DecodeError::MissingHops)?);
+            children.push(message_data(cursor)?);
+        }
+        msgtype::STREAM_CHUNK | msgtype::STREAM_LAST => {
+            children.push(fixed(cursor, TSP_NONCE, NodeKind::StreamId)?);
+            children.push(fixed(cursor, TSP_CHUNK_INDEX, NodeKind::ChunkIndex)?);
+            children.push(data(cursor)?);
+        }
+        msgtype::RATCHET_MSG => {
+            children.push(fixed(cursor, TSP_CHUNK_INDEX, NodeKind::RatchetIndex)?);
+            children.push(data(cursor)?);
+        }
+        msgtype::NEW_REL => {
+            children.extend(inspect_hops(cursor)?);
+            children.push(fixed(cursor, TSP_NONCE, NodeKind::Nonce)?);
+            children.extend(fixed(cursor, HPKE_PUBLICKEY, NodeKind::EncryptionKey).ok());
+        }
+        msgtype::NEW_REL_REPLY => {
+            children.push(fixed(cursor, TSP_SHA256, NodeKind::Digest)?);
+            children.extend(cursor.variable(TSP_CIPHERTEXT, NodeKind::WrappedKey));
+        }
+        msgtype::RECEIPT => {
+            children.push(fixed(cursor, TSP_SHA256, NodeKind::Digest)?);
+        }
+        msgtype::NEW_NEST_REL => {
+            children.push(fixed(cursor, ED25519_PUBLICKEY, NodeKind::SigningKey)?);
+            children.push(fixed(cursor, HPKE_PUBLICKEY, NodeKind::EncryptionKey)?);
+        }
+        msgtype::NEW_NEST_REL_REPLY => {
+            children.push(fixed(cursor, TSP_SHA256, NodeKind::Digest)?);
+            children.push(fixed(cursor, ED25519_PUBLICKEY, NodeKind::SigningKey)?);
+            children.push(fixed(cursor, HPKE_PUBLICKEY, NodeKind::EncryptionKey)?);
+        }
+        msgtype::REL_REJECT | msgtype::REL_CANCEL => {
+            children.push(fixed(cursor, TSP_NONCE, NodeKind::Nonce)?);
+            children.push(fixed(cursor, TSP_SHA256, NodeKind::Digest)?);
+        }
+        _ => return Err(DecodeError::UnexpectedMsgType),
+    }
+
+    if !cursor.is_empty() {
+        return Err(DecodeError::TrailingGarbage);
+    }
+
+    Ok(cursor.frame(node, children))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::CipherSuite;
+
+    fn kinds(node: &Node) -> Vec<(NodeKind, usize)> {
+        let mut result = Vec::new();
+        node.walk(&mut |node, depth| result.push((node.kind, depth)));
+
+        result
+    }
+
+    #[test]
+    fn test_inspect_envelope() {
+        let mut outer = encode_ets_envelope_vec(Envelope {
+            sender: &b"Alister"[..],
+            receiver: Some(&b"Bobbi"[..]),
+            nonconfidential_data: Some(b"extra data"),
+        })
+        .unwrap();
+        encode_ciphertext(CipherSuite::default(), &[5; 40], &mut outer).unwrap();
+        encode_signature(&[1; 64], &mut outer);
+
+        let tree = inspect_message(&outer).unwrap();
+
+        assert_eq!(
+            kinds(&tree),
+            vec![
+                (NodeKind::EncryptedEnvelope, 0),
+                (NodeKind::Version, 1),
+                (NodeKind::Sender, 1),
+                (NodeKind::Receiver, 1),
+                (NodeKind::NonConfidentialData, 1),
+                (NodeKind::Ciphertext, 1),
+                (NodeKind::Signature, 1),
+            ]
+        );
+
+        assert_eq!(tree.code, "-EAB");
+        assert_eq!(tree.range, 0..outer.len());
+        assert_eq!(tree.children[0].code, "X");
+        assert_eq!(tree.children[0].range, 3..6);
+        assert_eq!(&outer[tree.children[1].data.clone()], b"Alister");
+        assert_eq!(&outer[tree.children[3].data.clone()], b"extra data");
+        assert_eq!(&outer[tree.children[4].data.clone()], &[5; 40]);
+        assert_eq!(tree.children[5].code, "0B");
+        assert_eq!(&outer[tree.children[5].data.clone()], &[1; 64]);
+
+        assert!(matches!(
+            inspect_message(&outer[..outer.len() - 1]),
+            Err(DecodeError::SignatureError)
+        ));
+    }
+
+    #[test]
+    fn test_inspect_routed_payload() {
+        let payload =
+            encode_payload_vec(Payload::<_, &[u8]>::GenericMessage(b"Hello Bobbi")).unwrap();
+        let mut inner = encode_s_envelope_vec(Envelope {
+            sender: &b"Alister"[..],
+            receiver: Some(&b"Bobbi"[..]),
+            nonconfidential_data: Some(&payload),
+        })
+        .unwrap();
+        encode_signature(&[1; 64], &mut inner);
+
+        let hops: Vec<&[u8]> = vec![b"foo", b"bar"];
+        let outer = encode_payload_vec(Payload::RoutedMessage(hops, &inner)).unwrap();
+
+        let tree = inspect_payload(&outer).unwrap();
+
+        assert_eq!(
+            kinds(&tree),
+            vec![
+                (NodeKind::Payload, 0),
+                (NodeKind::MessageType, 1),
+                (NodeKind::HopList, 1),
+                (NodeKind::Hop, 2),
+                (NodeKind::Hop, 2),
+                (NodeKind::MessageData, 1),
+                (NodeKind::SignedEnvelope, 2),
+                (NodeKind::Version, 3),
+                (NodeKind::Sender, 3),
+                (NodeKind::Receiver, 3),
+                (NodeKind::NonConfidentialData, 3),
+                (NodeKind::Payload, 4),
+                (NodeKind::MessageType, 5),
+                (NodeKind::MessageData, 5),
+                (NodeKind::Signature, 3),
+            ]
+        );
+
+        let hop_list = &tree.children[1];
+        assert_eq!(hop_list.range.end, hop_list.children[1].range.end);
+        assert_eq!(&outer[hop_list.children[1].data.clone()], b"bar");
+
+        let message_data = &tree.children[2].children[0].children[3].children[0].children[1];
+        assert_eq!(&outer[message_data.data.clone()], b"Hello Bobbi");
+    }
+
+    #[test]
+    fn test_inspect_deeply_nested_payload() {
+        let nest = |depth: usize| {
+            let mut payload =
+                encode_payload_vec(Payload::<_, &[u8]>::GenericMessage(b"Hello Bobbi")).unwrap();
+
+            for _ in 0..depth {
+                let mut inner = encode_s_envelope_vec(Envelope {
+                    sender: &b"Alister"[..],
+                    receiver: Some(&b"Bobbi"[..]),
+                    nonconfidential_data: Some(&payload),
+                })
+                .unwrap();
+                encode_signature(&[1; 64], &mut inner);
+
+                payload =
+                    encode_payload_vec(Payload::<_, &[u8]>::NestedMessage(&inner[..])).unwrap();
+            }
+
+            payload
+        };
+
+        let tree = inspect_payload(&nest(MAX_NESTING_DEPTH)).unwrap();
+        let mut max_depth = 0;
+        tree.walk(&mut |_, depth| max_depth = max_depth.max(depth));
+        assert_eq!(max_depth, 4 * MAX_NESTING_DEPTH + 1);
+
+        assert!(matches!(
+            inspect_payload(&nest(MAX_NESTING_DEPTH + 1)),
+            Err(DecodeError::TooDeeplyNested)
+        ));
+        assert!(matches!(
+            inspect_payload(&nest(100)),
+            Err(DecodeError::TooDeeplyNested)
+        ));
+    }
+}
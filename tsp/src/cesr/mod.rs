@@ -3,11 +3,16 @@ mod decode;
 mod detect;
 mod encode;
 pub mod error;
+mod inspect;
 mod packet;
+pub use inspect::{inspect_message, inspect_payload, Node, NodeKind};
 pub use packet::*;
 
 #[cfg(feature = "cesr-t")]
-pub use detect::to_binary;
+pub use detect::{text_message_length, to_binary, to_text};
+
+#[cfg(test)]
+pub(crate) use packet::test::{payload_parts, PayloadParts};
 
 /// Safely restrict value to a certain number of bits
 fn bits(value: impl Into<u32>, bits: u8) -> u32 {
@@ -72,9 +77,24 @@ pub enum EnvelopeType<'a> {
         sender: &'a [u8],
         receiver: Option<&'a [u8]>,
     },
+    MulticastMessage {
+        sender: &'a [u8],
+    },
+    /// An encrypted message of which the sender is only known after decryption
+    HiddenSenderMessage {
+        receiver: &'a [u8],
+    },
 }
 
 pub fn probe(stream: &mut [u8]) -> Result<EnvelopeType, error::DecodeError> {
+    if is_hidden_sender_envelope(stream) {
+        let envelope = decode_hidden_sender_envelope(stream)?;
+
+        return Ok(EnvelopeType::HiddenSenderMessage {
+            receiver: &stream[envelope.receiver],
+        });
+    }
+
     let (_, has_confidential_part) =
         detected_tsp_header_size_and_confidentiality(&mut (stream as &[u8]))?;
 
@@ -83,19 +103,40 @@ pub fn probe(stream: &mut [u8]) -> Result<EnvelopeType, error::DecodeError> {
         .expect("Infallible")
         .envelope;
 
-    Ok(if has_confidential_part {
-        EnvelopeType::EncryptedMessage {
+    Ok(match (has_confidential_part, envelope.receiver) {
+        (true, Some(receiver)) => EnvelopeType::EncryptedMessage {
             sender: envelope.sender,
-            receiver: envelope.receiver.expect("Infallible"),
-        }
-    } else {
-        EnvelopeType::SignedMessage {
+            receiver,
+        },
+        (true, None) => EnvelopeType::MulticastMessage {
             sender: envelope.sender,
-            receiver: envelope.receiver,
-        }
+        },
+        (false, receiver) => EnvelopeType::SignedMessage {
+            sender: envelope.sender,
+            receiver,
+        },
     })
 }
 
+/// Get the VIDs of the recipients of a CESR-encoded multicast message
+pub fn get_multicast_recipients(message: &[u8]) -> Result<Vec<&[u8]>, error::DecodeError> {
+    let (
+        DecodedEnvelope {
+            ciphertext: Some(mut ciphertext),
+            ..
+        },
+        _,
+    ) = decode_envelope::<&[u8]>(message)?
+    else {
+        return Err(error::DecodeError::UnexpectedData);
+    };
+
+    Ok(decode_recipients(&mut ciphertext)?
+        .into_iter()
+        .map(|(vid, _)| vid)
+        .collect())
+}
+
 #[cfg(test)]
 mod test {
     use super::{decode::*, encode::*, *};
@@ -117,6 +158,24 @@ mod test {
         assert!(header_match(&[0xFF], &[0xFC]));
     }
 
+    #[test]
+    fn encoded_sizes() {
+        for len in [0, 1, 2, 3, 31, 32, 64] {
+            let mut data = vec![];
+            encode_fixed_data(42, &vec![0; len], &mut data);
+            assert_eq!(data.len(), fixed_data_size(len));
+        }
+
+        for (identifier, len) in [(3, 0), (3, 1), (3, 2), (3, 12284), (3, 12285), (3, 12286)]
+            .into_iter()
+            .chain([(122, 0), (122, 1), (122, 2), (122, 100)])
+        {
+            let mut data = vec![];
+            encode_variable_data(identifier, &vec![0; len], &mut data);
+            assert_eq!(data.len(), variable_data_size(identifier, len));
+        }
+    }
+
     #[test]
     fn encode_and_decode() {
         let mut data = vec![];
@@ -1,38 +1,65 @@
 /// Constants that determine the specific CESR types for "variable length data"
-const TSP_PLAINTEXT: u32 = (b'B' - b'A') as u32;
-const TSP_CIPHERTEXT: u32 = (b'C' - b'A') as u32;
-const TSP_DEVELOPMENT_VID: u32 = (21 << 6 | 8) << 6 | 3; // "VID"
+pub(super) const TSP_PLAINTEXT: u32 = (b'B' - b'A') as u32;
+pub(super) const TSP_CIPHERTEXT: u32 = (b'C' - b'A') as u32;
+pub(super) const TSP_CIPHERTEXT_X25519_AES128GCM: u32 = (b'D' - b'A') as u32;
+pub(super) const TSP_CIPHERTEXT_P256_AES128GCM: u32 = (b'E' - b'A') as u32;
+#[cfg(feature = "pq")]
+pub(super) const TSP_CIPHERTEXT_X25519MLKEM768: u32 = (b'F' - b'A') as u32;
+pub(super) const TSP_DEVELOPMENT_VID: u32 = (21 << 6 | 8) << 6 | 3; // "VID"
 
 /// Constants that determine the specific CESR types for "fixed length data"
-const TSP_TYPECODE: u32 = (b'X' - b'A') as u32;
-const ED25519_SIGNATURE: u32 = (b'B' - b'A') as u32;
+pub(super) const TSP_TYPECODE: u32 = (b'X' - b'A') as u32;
+pub(super) const ED25519_SIGNATURE: u32 = (b'B' - b'A') as u32;
 #[allow(clippy::eq_op)]
-const TSP_NONCE: u32 = (b'A' - b'A') as u32;
-const TSP_SHA256: u32 = (b'I' - b'A') as u32;
-const ED25519_PUBLICKEY: u32 = (b'D' - b'A') as u32;
-const HPKE_PUBLICKEY: u32 = (b'Q' - b'A') as u32;
+pub(super) const TSP_NONCE: u32 = (b'A' - b'A') as u32;
+pub(super) const TSP_SHA256: u32 = (b'I' - b'A') as u32;
+pub(super) const ED25519_PUBLICKEY: u32 = (b'D' - b'A') as u32;
+pub(super) const HPKE_PUBLICKEY: u32 = (b'Q' - b'A') as u32;
+pub(super) const TSP_CHUNK_INDEX: u32 = (b'N' - b'A') as u32;
+pub(super) const TSP_TIMESTAMP: u32 = (b'T' - b'A') as u32;
 
 /// Constants that determine the specific CESR types for the framing codes
-const TSP_ETS_WRAPPER: u16 = (b'E' - b'A') as u16;
-const TSP_S_WRAPPER: u16 = (b'S' - b'A') as u16;
-const TSP_HOP_LIST: u16 = (b'I' - b'A') as u16;
-const TSP_PAYLOAD: u16 = (b'Z' - b'A') as u16;
+pub(super) const TSP_ETS_WRAPPER: u16 = (b'E' - b'A') as u16;
+pub(super) const TSP_S_WRAPPER: u16 = (b'S' - b'A') as u16;
+pub(super) const TSP_HIDDEN_SENDER_WRAPPER: u16 = (b'H' - b'A') as u16;
+pub(super) const TSP_HOP_LIST: u16 = (b'I' - b'A') as u16;
+pub(super) const TSP_PAYLOAD: u16 = (b'Z' - b'A') as u16;
+pub(super) const TSP_RECIPIENT_LIST: u16 = (b'R' - b'A') as u16;
 
 /// Constants to encode message types
-mod msgtype {
-    pub(super) const GEN_MSG: [u8; 2] = [0, 0];
-    pub(super) const NEST_MSG: [u8; 2] = [0, 1];
-    pub(super) const ROUTE_MSG: [u8; 2] = [0, 2];
-    pub(super) const NEW_REL: [u8; 2] = [1, 0];
-    pub(super) const NEW_REL_REPLY: [u8; 2] = [1, 1];
-    pub(super) const NEW_NEST_REL: [u8; 2] = [1, 2];
-    pub(super) const NEW_NEST_REL_REPLY: [u8; 2] = [1, 3];
-    pub(super) const REL_CANCEL: [u8; 2] = [1, 255];
+pub(super) mod msgtype {
+    pub(in crate::cesr) const GEN_MSG: [u8; 2] = [0, 0];
+    pub(in crate::cesr) const NEST_MSG: [u8; 2] = [0, 1];
+    pub(in crate::cesr) const ROUTE_MSG: [u8; 2] = [0, 2];
+    pub(in crate::cesr) const STREAM_CHUNK: [u8; 2] = [0, 3];
+    pub(in crate::cesr) const STREAM_LAST: [u8; 2] = [0, 4];
+    pub(in crate::cesr) const COMP_MSG: [u8; 2] = [0, 5];
+    pub(in crate::cesr) const FRESH_MSG: [u8; 2] = [0, 6];
+    pub(in crate::cesr) const ACK_MSG: [u8; 2] = [0, 7];
+    pub(in crate::cesr) const THREAD_MSG: [u8; 2] = [0, 8];
+    pub(in crate::cesr) const RATCHET_MSG: [u8; 2] = [0, 9];
+    pub(in crate::cesr) const NEW_REL: [u8; 2] = [1, 0];
+    pub(in crate::cesr) const NEW_REL_REPLY: [u8; 2] = [1, 1];
+    pub(in crate::cesr) const NEW_NEST_REL: [u8; 2] = [1, 2];
+    pub(in crate::cesr) const NEW_NEST_REL_REPLY: [u8; 2] = [1, 3];
+    pub(in crate::cesr) const RECEIPT: [u8; 2] = [1, 4];
+    pub(in crate::cesr) const REL_REJECT: [u8; 2] = [1, 254];
+    pub(in crate::cesr) const REL_CANCEL: [u8; 2] = [1, 255];
 }
 
+use std::ops::Range;
+
+use crate::definitions::CipherSuite;
+
 use super::{
-    decode::{decode_count, decode_fixed_data, decode_variable_data, decode_variable_data_index},
-    encode::{encode_count, encode_fixed_data},
+    decode::{
+        decode_count, decode_fixed_data, decode_variable_data, decode_variable_data_header,
+        decode_variable_data_index,
+    },
+    encode::{
+        encode_count, encode_fixed_data, encode_variable_data_header, fixed_data_size,
+        variable_data_size,
+    },
     error::{DecodeError, EncodeError},
 };
 
@@ -74,14 +101,53 @@ pub struct PairedKeys<'a> {
 pub enum Payload<'a, Bytes: AsRef<[u8]>, Vid> {
     /// A TSP message which consists only of a message which will be protected using HPKE
     GenericMessage(Bytes),
+    /// A TSP message whose message data has been compressed using DEFLATE
+    CompressedMessage(Bytes),
+    /// A TSP message with a unique identifier and the time (in seconds since the UNIX epoch)
+    /// it was sent, to allow the receiver to detect replayed or stale messages
+    FreshMessage {
+        message_id: &'a Sha256Digest,
+        timestamp: u64,
+        data: Bytes,
+    },
+    /// A TSP message for which the sender requests a receipt; the nonce makes the digest
+    /// that the receipt refers to unique, even if the same message is sent twice
+    AcknowledgedMessage { nonce: Nonce, data: Bytes },
+    /// A TSP message that is part of a conversation, optionally replying to an earlier message;
+    /// the nonce makes the digest that a reply refers to unique
+    ThreadedMessage {
+        nonce: Nonce,
+        thread_id: &'a Sha256Digest,
+        reply_to: Option<&'a Sha256Digest>,
+        data: Bytes,
+    },
     /// A payload that consists of a TSP Envelope+Message (TODO: maybe add some extra decoding)
     NestedMessage(Bytes),
     /// A routed payload; same as above but with routing information attached
     RoutedMessage(Vec<Vid>, Bytes),
-    /// A TSP message requesting a relationship
-    DirectRelationProposal { nonce: Nonce, hops: Vec<Vid> },
-    /// A TSP message confiming a relationship
-    DirectRelationAffirm { reply: &'a Sha256Digest },
+    /// A part of a larger payload that is sent as a sequence of TSP messages
+    StreamChunk {
+        stream_id: &'a Sha256Digest,
+        index: u64,
+        last: bool,
+        data: Bytes,
+    },
+    /// A TSP message whose message data is encrypted once more, with the key number `index`
+    /// of the ratchet of a relationship
+    RatchetedMessage { index: u64, data: Bytes },
+    /// A TSP message requesting a relationship, optionally proposing a ratchet by
+    /// including an ephemeral public key
+    DirectRelationProposal {
+        nonce: Nonce,
+        hops: Vec<Vid>,
+        ratchet_key: Option<&'a [u8; 32]>,
+    },
+    /// A TSP message confiming a relationship, optionally with the initial secret of
+    /// the proposed ratchet, encrypted for its ephemeral public key
+    DirectRelationAffirm {
+        reply: &'a Sha256Digest,
+        ratchet_secret: Option<&'a [u8]>,
+    },
     /// A TSP message requesting a nested relationship
     NestedRelationProposal { public_keys: PairedKeys<'a> },
     /// A TSP message confiming a relationship
@@ -89,6 +155,13 @@ pub enum Payload<'a, Bytes: AsRef<[u8]>, Vid> {
         reply: &'a Sha256Digest,
         public_keys: PairedKeys<'a>,
     },
+    /// A TSP message confirming the receipt of a message
+    Receipt { reply: &'a Sha256Digest },
+    /// A TSP message declining a relationship request
+    RelationshipReject {
+        nonce: Nonce,
+        reply: &'a Sha256Digest,
+    },
     /// A TSP cancellation message
     RelationshipCancel {
         nonce: Nonce,
@@ -96,12 +169,93 @@ pub enum Payload<'a, Bytes: AsRef<[u8]>, Vid> {
     },
 }
 
-impl<'a, Bytes: AsRef<[u8]>, Vid> Payload<'a, Bytes, Vid> {
+impl<'a, Bytes: AsRef<[u8]>, Vid: AsRef<[u8]>> Payload<'a, Bytes, Vid> {
+    /// The size of this payload once it is encoded using [encode_payload]
     pub fn estimate_size(&self) -> usize {
-        0 // TODO
+        let digest = fixed_data_size(32);
+        let data = |data: &Bytes| variable_data_size(TSP_PLAINTEXT, data.as_ref().len());
+
+        let fields = match self {
+            Payload::GenericMessage(bytes)
+            | Payload::CompressedMessage(bytes)
+            | Payload::NestedMessage(bytes) => data(bytes),
+            Payload::AcknowledgedMessage { data: bytes, .. } => digest + data(bytes),
+            Payload::FreshMessage { data: bytes, .. }
+            | Payload::StreamChunk { data: bytes, .. } => digest + fixed_data_size(8) + data(bytes),
+            Payload::RatchetedMessage { data: bytes, .. } => fixed_data_size(8) + data(bytes),
+            Payload::ThreadedMessage {
+                reply_to,
+                data: bytes,
+                ..
+            } => 2 * digest + reply_to.map_or(0, |_| digest) + data(bytes),
+            Payload::RoutedMessage(hops, bytes) => hops_size(hops) + data(bytes),
+            Payload::DirectRelationProposal {
+                hops, ratchet_key, ..
+            } => hops_size(hops) + digest + ratchet_key.map_or(0, |_| digest),
+            Payload::DirectRelationAffirm { ratchet_secret, .. } => {
+                digest
+                    + ratchet_secret
+                        .map_or(0, |secret| variable_data_size(TSP_CIPHERTEXT, secret.len()))
+            }
+            Payload::Receipt { .. } => digest,
+            Payload::NestedRelationProposal { .. }
+            | Payload::RelationshipReject { .. }
+            | Payload::RelationshipCancel { .. } => 2 * digest,
+            Payload::NestedRelationAffirm { .. } => 3 * digest,
+        };
+
+        COUNT_SIZE + fixed_data_size(2) + fields
     }
 }
 
+/// The size of an encoded count code
+const COUNT_SIZE: usize = 3;
+
+/// The size of an encoded Ed25519 signature
+pub const SIGNATURE_SIZE: usize = fixed_data_size(64);
+
+/// The size of the encoded hops list, see [encode_hops]
+fn hops_size(hops: &[impl AsRef<[u8]>]) -> usize {
+    if hops.is_empty() {
+        0
+    } else {
+        COUNT_SIZE
+            + hops
+                .iter()
+                .map(|hop| variable_data_size(TSP_DEVELOPMENT_VID, hop.as_ref().len()))
+                .sum::<usize>()
+    }
+}
+
+/// The size of an encoded ciphertext of `len` bytes, see [encode_ciphertext]
+pub const fn ciphertext_size(len: usize) -> usize {
+    variable_data_size(TSP_CIPHERTEXT, len)
+}
+
+/// The CESR code of a ciphertext identifies the cipher suite that was used to encrypt it
+pub(super) const fn ciphertext_code(cipher_suite: CipherSuite) -> u32 {
+    match cipher_suite {
+        CipherSuite::X25519ChaCha20Poly1305 => TSP_CIPHERTEXT,
+        CipherSuite::X25519Aes128Gcm => TSP_CIPHERTEXT_X25519_AES128GCM,
+        CipherSuite::P256Aes128Gcm => TSP_CIPHERTEXT_P256_AES128GCM,
+        // the ciphertext of a hybrid KEM ends with a larger encapsulated key
+        #[cfg(feature = "pq")]
+        CipherSuite::X25519MlKem768ChaCha20Poly1305 => TSP_CIPHERTEXT_X25519MLKEM768,
+    }
+}
+
+/// Determine the cipher suite of the ciphertext at the start of `stream`, if there is one
+pub(super) fn detect_cipher_suite(stream: &[u8]) -> Option<CipherSuite> {
+    let Ok(Some((code, _))) = decode_variable_data_header(stream) else {
+        return None;
+    };
+
+    CipherSuite::ALL
+        .iter()
+        .copied()
+        .find(|suite| ciphertext_code(*suite) == code)
+}
+
 /// Type representing a TSP Envelope
 #[derive(Debug, Clone)]
 pub struct Envelope<'a, Vid> {
@@ -110,23 +264,42 @@ pub struct Envelope<'a, Vid> {
     pub nonconfidential_data: Option<&'a [u8]>,
 }
 
+impl<'a, Vid: AsRef<[u8]>> Envelope<'a, Vid> {
+    /// The size of this envelope once it is encoded using [encode_ets_envelope] or
+    /// [encode_s_envelope], i.e. without ciphertext and signature
+    pub fn estimate_size(&self) -> usize {
+        let vid = |vid: &Vid| variable_data_size(TSP_DEVELOPMENT_VID, vid.as_ref().len());
+
+        COUNT_SIZE
+            + fixed_data_size(2)
+            + vid(&self.sender)
+            + self.receiver.as_ref().map_or(0, vid)
+            + self
+                .nonconfidential_data
+                .map_or(0, |data| variable_data_size(TSP_PLAINTEXT, data.len()))
+    }
+}
+
 pub struct DecodedEnvelope<'a, Vid, Bytes> {
     pub envelope: Envelope<'a, Vid>,
     pub raw_header: &'a [u8], // for associated data purposes
     pub ciphertext: Option<Bytes>,
+    /// The payload of a signed message that also has non-confidential data, see [encode_signed_payload]
+    pub plaintext: Option<&'a [u8]>,
 }
 
 /// TODO: something more type safe
 pub type Signature = [u8; 64];
 
+/// The maximum size of variable data
+pub(crate) const DATA_LIMIT: usize = 50000000;
+
 /// Safely encode variable data, returning a soft error in case the size limit is exceeded
 fn checked_encode_variable_data(
     identifier: u32,
     payload: &[u8],
     stream: &mut impl for<'a> Extend<&'a u8>,
 ) -> Result<(), EncodeError> {
-    const DATA_LIMIT: usize = 50000000;
-
     if payload.len() >= DATA_LIMIT {
         return Err(EncodeError::PayloadTooLarge);
     }
@@ -147,6 +320,39 @@ pub fn encode_payload(
             encode_fixed_data(TSP_TYPECODE, &msgtype::GEN_MSG, output);
             checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
         }
+        Payload::CompressedMessage(data) => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::COMP_MSG, output);
+            checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+        }
+        Payload::FreshMessage {
+            message_id,
+            timestamp,
+            data,
+        } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::FRESH_MSG, output);
+            encode_fixed_data(TSP_NONCE, message_id, output);
+            encode_fixed_data(TSP_TIMESTAMP, &timestamp.to_be_bytes(), output);
+            checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+        }
+        Payload::AcknowledgedMessage { nonce, data } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::ACK_MSG, output);
+            encode_fixed_data(TSP_NONCE, &nonce.0, output);
+            checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+        }
+        Payload::ThreadedMessage {
+            nonce,
+            thread_id,
+            reply_to,
+            data,
+        } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::THREAD_MSG, output);
+            encode_fixed_data(TSP_NONCE, &nonce.0, output);
+            encode_fixed_data(TSP_NONCE, thread_id, output);
+            if let Some(reply_to) = reply_to {
+                encode_fixed_data(TSP_SHA256, reply_to, output);
+            }
+            checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+        }
         Payload::NestedMessage(data) => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::NEST_MSG, output);
             checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
@@ -156,14 +362,48 @@ pub fn encode_payload(
             encode_hops(hops, output)?;
             checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
         }
-        Payload::DirectRelationProposal { nonce, hops } => {
+        Payload::StreamChunk {
+            stream_id,
+            index,
+            last,
+            data,
+        } => {
+            let msgtype = if last {
+                msgtype::STREAM_LAST
+            } else {
+                msgtype::STREAM_CHUNK
+            };
+            encode_fixed_data(TSP_TYPECODE, &msgtype, output);
+            encode_fixed_data(TSP_NONCE, stream_id, output);
+            encode_fixed_data(TSP_CHUNK_INDEX, &index.to_be_bytes(), output);
+            checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+        }
+        Payload::RatchetedMessage { index, data } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::RATCHET_MSG, output);
+            encode_fixed_data(TSP_CHUNK_INDEX, &index.to_be_bytes(), output);
+            checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+        }
+        Payload::DirectRelationProposal {
+            nonce,
+            hops,
+            ratchet_key,
+        } => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::NEW_REL, output);
             encode_hops(hops, output)?;
             encode_fixed_data(TSP_NONCE, &nonce.0, output);
+            if let Some(ratchet_key) = ratchet_key {
+                encode_fixed_data(HPKE_PUBLICKEY, ratchet_key, output);
+            }
         }
-        Payload::DirectRelationAffirm { reply } => {
+        Payload::DirectRelationAffirm {
+            reply,
+            ratchet_secret,
+        } => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::NEW_REL_REPLY, output);
             encode_fixed_data(TSP_SHA256, reply, output);
+            if let Some(ratchet_secret) = ratchet_secret {
+                checked_encode_variable_data(TSP_CIPHERTEXT, ratchet_secret, output)?;
+            }
         }
         Payload::NestedRelationProposal { public_keys } => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::NEW_NEST_REL, output);
@@ -176,6 +416,15 @@ pub fn encode_payload(
             encode_fixed_data(ED25519_PUBLICKEY, public_keys.signing, output);
             encode_fixed_data(HPKE_PUBLICKEY, public_keys.encrypting, output);
         }
+        Payload::Receipt { reply } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::RECEIPT, output);
+            encode_fixed_data(TSP_SHA256, reply, output);
+        }
+        Payload::RelationshipReject { nonce, reply } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::REL_REJECT, output);
+            encode_fixed_data(TSP_NONCE, &nonce.0, output);
+            encode_fixed_data(TSP_SHA256, reply, output);
+        }
         Payload::RelationshipCancel { nonce, reply } => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::REL_CANCEL, output);
             encode_fixed_data(TSP_NONCE, &nonce.0, output);
@@ -220,10 +469,96 @@ fn decode_hops<'a, Vid: TryFrom<&'a [u8]>>(stream: &mut &'a [u8]) -> Result<Vec<
     Ok(hop_list)
 }
 
+/// Encode the recipient list of a multicast message; each recipient VID is followed by
+/// the content key that was encrypted for that recipient
+pub fn encode_recipients(
+    recipients: &[(impl AsRef<[u8]>, impl AsRef<[u8]>)],
+    output: &mut impl for<'a> Extend<&'a u8>,
+) -> Result<(), EncodeError> {
+    // the count code can represent at most 12 bits
+    if recipients.is_empty() || recipients.len() >= 1 << 12 {
+        return Err(EncodeError::PayloadTooLarge);
+    }
+
+    encode_count(TSP_RECIPIENT_LIST, recipients.len() as u16, output);
+    for (vid, wrapped_key) in recipients {
+        checked_encode_variable_data(TSP_DEVELOPMENT_VID, vid.as_ref(), output)?;
+        checked_encode_variable_data(TSP_CIPHERTEXT, wrapped_key.as_ref(), output)?;
+    }
+
+    Ok(())
+}
+
+/// A recipient of a multicast message: its VID and the content key encrypted for it
+pub type Recipient<'a> = (&'a [u8], &'a [u8]);
+
+/// Decode the recipient list of a multicast message
+pub fn decode_recipients<'a>(stream: &mut &'a [u8]) -> Result<Vec<Recipient<'a>>, DecodeError> {
+    let Some(count) = decode_count(TSP_RECIPIENT_LIST, stream) else {
+        return Err(DecodeError::UnexpectedData);
+    };
+
+    let mut recipients = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let vid =
+            decode_variable_data(TSP_DEVELOPMENT_VID, stream).ok_or(DecodeError::UnexpectedData)?;
+        let wrapped_key =
+            decode_variable_data(TSP_CIPHERTEXT, stream).ok_or(DecodeError::UnexpectedData)?;
+
+        recipients.push((vid, wrapped_key));
+    }
+
+    Ok(recipients)
+}
+
 /// Decode a TSP Payload
 pub fn decode_payload<'a, Vid: TryFrom<&'a [u8]>>(
-    mut stream: &'a [u8],
+    stream: &'a [u8],
 ) -> Result<Payload<&'a [u8], Vid>, DecodeError> {
+    decode_payload_with(stream, |stream| decode_variable_data(TSP_PLAINTEXT, stream))
+}
+
+/// A decoded TSP Payload with mutable message data, and the raw bytes preceding that data
+pub type PayloadMut<'a, Vid> = (Payload<'a, &'a mut [u8], Vid>, &'a [u8]);
+
+/// Decode a TSP Payload, giving mutable access to its message data (if it has any),
+/// so that e.g. a nested TSP message can be opened in place.
+/// Also returns the raw bytes of the payload that precede the message data.
+pub fn decode_payload_mut<'a, Vid: TryFrom<&'a [u8]>>(
+    stream: &'a mut [u8],
+) -> Result<PayloadMut<'a, Vid>, DecodeError> {
+    // the message data is always the final element of a payload
+    let data_len = match decode_payload::<&[u8]>(stream)? {
+        Payload::GenericMessage(data)
+        | Payload::CompressedMessage(data)
+        | Payload::FreshMessage { data, .. }
+        | Payload::AcknowledgedMessage { data, .. }
+        | Payload::ThreadedMessage { data, .. }
+        | Payload::NestedMessage(data)
+        | Payload::RoutedMessage(_, data)
+        | Payload::StreamChunk { data, .. }
+        | Payload::RatchetedMessage { data, .. } => data.len(),
+        _ => 0,
+    };
+
+    let (header, data) = stream.split_at_mut(stream.len() - data_len);
+    let header: &'a [u8] = header;
+    let mut data = Some(data);
+
+    let payload = decode_payload_with(header, |stream| {
+        // the remainder of the header is the (already checked) prefix of the message data
+        *stream = &[];
+        data.take()
+    })?;
+
+    Ok((payload, header))
+}
+
+/// Decode a TSP Payload, using `decode_data` to extract the message data (if present)
+fn decode_payload_with<'a, Bytes: AsRef<[u8]>, Vid: TryFrom<&'a [u8]>>(
+    mut stream: &'a [u8],
+    mut decode_data: impl FnMut(&mut &'a [u8]) -> Option<Bytes>,
+) -> Result<Payload<'a, Bytes, Vid>, DecodeError> {
     let Some(1) = decode_count(TSP_PAYLOAD, &mut stream) else {
         return Err(DecodeError::VersionMismatch);
     };
@@ -231,31 +566,77 @@ pub fn decode_payload<'a, Vid: TryFrom<&'a [u8]>>(
     let payload = match *decode_fixed_data(TSP_TYPECODE, &mut stream)
         .ok_or(DecodeError::UnexpectedData)?
     {
-        msgtype::GEN_MSG => {
-            decode_variable_data(TSP_PLAINTEXT, &mut stream).map(Payload::GenericMessage)
-        }
+        msgtype::GEN_MSG => decode_data(&mut stream).map(Payload::GenericMessage),
         msgtype::NEW_REL => {
             let hop_list = decode_hops(&mut stream)?;
 
             decode_fixed_data(TSP_NONCE, &mut stream).map(|nonce| Payload::DirectRelationProposal {
                 nonce: Nonce(*nonce),
                 hops: hop_list,
+                ratchet_key: decode_fixed_data(HPKE_PUBLICKEY, &mut stream),
             })
         }
-        msgtype::NEST_MSG => {
-            decode_variable_data(TSP_PLAINTEXT, &mut stream).map(Payload::NestedMessage)
-        }
+        msgtype::COMP_MSG => decode_data(&mut stream).map(Payload::CompressedMessage),
+        msgtype::FRESH_MSG => decode_fixed_data(TSP_NONCE, &mut stream).and_then(|message_id| {
+            decode_fixed_data(TSP_TIMESTAMP, &mut stream).and_then(|timestamp| {
+                decode_data(&mut stream).map(|data| Payload::FreshMessage {
+                    message_id,
+                    timestamp: u64::from_be_bytes(*timestamp),
+                    data,
+                })
+            })
+        }),
+        msgtype::ACK_MSG => decode_fixed_data(TSP_NONCE, &mut stream).and_then(|nonce| {
+            decode_data(&mut stream).map(|data| Payload::AcknowledgedMessage {
+                nonce: Nonce(*nonce),
+                data,
+            })
+        }),
+        msgtype::THREAD_MSG => decode_fixed_data(TSP_NONCE, &mut stream).and_then(|nonce| {
+            decode_fixed_data(TSP_NONCE, &mut stream).and_then(|thread_id| {
+                let reply_to = decode_fixed_data(TSP_SHA256, &mut stream);
+
+                decode_data(&mut stream).map(|data| Payload::ThreadedMessage {
+                    nonce: Nonce(*nonce),
+                    thread_id,
+                    reply_to,
+                    data,
+                })
+            })
+        }),
+        msgtype::NEST_MSG => decode_data(&mut stream).map(Payload::NestedMessage),
         msgtype::ROUTE_MSG => {
             let hop_list = decode_hops(&mut stream)?;
             if hop_list.is_empty() {
                 return Err(DecodeError::MissingHops);
             }
 
-            decode_variable_data(TSP_PLAINTEXT, &mut stream)
-                .map(|msg| Payload::RoutedMessage(hop_list, msg))
+            decode_data(&mut stream).map(|msg| Payload::RoutedMessage(hop_list, msg))
+        }
+        msgtype @ (msgtype::STREAM_CHUNK | msgtype::STREAM_LAST) => {
+            decode_fixed_data(TSP_NONCE, &mut stream).and_then(|stream_id| {
+                decode_fixed_data(TSP_CHUNK_INDEX, &mut stream).and_then(|index| {
+                    decode_data(&mut stream).map(|data| Payload::StreamChunk {
+                        stream_id,
+                        index: u64::from_be_bytes(*index),
+                        last: msgtype == msgtype::STREAM_LAST,
+                        data,
+                    })
+                })
+            })
+        }
+        msgtype::RATCHET_MSG => decode_fixed_data(TSP_CHUNK_INDEX, &mut stream).and_then(|index| {
+            decode_data(&mut stream).map(|data| Payload::RatchetedMessage {
+                index: u64::from_be_bytes(*index),
+                data,
+            })
+        }),
+        msgtype::NEW_REL_REPLY => {
+            decode_fixed_data(TSP_SHA256, &mut stream).map(|reply| Payload::DirectRelationAffirm {
+                reply,
+                ratchet_secret: decode_variable_data(TSP_CIPHERTEXT, &mut stream),
+            })
         }
-        msgtype::NEW_REL_REPLY => decode_fixed_data(TSP_SHA256, &mut stream)
-            .map(|reply| Payload::DirectRelationAffirm { reply }),
         msgtype::NEW_NEST_REL => {
             decode_fixed_data(ED25519_PUBLICKEY, &mut stream).and_then(|signing| {
                 decode_fixed_data(HPKE_PUBLICKEY, &mut stream).map(|encrypting| {
@@ -280,6 +661,15 @@ pub fn decode_payload<'a, Vid: TryFrom<&'a [u8]>>(
                 })
             })
         }
+        msgtype::RECEIPT => {
+            decode_fixed_data(TSP_SHA256, &mut stream).map(|reply| Payload::Receipt { reply })
+        }
+        msgtype::REL_REJECT => decode_fixed_data(TSP_NONCE, &mut stream).and_then(|nonce| {
+            decode_fixed_data(TSP_SHA256, &mut stream).map(|reply| Payload::RelationshipReject {
+                nonce: Nonce(*nonce),
+                reply,
+            })
+        }),
         msgtype::REL_CANCEL => decode_fixed_data(TSP_NONCE, &mut stream).and_then(|nonce| {
             decode_fixed_data(TSP_SHA256, &mut stream).map(|reply| Payload::RelationshipCancel {
                 nonce: Nonce(*nonce),
@@ -339,18 +729,103 @@ fn encode_envelope_fields<'a, Vid: AsRef<[u8]>>(
     Ok(())
 }
 
+/// Encode the envelope of a message with a hidden sender ("sealed sender") into CESR;
+/// it only contains the receiver and non-confidential data, the sender is encoded
+/// together with the payload, using [encode_hidden_sender], and encrypted
+pub fn encode_hidden_sender_envelope<Vid: AsRef<[u8]>>(
+    receiver: Vid,
+    nonconfidential_data: Option<&[u8]>,
+    output: &mut impl for<'b> Extend<&'b u8>,
+) -> Result<(), EncodeError> {
+    encode_count(TSP_HIDDEN_SENDER_WRAPPER, 1, output);
+    encode_fixed_data(TSP_TYPECODE, &[0, 0], output);
+    checked_encode_variable_data(TSP_DEVELOPMENT_VID, receiver.as_ref(), output)?;
+
+    if let Some(data) = nonconfidential_data {
+        checked_encode_variable_data(TSP_PLAINTEXT, data, output)?;
+    }
+
+    Ok(())
+}
+
+/// The size of an encoded hidden sender, see [encode_hidden_sender]
+pub fn hidden_sender_size(sender: impl AsRef<[u8]>) -> usize {
+    variable_data_size(TSP_DEVELOPMENT_VID, sender.as_ref().len())
+}
+
+/// Encode the sender of a message with a hidden sender; in the plaintext it is
+/// followed by the payload and the signature of the sender
+pub fn encode_hidden_sender(
+    sender: impl AsRef<[u8]>,
+    output: &mut impl for<'b> Extend<&'b u8>,
+) -> Result<(), EncodeError> {
+    checked_encode_variable_data(TSP_DEVELOPMENT_VID, sender.as_ref(), output)
+}
+
+/// Decode the plaintext of a message with a hidden sender; returns the range of the
+/// sender VID, the range of the payload and the signature that follows them
+pub fn decode_hidden_sender(
+    plaintext: &[u8],
+) -> Result<(Range<usize>, Range<usize>, Signature), DecodeError> {
+    let sender =
+        decode_variable_data_index(TSP_DEVELOPMENT_VID, plaintext).ok_or(DecodeError::VidError)?;
+
+    let payload_end = plaintext
+        .len()
+        .checked_sub(SIGNATURE_SIZE)
+        .filter(|end| *end >= sender.end)
+        .ok_or(DecodeError::UnexpectedData)?;
+
+    let mut stream = &plaintext[payload_end..];
+    let signature =
+        decode_fixed_data(ED25519_SIGNATURE, &mut stream).ok_or(DecodeError::SignatureError)?;
+
+    Ok((sender.clone(), sender.end..payload_end, *signature))
+}
+
+/// Encode the payload of a signed message that also has non-confidential data; it follows
+/// the envelope. Without non-confidential data, the payload takes its place in the envelope.
+pub fn encode_signed_payload(
+    payload: &[u8],
+    output: &mut impl for<'b> Extend<&'b u8>,
+) -> Result<(), EncodeError> {
+    checked_encode_variable_data(TSP_PLAINTEXT, payload, output)
+}
+
 /// Encode a Ed25519 signature into CESR
 /// TODO: replace type with a more precise "signature" type
 pub fn encode_signature(signature: &Signature, output: &mut impl for<'a> Extend<&'a u8>) {
     encode_fixed_data(ED25519_SIGNATURE, signature, output);
 }
 
-/// Encode a encrypted ciphertext into CESR
+/// Encode a ciphertext, that was encrypted using `cipher_suite`, into CESR
 pub fn encode_ciphertext(
+    cipher_suite: CipherSuite,
     ciphertext: &[u8],
     output: &mut impl for<'a> Extend<&'a u8>,
 ) -> Result<(), EncodeError> {
-    checked_encode_variable_data(TSP_CIPHERTEXT, ciphertext, output)
+    checked_encode_variable_data(ciphertext_code(cipher_suite), ciphertext, output)
+}
+
+/// Encode the CESR header for a ciphertext of `len` bytes, which has to be appended
+/// after it; this allows encrypting the ciphertext in place, in the output buffer
+pub fn encode_ciphertext_header(
+    cipher_suite: CipherSuite,
+    len: usize,
+    output: &mut impl for<'a> Extend<&'a u8>,
+) -> Result<(), EncodeError> {
+    if len >= DATA_LIMIT {
+        return Err(EncodeError::PayloadTooLarge);
+    }
+
+    encode_variable_data_header(ciphertext_code(cipher_suite), len, output);
+
+    Ok(())
+}
+
+/// Checks whether `stream` starts with the envelope of a message with a hidden sender
+pub(super) fn is_hidden_sender_envelope(stream: &[u8]) -> bool {
+    decode_count(TSP_HIDDEN_SENDER_WRAPPER, &mut &stream[..]) == Some(1)
 }
 
 /// Checks whether the expected TSP header is present and returns its size and whether it
@@ -376,6 +851,52 @@ pub(super) fn detected_tsp_header_size_and_confidentiality(
     Ok((6, encrypted))
 }
 
+/// Determine the length of the TSP message at the start of `stream`, without decoding it;
+/// returns `Ok(None)` if `stream` does not yet contain the complete message.
+/// This can be used to split a stream of bytes into TSP messages.
+pub fn message_length(stream: &[u8]) -> Result<Option<usize>, DecodeError> {
+    if stream.len() < 6 {
+        return Ok(None);
+    }
+
+    // a message with a hidden sender has no signature after its ciphertext
+    let hidden_sender = is_hidden_sender_envelope(stream);
+    let mut pos = if hidden_sender {
+        match decode_fixed_data(TSP_TYPECODE, &mut &stream[3..]) {
+            Some([0, 0]) => 6,
+            _ => return Err(DecodeError::VersionMismatch),
+        }
+    } else {
+        detected_tsp_header_size_and_confidentiality(&mut &stream[..])?.0
+    };
+
+    // sender, receiver, non-confidential data and ciphertext are all variable size data,
+    // the signature that follows them is not
+    loop {
+        let Some(rest) = stream.get(pos..) else {
+            return Ok(None);
+        };
+
+        match decode_variable_data_header(rest) {
+            Ok(Some((TSP_DEVELOPMENT_VID | TSP_PLAINTEXT, size))) => pos += size,
+            Ok(Some((_, size))) if detect_cipher_suite(rest).is_some() => {
+                pos += size;
+
+                if hidden_sender {
+                    return Ok((stream.len() >= pos).then_some(pos));
+                }
+            }
+            Ok(Some(_)) => return Err(DecodeError::UnexpectedData),
+            Ok(None) => return Ok(None),
+            Err(_) => break,
+        }
+    }
+
+    let end = pos + (std::mem::size_of::<Signature>() + 1).next_multiple_of(3);
+
+    Ok((stream.len() >= end).then_some(end))
+}
+
 /// A structure representing a siganture + data that needs to be verified
 #[derive(Clone, Debug)]
 #[must_use]
@@ -418,9 +939,17 @@ pub fn decode_envelope<'a, Vid: TryFrom<&'a [u8]>>(
     let nonconfidential_data = decode_variable_data(TSP_PLAINTEXT, &mut stream);
     let raw_header = &origin[..origin.len() - stream.len()];
 
+    let plaintext = if has_confidential_part {
+        None
+    } else {
+        decode_variable_data(TSP_PLAINTEXT, &mut stream)
+    };
+
     let ciphertext = has_confidential_part
         .then(|| {
-            decode_variable_data(TSP_CIPHERTEXT, &mut stream).ok_or(DecodeError::UnexpectedData)
+            let cipher_suite = detect_cipher_suite(stream).ok_or(DecodeError::UnexpectedData)?;
+            decode_variable_data(ciphertext_code(cipher_suite), &mut stream)
+                .ok_or(DecodeError::UnexpectedData)
         })
         .transpose()?;
     let signed_data = &origin[..origin.len() - stream.len()];
@@ -440,6 +969,7 @@ pub fn decode_envelope<'a, Vid: TryFrom<&'a [u8]>>(
             },
             raw_header,
             ciphertext,
+            plaintext,
         },
         VerificationChallenge {
             signed_data,
@@ -448,7 +978,61 @@ pub fn decode_envelope<'a, Vid: TryFrom<&'a [u8]>>(
     ))
 }
 
-use std::ops::Range;
+/// The locations of the parts of a message with a hidden sender, see [decode_hidden_sender_envelope]
+#[derive(Clone, Debug)]
+pub struct HiddenSenderEnvelope {
+    pub receiver: Range<usize>,
+    pub nonconfidential_data: Option<Range<usize>>,
+    /// The envelope, which is used as associated data
+    pub associated_data: Range<usize>,
+    /// The ciphertext, excluding its CESR code
+    pub ciphertext: Range<usize>,
+    pub cipher_suite: CipherSuite,
+}
+
+/// Decode the envelope of a message with a hidden sender; the message is not
+/// modified, the returned ranges can be used to decrypt it in place
+pub fn decode_hidden_sender_envelope(stream: &[u8]) -> Result<HiddenSenderEnvelope, DecodeError> {
+    let mut rest = stream;
+    if decode_count(TSP_HIDDEN_SENDER_WRAPPER, &mut rest) != Some(1) {
+        return Err(DecodeError::VersionMismatch);
+    }
+    match decode_fixed_data(TSP_TYPECODE, &mut rest) {
+        Some([0, 0]) => {}
+        _ => return Err(DecodeError::VersionMismatch),
+    }
+
+    // decode the variable size data at `pos`, and advance past it
+    let next = |identifier: u32, pos: &mut usize| {
+        decode_variable_data_index(identifier, &stream[*pos..]).map(|range| {
+            let range = *pos + range.start..*pos + range.end;
+            *pos = range.end;
+
+            range
+        })
+    };
+
+    let mut pos = stream.len() - rest.len();
+    let receiver = next(TSP_DEVELOPMENT_VID, &mut pos).ok_or(DecodeError::VidError)?;
+    let nonconfidential_data = next(TSP_PLAINTEXT, &mut pos);
+    let associated_data = 0..pos;
+
+    let cipher_suite = detect_cipher_suite(&stream[pos..]).ok_or(DecodeError::UnexpectedData)?;
+    let ciphertext =
+        next(ciphertext_code(cipher_suite), &mut pos).ok_or(DecodeError::UnexpectedData)?;
+
+    if ciphertext.end != stream.len() {
+        return Err(DecodeError::TrailingGarbage);
+    }
+
+    Ok(HiddenSenderEnvelope {
+        receiver,
+        nonconfidential_data,
+        associated_data,
+        ciphertext,
+        cipher_suite,
+    })
+}
 
 #[derive(Debug)]
 pub struct CipherView<'a> {
@@ -463,6 +1047,8 @@ pub struct CipherView<'a> {
 
     signed_data: Range<usize>,
     ciphertext: Option<Range<usize>>,
+    cipher_suite: Option<CipherSuite>,
+    plaintext: Option<Range<usize>>,
 }
 
 impl<'a> CipherView<'a> {
@@ -496,9 +1082,15 @@ impl<'a> CipherView<'a> {
             envelope,
             raw_header,
             ciphertext,
+            plaintext: self.plaintext.map(|range| &header[range]),
         })
     }
 
+    /// The cipher suite that was used to encrypt the ciphertext, if there is any
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        self.cipher_suite
+    }
+
     pub fn as_challenge(&self) -> VerificationChallenge {
         VerificationChallenge {
             signed_data: &self.data[self.signed_data.clone()],
@@ -534,9 +1126,27 @@ pub fn decode_envelope_mut<'a>(stream: &'a mut [u8]) -> Result<CipherView<'a>, D
 
     let associated_data = 0..pos;
 
-    let ciphertext = if has_confidential_part {
-        let mut ciphertext = decode_variable_data_index(TSP_CIPHERTEXT, &stream[pos..])
-            .ok_or(DecodeError::UnexpectedData)?;
+    let plaintext = if has_confidential_part {
+        None
+    } else {
+        decode_variable_data_index(TSP_PLAINTEXT, &stream[pos..]).map(|range| {
+            let range = pos + range.start..pos + range.end;
+            pos = range.end;
+
+            range
+        })
+    };
+
+    let cipher_suite = if has_confidential_part {
+        Some(detect_cipher_suite(&stream[pos..]).ok_or(DecodeError::UnexpectedData)?)
+    } else {
+        None
+    };
+
+    let ciphertext = if let Some(cipher_suite) = cipher_suite {
+        let mut ciphertext =
+            decode_variable_data_index(ciphertext_code(cipher_suite), &stream[pos..])
+                .ok_or(DecodeError::UnexpectedData)?;
         ciphertext.start += pos;
         ciphertext.end += pos;
         pos = ciphertext.end;
@@ -571,6 +1181,8 @@ pub fn decode_envelope_mut<'a>(stream: &'a mut [u8]) -> Result<CipherView<'a>, D
 
         signed_data,
         ciphertext,
+        cipher_suite,
+        plaintext,
     })
 }
 
@@ -639,12 +1251,19 @@ pub struct MessageParts {
     pub sender: Part,
     pub receiver: Option<Part>,
     pub nonconfidential_data: Option<Part>,
+    /// The payload of a signed message that also has non-confidential data
+    pub plaintext: Option<Part>,
     pub ciphertext: Option<Part>,
     pub signature: Part,
 }
 
 /// Decode a CESR-encoded message into its CESR-encoded parts
 pub fn open_message_into_parts(data: &[u8]) -> Result<MessageParts, DecodeError> {
+    #[cfg(feature = "cesr-t")]
+    let mut buffer = data.to_vec();
+    #[cfg(feature = "cesr-t")]
+    let data: &[u8] = super::to_binary(&mut buffer).ok_or(DecodeError::UnexpectedData)?;
+
     let (mut pos, _) = detected_tsp_header_size_and_confidentiality(&mut (data as &[u8]))?;
 
     let prefix = Part {
@@ -655,7 +1274,9 @@ pub fn open_message_into_parts(data: &[u8]) -> Result<MessageParts, DecodeError>
     let sender = Part::decode(TSP_DEVELOPMENT_VID, data, &mut pos).ok_or(DecodeError::VidError)?;
     let receiver = Part::decode(TSP_DEVELOPMENT_VID, data, &mut pos);
     let nonconfidential_data = Part::decode(TSP_PLAINTEXT, data, &mut pos);
-    let ciphertext = Part::decode(TSP_CIPHERTEXT, data, &mut pos);
+    let plaintext = Part::decode(TSP_PLAINTEXT, data, &mut pos);
+    let ciphertext = detect_cipher_suite(&data[pos..])
+        .and_then(|cipher_suite| Part::decode(ciphertext_code(cipher_suite), data, &mut pos));
 
     let signature: &[u8; 64] = decode_fixed_data(ED25519_SIGNATURE, &mut &data[pos..])
         .ok_or(DecodeError::SignatureError)?;
@@ -670,6 +1291,7 @@ pub fn open_message_into_parts(data: &[u8]) -> Result<MessageParts, DecodeError>
         sender,
         receiver,
         nonconfidential_data,
+        plaintext,
         ciphertext,
         signature,
     })
@@ -706,7 +1328,7 @@ pub fn encode_tsp_message<Vid: AsRef<[u8]>>(
 
     let ciphertext = &encrypt(receiver, encode_payload_vec(message)?);
 
-    encode_ciphertext(ciphertext, &mut cesr)?;
+    encode_ciphertext(CipherSuite::default(), ciphertext, &mut cesr)?;
     encode_signature(&sign(sender, &cesr), &mut cesr);
 
     Ok(cesr)
@@ -756,7 +1378,7 @@ pub fn decode_tsp_message<'a, Vid: TryFrom<&'a [u8]>>(
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
 
     #[test]
@@ -776,7 +1398,7 @@ mod test {
         })
         .unwrap();
         let ciphertext = dummy_crypt(&cesr_payload);
-        encode_ciphertext(ciphertext, &mut outer).unwrap();
+        encode_ciphertext(CipherSuite::default(), ciphertext, &mut outer).unwrap();
 
         let signed_data = outer.clone();
         encode_signature(&fixed_sig, &mut outer);
@@ -803,6 +1425,41 @@ mod test {
         assert_eq!(data, b"Hello TSP!");
     }
 
+    #[test]
+    fn signed_envelope_with_nonconfidential_data() {
+        let fixed_sig = [1; 64];
+
+        let mut outer = encode_s_envelope_vec(Envelope {
+            sender: &b"Alister"[..],
+            receiver: Some(&b"Bobbi"[..]),
+            nonconfidential_data: Some(b"treasure"),
+        })
+        .unwrap();
+        encode_signed_payload(b"Hello TSP!", &mut outer).unwrap();
+
+        let signed_data = outer.clone();
+        encode_signature(&fixed_sig, &mut outer);
+
+        let (
+            DecodedEnvelope {
+                envelope: env,
+                ciphertext,
+                plaintext,
+                ..
+            },
+            ver,
+        ) = decode_envelope::<&[u8]>(&outer).unwrap();
+        assert_eq!(ver.signed_data, signed_data);
+        assert_eq!(env.nonconfidential_data, Some(&b"treasure"[..]));
+        assert_eq!(plaintext, Some(&b"Hello TSP!"[..]));
+        assert!(ciphertext.is_none());
+
+        let view = decode_envelope_mut(&mut outer).unwrap();
+        assert_eq!(view.as_challenge().signed_data, signed_data);
+        let DecodedEnvelope { plaintext, .. } = view.into_opened::<&[u8]>().unwrap();
+        assert_eq!(plaintext, Some(&b"Hello TSP!"[..]));
+    }
+
     #[test]
     fn envelope_with_nonconfidential_data() {
         fn dummy_crypt(data: &[u8]) -> &[u8] {
@@ -820,7 +1477,7 @@ mod test {
         })
         .unwrap();
         let ciphertext = dummy_crypt(&cesr_payload);
-        encode_ciphertext(ciphertext, &mut outer).unwrap();
+        encode_ciphertext(CipherSuite::default(), ciphertext, &mut outer).unwrap();
 
         let signed_data = outer.clone();
         encode_signature(&fixed_sig, &mut outer);
@@ -895,7 +1552,7 @@ mod test {
         })
         .unwrap();
         let ciphertext = dummy_crypt(&cesr_payload); // this is wrong
-        encode_ciphertext(ciphertext, &mut outer).unwrap();
+        encode_ciphertext(CipherSuite::default(), ciphertext, &mut outer).unwrap();
         encode_signature(&fixed_sig, &mut outer);
 
         assert!(decode_envelope::<&[u8]>(&outer).is_err());
@@ -916,7 +1573,7 @@ mod test {
         )
         .unwrap();
         encode_signature(&fixed_sig, &mut outer);
-        encode_ciphertext(&[], &mut outer).unwrap();
+        encode_ciphertext(CipherSuite::default(), &[], &mut outer).unwrap();
 
         assert!(decode_envelope::<&[u8]>(&outer).is_err());
     }
@@ -931,7 +1588,7 @@ mod test {
             nonconfidential_data: Some(b"treasure"),
         })
         .unwrap();
-        encode_ciphertext(&[], &mut outer).unwrap();
+        encode_ciphertext(CipherSuite::default(), &[], &mut outer).unwrap();
         encode_signature(&fixed_sig, &mut outer);
         outer.push(b'-');
 
@@ -975,6 +1632,45 @@ mod test {
         test_turn_around(Payload::GenericMessage(&b"Hello TSP!"[..]));
     }
 
+    #[test]
+    fn test_compressed_msg() {
+        test_turn_around(Payload::CompressedMessage(&b"Hello TSP!"[..]));
+    }
+
+    #[test]
+    fn test_fresh_msg() {
+        test_turn_around(Payload::FreshMessage {
+            message_id: &[1; 32],
+            timestamp: 1_700_000_000,
+            data: &b"Hello TSP!"[..],
+        });
+    }
+
+    #[test]
+    fn test_acknowledged_msg() {
+        test_turn_around(Payload::AcknowledgedMessage {
+            nonce: Nonce([1; 32]),
+            data: &b"Hello TSP!"[..],
+        });
+        test_turn_around(Payload::Receipt { reply: &[1; 32] });
+    }
+
+    #[test]
+    fn test_threaded_msg() {
+        test_turn_around(Payload::ThreadedMessage {
+            nonce: Nonce([3; 32]),
+            thread_id: &[1; 32],
+            reply_to: None,
+            data: &b"Hello TSP!"[..],
+        });
+        test_turn_around(Payload::ThreadedMessage {
+            nonce: Nonce([3; 32]),
+            thread_id: &[1; 32],
+            reply_to: Some(&[2; 32]),
+            data: &b"Hello TSP!"[..],
+        });
+    }
+
     #[test]
     fn test_nested_msg() {
         test_turn_around(Payload::NestedMessage(&b"Hello TSP!"[..]));
@@ -988,6 +1684,72 @@ mod test {
         ));
     }
 
+    #[test]
+    fn test_message_length() {
+        let mut message = encode_ets_envelope_vec(Envelope {
+            sender: &b"Alister"[..],
+            receiver: Some(&b"Bobbi"[..]),
+            nonconfidential_data: Some(b"treasure"),
+        })
+        .unwrap();
+        encode_ciphertext(CipherSuite::default(), &[5; 5000], &mut message).unwrap();
+        encode_signature(&[1; 64], &mut message);
+
+        let length = message.len();
+        for i in 0..length {
+            assert_eq!(message_length(&message[..i]).unwrap(), None);
+        }
+
+        message.extend_from_slice(&message.clone());
+        assert_eq!(message_length(&message).unwrap(), Some(length));
+        assert!(message_length(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_hidden_sender_envelope() {
+        let mut message = vec![];
+        encode_hidden_sender_envelope(&b"Bobbi"[..], Some(b"treasure"), &mut message).unwrap();
+        let header_len = message.len();
+        encode_ciphertext(CipherSuite::default(), &[5; 5000], &mut message).unwrap();
+
+        let envelope = decode_hidden_sender_envelope(&message).unwrap();
+        assert_eq!(&message[envelope.receiver], b"Bobbi");
+        assert_eq!(
+            &message[envelope.nonconfidential_data.unwrap()],
+            b"treasure"
+        );
+        assert_eq!(envelope.associated_data, 0..header_len);
+        assert_eq!(message[envelope.ciphertext], [5; 5000]);
+        assert_eq!(envelope.cipher_suite, CipherSuite::default());
+
+        // there is no signature after the ciphertext
+        let length = message.len();
+        for i in 0..length {
+            assert_eq!(message_length(&message[..i]).unwrap(), None);
+        }
+
+        message.extend_from_slice(&message.clone());
+        assert_eq!(message_length(&message).unwrap(), Some(length));
+        assert!(decode_hidden_sender_envelope(&message).is_err());
+    }
+
+    #[test]
+    fn test_stream_chunk() {
+        let stream_id = [7; 32];
+        test_turn_around(Payload::StreamChunk {
+            stream_id: &stream_id,
+            index: 0,
+            last: false,
+            data: &b"Hello "[..],
+        });
+        test_turn_around(Payload::StreamChunk {
+            stream_id: &stream_id,
+            index: 1,
+            last: true,
+            data: &b"TSP!"[..],
+        });
+    }
+
     fn test_turn_around(payload: Payload<&[u8], &[u8]>) {
         fn dummy_crypt(data: &[u8]) -> &[u8] {
             data
@@ -996,17 +1758,25 @@ mod test {
 
         let cesr_payload = encode_payload_vec(payload.clone()).unwrap();
 
-        let mut outer = encode_ets_envelope_vec(Envelope {
+        let envelope = Envelope {
             sender: &b"Alister"[..],
             receiver: Some(&b"Bobbi"[..]),
             nonconfidential_data: Some(b"treasure"),
-        })
-        .unwrap();
+        };
+        let envelope_size = envelope.estimate_size();
+
+        let mut outer = encode_ets_envelope_vec(envelope).unwrap();
+        assert_eq!(outer.len(), envelope_size);
+
         let ciphertext = dummy_crypt(&cesr_payload);
-        encode_ciphertext(ciphertext, &mut outer).unwrap();
+        encode_ciphertext(CipherSuite::default(), ciphertext, &mut outer).unwrap();
 
         let signed_data = outer.clone();
         encode_signature(&fixed_sig, &mut outer);
+        assert_eq!(
+            outer.len(),
+            envelope_size + ciphertext_size(ciphertext.len()) + SIGNATURE_SIZE
+        );
 
         let view = decode_envelope_mut(&mut outer).unwrap();
         assert_eq!(view.as_challenge().signed_data, signed_data);
@@ -1036,8 +1806,21 @@ mod test {
         test_turn_around(Payload::DirectRelationProposal {
             nonce: Nonce(*nonce),
             hops: vec![],
+            ratchet_key: None,
+        });
+        test_turn_around(Payload::DirectRelationAffirm {
+            reply: nonce,
+            ratchet_secret: None,
+        });
+        test_turn_around(Payload::DirectRelationProposal {
+            nonce: Nonce(*nonce),
+            hops: vec![],
+            ratchet_key: Some(pk1.as_slice().try_into().unwrap()),
+        });
+        test_turn_around(Payload::DirectRelationAffirm {
+            reply: nonce,
+            ratchet_secret: Some(&pk2),
         });
-        test_turn_around(Payload::DirectRelationAffirm { reply: nonce });
         let public_keys = PairedKeys {
             signing: pk1.as_slice().try_into().unwrap(),
             encrypting: pk2.as_slice().try_into().unwrap(),
@@ -1048,6 +1831,10 @@ mod test {
             public_keys,
         });
 
+        test_turn_around(Payload::RelationshipReject {
+            reply: nonce,
+            nonce: Nonce(*nonce),
+        });
         test_turn_around(Payload::RelationshipCancel {
             reply: nonce,
             nonce: Nonce(*nonce),
@@ -1068,4 +1855,194 @@ mod test {
         assert_eq!(parts.receiver.unwrap().data.len(), 14);
         assert_eq!(parts.ciphertext.unwrap().data.len(), 69);
     }
+
+    /// The owned data from which an arbitrary [Payload] is assembled
+    #[derive(Debug, Clone)]
+    pub(crate) struct PayloadParts {
+        pub(crate) variant: PayloadVariant,
+        pub(crate) data: Vec<u8>,
+        pub(crate) hops: Vec<Vec<u8>>,
+        pub(crate) digests: [[u8; 32]; 3],
+        pub(crate) number: u64,
+        pub(crate) flag: bool,
+    }
+
+    /// The variants of [Payload]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) enum PayloadVariant {
+        GenericMessage,
+        CompressedMessage,
+        FreshMessage,
+        AcknowledgedMessage,
+        ThreadedMessage,
+        NestedMessage,
+        RoutedMessage,
+        StreamChunk,
+        DirectRelationProposal,
+        DirectRelationAffirm,
+        NestedRelationProposal,
+        NestedRelationAffirm,
+        Receipt,
+        RatchetedMessage,
+        RelationshipReject,
+        RelationshipCancel,
+    }
+
+    impl PayloadVariant {
+        /// The variant of `payload`; a new variant of [Payload] does not compile until it is
+        /// added here, and generated by [payload_parts]
+        fn of(payload: &Payload<&[u8], &[u8]>) -> Self {
+            match payload {
+                Payload::GenericMessage(_) => Self::GenericMessage,
+                Payload::CompressedMessage(_) => Self::CompressedMessage,
+                Payload::FreshMessage { .. } => Self::FreshMessage,
+                Payload::AcknowledgedMessage { .. } => Self::AcknowledgedMessage,
+                Payload::ThreadedMessage { .. } => Self::ThreadedMessage,
+                Payload::NestedMessage(_) => Self::NestedMessage,
+                Payload::RoutedMessage(..) => Self::RoutedMessage,
+                Payload::StreamChunk { .. } => Self::StreamChunk,
+                Payload::DirectRelationProposal { .. } => Self::DirectRelationProposal,
+                Payload::DirectRelationAffirm { .. } => Self::DirectRelationAffirm,
+                Payload::NestedRelationProposal { .. } => Self::NestedRelationProposal,
+                Payload::NestedRelationAffirm { .. } => Self::NestedRelationAffirm,
+                Payload::Receipt { .. } => Self::Receipt,
+                Payload::RatchetedMessage { .. } => Self::RatchetedMessage,
+                Payload::RelationshipReject { .. } => Self::RelationshipReject,
+                Payload::RelationshipCancel { .. } => Self::RelationshipCancel,
+            }
+        }
+    }
+
+    pub(crate) fn payload_parts() -> impl proptest::strategy::Strategy<Value = PayloadParts> {
+        use proptest::{collection::vec, prelude::*};
+        use PayloadVariant::*;
+
+        let variant = prop_oneof![
+            Just(GenericMessage),
+            Just(CompressedMessage),
+            Just(FreshMessage),
+            Just(AcknowledgedMessage),
+            Just(ThreadedMessage),
+            Just(NestedMessage),
+            Just(RoutedMessage),
+            Just(StreamChunk),
+            Just(DirectRelationProposal),
+            Just(DirectRelationAffirm),
+            Just(NestedRelationProposal),
+            Just(NestedRelationAffirm),
+            Just(Receipt),
+            Just(RatchetedMessage),
+            Just(RelationshipReject),
+            Just(RelationshipCancel),
+        ];
+
+        // variable length data that is large enough to need a long header as well
+        let data = prop_oneof![vec(any::<u8>(), 0..64), vec(any::<u8>(), 12_000..13_000)];
+
+        (
+            variant,
+            data,
+            vec(vec(any::<u8>(), 1..48), 1..5),
+            any::<[[u8; 32]; 3]>(),
+            any::<u64>(),
+            any::<bool>(),
+        )
+            .prop_map(
+                |(variant, data, hops, digests, number, flag)| PayloadParts {
+                    variant,
+                    data,
+                    hops,
+                    digests,
+                    number,
+                    flag,
+                },
+            )
+    }
+
+    impl PayloadParts {
+        fn payload(&self) -> Payload<&[u8], &[u8]> {
+            let data = self.data.as_slice();
+            let hops = self.hops.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            let [first, second, third] = &self.digests;
+            let public_keys = PairedKeys {
+                signing: second,
+                encrypting: third,
+            };
+
+            match self.variant {
+                PayloadVariant::GenericMessage => Payload::GenericMessage(data),
+                PayloadVariant::CompressedMessage => Payload::CompressedMessage(data),
+                PayloadVariant::FreshMessage => Payload::FreshMessage {
+                    message_id: first,
+                    timestamp: self.number,
+                    data,
+                },
+                PayloadVariant::AcknowledgedMessage => Payload::AcknowledgedMessage {
+                    nonce: Nonce(*first),
+                    data,
+                },
+                PayloadVariant::ThreadedMessage => Payload::ThreadedMessage {
+                    nonce: Nonce(*third),
+                    thread_id: first,
+                    reply_to: self.flag.then_some(second),
+                    data,
+                },
+                PayloadVariant::NestedMessage => Payload::NestedMessage(data),
+                PayloadVariant::RoutedMessage => Payload::RoutedMessage(hops, data),
+                PayloadVariant::StreamChunk => Payload::StreamChunk {
+                    stream_id: first,
+                    index: self.number,
+                    last: self.flag,
+                    data,
+                },
+                PayloadVariant::DirectRelationProposal => Payload::DirectRelationProposal {
+                    nonce: Nonce(*first),
+                    hops: if self.flag { hops } else { vec![] },
+                    ratchet_key: self.flag.then_some(second),
+                },
+                PayloadVariant::DirectRelationAffirm => Payload::DirectRelationAffirm {
+                    reply: first,
+                    ratchet_secret: self.flag.then_some(data),
+                },
+                PayloadVariant::NestedRelationProposal => {
+                    Payload::NestedRelationProposal { public_keys }
+                }
+                PayloadVariant::NestedRelationAffirm => Payload::NestedRelationAffirm {
+                    reply: first,
+                    public_keys,
+                },
+                PayloadVariant::Receipt => Payload::Receipt { reply: first },
+                PayloadVariant::RatchetedMessage => Payload::RatchetedMessage {
+                    index: self.number,
+                    data,
+                },
+                PayloadVariant::RelationshipReject => Payload::RelationshipReject {
+                    nonce: Nonce(*first),
+                    reply: second,
+                },
+                PayloadVariant::RelationshipCancel => Payload::RelationshipCancel {
+                    nonce: Nonce(*first),
+                    reply: second,
+                },
+            }
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_payload_round_trip(parts in payload_parts()) {
+            let payload = parts.payload();
+            proptest::prop_assert_eq!(PayloadVariant::of(&payload), parts.variant);
+
+            let encoded = encode_payload_vec(payload.clone()).unwrap();
+            proptest::prop_assert_eq!(encoded.len(), payload.estimate_size());
+            proptest::prop_assert_eq!(decode_payload(&encoded).unwrap(), payload.clone());
+
+            let mut encoded_mut = encoded.clone();
+            let (_, raw_bytes) = decode_payload_mut::<&[u8]>(&mut encoded_mut).unwrap();
+            proptest::prop_assert!(encoded.starts_with(raw_bytes));
+
+            test_turn_around(payload);
+        }
+    }
 }
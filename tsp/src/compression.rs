@@ -0,0 +1,51 @@
+use crate::error::Error;
+use miniz_oxide::inflate::TINFLStatus;
+
+/// The maximum size of a decompressed payload; a payload can never be larger than
+/// what could have been sent without compression, which protects against decompression bombs
+pub(crate) const MAX_DECOMPRESSED_SIZE: usize = crate::cesr::DATA_LIMIT;
+
+/// The DEFLATE compression level
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Compress `data` using DEFLATE
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(data, COMPRESSION_LEVEL)
+}
+
+/// Decompress DEFLATE compressed `data`, failing if the result would be larger than `limit`
+pub(crate) fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(data, limit).map_err(|e| {
+        Error::Decompression(match e.status {
+            TINFLStatus::HasMoreOutput => format!("payload is larger than {limit} bytes"),
+            _ => "invalid compressed data".to_string(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress, decompress};
+    use crate::Error;
+
+    #[test]
+    fn test_round_trip() {
+        let data =
+            b"{\"credential\": \"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\"}";
+        let compressed = compress(data);
+
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        let compressed = compress(&[0; 100_000]);
+
+        assert!(matches!(
+            decompress(&compressed, 1000),
+            Err(Error::Decompression(_))
+        ));
+        assert!(decompress(b"not compressed", 1000).is_err());
+    }
+}
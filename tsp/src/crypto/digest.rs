@@ -3,3 +3,15 @@ pub fn sha256(content: &[u8]) -> [u8; 32] {
     use sha2::Digest;
     sha2::Sha256::digest(content).into()
 }
+
+/// Calculate the SHA2-256 of a piece of arbitrary data that is split into several parts
+pub fn sha256_concat(parts: &[&[u8]]) -> [u8; 32] {
+    use sha2::Digest;
+    parts
+        .iter()
+        .fold(sha2::Sha256::new(), |hasher, part| {
+            hasher.chain_update(part)
+        })
+        .finalize()
+        .into()
+}
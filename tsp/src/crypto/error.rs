@@ -10,6 +10,16 @@ pub enum CryptoError {
     Verify(#[from] ed25519_dalek::ed25519::Error),
     #[error("unexpected recipient")]
     UnexpectedRecipient,
+    #[error("unexpected sender")]
+    UnexpectedSender,
     #[error("no ciphertext found in encrypted message")]
     MissingCiphertext,
+    #[error("the size of the ciphertext does not match its header")]
+    CiphertextSize,
+    #[error("post-quantum key encapsulation failed")]
+    KeyEncapsulation,
+    #[error("no ratchet key for message {0}")]
+    MissingRatchetKey(u64),
+    #[error("cipher suite {0} cannot be used for this message")]
+    UnsupportedCipherSuite(crate::definitions::CipherSuite),
 }
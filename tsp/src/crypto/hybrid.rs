@@ -0,0 +1,170 @@
+use hpke::Deserializable;
+use rand::rngs::StdRng;
+
+use super::CryptoError;
+use crate::definitions::KeyData;
+
+/// A shared secret of a post-quantum KEM, which is used as the pre-shared key of HPKE
+pub(crate) type SharedSecret = [u8; 32];
+
+/// Identifies the pre-shared key in the key schedule of HPKE
+pub(crate) const PSK_ID: &[u8] = b"TSP hybrid KEM";
+
+/// A (post-quantum) KEM that can be combined with the KEM of HPKE: its shared secret is
+/// used as the pre-shared key of HPKE "AuthPSK" mode, and its ciphertext is appended
+/// to the encapsulated key of HPKE
+pub(crate) trait HybridKem {
+    /// The size of a ciphertext of this KEM
+    const CIPHERTEXT_SIZE: usize;
+
+    /// The HPKE private key that is contained in the private key of a VID
+    fn private_key<Kem: hpke::Kem>(key: &KeyData) -> Result<Kem::PrivateKey, CryptoError>;
+
+    /// Split the public key of a VID into a HPKE public key and a public key of this KEM
+    fn public_key<Kem: hpke::Kem>(key: &[u8]) -> Result<(Kem::PublicKey, &[u8]), CryptoError>;
+
+    /// Generate a shared secret and its ciphertext for a public key of this KEM
+    fn encapsulate(
+        public_key: &[u8],
+        csprng: &mut StdRng,
+    ) -> Result<Option<(Vec<u8>, SharedSecret)>, CryptoError>;
+
+    /// Recover the shared secret from a ciphertext, using the private key of a VID
+    fn decapsulate(
+        private_key: &KeyData,
+        ciphertext: &[u8],
+    ) -> Result<Option<SharedSecret>, CryptoError>;
+}
+
+/// Only use the KEM of HPKE, i.e. HPKE "Auth" mode
+pub(crate) struct Classical;
+
+impl HybridKem for Classical {
+    const CIPHERTEXT_SIZE: usize = 0;
+
+    fn private_key<Kem: hpke::Kem>(key: &KeyData) -> Result<Kem::PrivateKey, CryptoError> {
+        Ok(Kem::PrivateKey::from_bytes(key)?)
+    }
+
+    fn public_key<Kem: hpke::Kem>(key: &[u8]) -> Result<(Kem::PublicKey, &[u8]), CryptoError> {
+        Ok((Kem::PublicKey::from_bytes(key)?, &[]))
+    }
+
+    fn encapsulate(
+        _public_key: &[u8],
+        _csprng: &mut StdRng,
+    ) -> Result<Option<(Vec<u8>, SharedSecret)>, CryptoError> {
+        Ok(None)
+    }
+
+    fn decapsulate(
+        _private_key: &KeyData,
+        _ciphertext: &[u8],
+    ) -> Result<Option<SharedSecret>, CryptoError> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "pq")]
+pub(crate) use ml_kem_768::{gen_keypair as gen_ml_kem_768_keypair, MlKem768};
+
+#[cfg(feature = "pq")]
+mod ml_kem_768 {
+    use hpke::{Deserializable, Serializable};
+    use ml_kem::{
+        kem::{Decapsulate, Encapsulate},
+        Ciphertext, Encoded, EncodedSizeUser, KemCore,
+    };
+    use rand::{rngs::StdRng, RngCore};
+
+    use super::{CryptoError, HybridKem, SharedSecret};
+    use crate::{cesr::error::DecodeError, crypto::sha256_concat, definitions::KeyData};
+
+    type EncapsulationKey = <ml_kem::MlKem768 as KemCore>::EncapsulationKey;
+    type DecapsulationKey = <ml_kem::MlKem768 as KemCore>::DecapsulationKey;
+
+    /// ML-KEM-768 combined with the KEM of HPKE; like X-Wing, the private key of a VID is
+    /// a seed from which both the HPKE and the ML-KEM key pairs are derived
+    pub(crate) struct MlKem768;
+
+    fn derive_hpke_keypair<Kem: hpke::Kem>(seed: &KeyData) -> (Kem::PrivateKey, Kem::PublicKey) {
+        Kem::derive_keypair(&sha256_concat(&[b"TSP hybrid KEM: HPKE", seed]))
+    }
+
+    fn derive_ml_kem_keypair(seed: &KeyData) -> (DecapsulationKey, EncapsulationKey) {
+        let d = sha256_concat(&[b"TSP hybrid KEM: ML-KEM d", seed]);
+        let z = sha256_concat(&[b"TSP hybrid KEM: ML-KEM z", seed]);
+
+        ml_kem::MlKem768::generate_deterministic(&d.into(), &z.into())
+    }
+
+    /// Generate a new seed and the corresponding hybrid public key
+    pub(crate) fn gen_keypair<Kem: hpke::Kem>() -> (KeyData, Vec<u8>) {
+        let mut seed = KeyData::default();
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+
+        let (_, hpke_public_key) = derive_hpke_keypair::<Kem>(&seed);
+        let (_, ml_kem_public_key) = derive_ml_kem_keypair(&seed);
+
+        let public_key = [
+            hpke_public_key.to_bytes().as_ref(),
+            ml_kem_public_key.as_bytes().as_ref(),
+        ]
+        .concat();
+
+        (seed, public_key)
+    }
+
+    impl HybridKem for MlKem768 {
+        const CIPHERTEXT_SIZE: usize = 1088;
+
+        fn private_key<Kem: hpke::Kem>(key: &KeyData) -> Result<Kem::PrivateKey, CryptoError> {
+            Ok(derive_hpke_keypair::<Kem>(key).0)
+        }
+
+        fn public_key<Kem: hpke::Kem>(key: &[u8]) -> Result<(Kem::PublicKey, &[u8]), CryptoError> {
+            if key.len() < Kem::PublicKey::size() {
+                return Err(DecodeError::UnexpectedData.into());
+            }
+            let (hpke_public_key, ml_kem_public_key) = key.split_at(Kem::PublicKey::size());
+
+            Ok((
+                Kem::PublicKey::from_bytes(hpke_public_key)?,
+                ml_kem_public_key,
+            ))
+        }
+
+        fn encapsulate(
+            public_key: &[u8],
+            csprng: &mut StdRng,
+        ) -> Result<Option<(Vec<u8>, SharedSecret)>, CryptoError> {
+            let public_key = Encoded::<EncapsulationKey>::try_from(public_key)
+                .map_err(|_| DecodeError::UnexpectedData)?;
+            let (ciphertext, shared_key) = EncapsulationKey::from_bytes(&public_key)
+                .encapsulate(csprng)
+                .map_err(|_| CryptoError::KeyEncapsulation)?;
+
+            let mut shared_secret = SharedSecret::default();
+            shared_secret.copy_from_slice(&shared_key);
+
+            Ok(Some((ciphertext.to_vec(), shared_secret)))
+        }
+
+        fn decapsulate(
+            private_key: &KeyData,
+            ciphertext: &[u8],
+        ) -> Result<Option<SharedSecret>, CryptoError> {
+            let ciphertext = Ciphertext::<ml_kem::MlKem768>::try_from(ciphertext)
+                .map_err(|_| DecodeError::UnexpectedData)?;
+            let (decapsulation_key, _) = derive_ml_kem_keypair(private_key);
+            let shared_key = decapsulation_key
+                .decapsulate(&ciphertext)
+                .map_err(|_| CryptoError::KeyEncapsulation)?;
+
+            let mut shared_secret = SharedSecret::default();
+            shared_secret.copy_from_slice(&shared_key);
+
+            Ok(Some(shared_secret))
+        }
+    }
+}
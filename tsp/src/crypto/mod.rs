@@ -1,20 +1,95 @@
 use crate::definitions::{
-    Digest, NonConfidentialData, Payload, PrivateVid, TSPMessage, VerifiedVid,
+    CipherSuite, Digest, KeyData, NonConfidentialData, Payload, PrivateVid, TSPMessage, VerifiedVid,
 };
+use hpke::Serializable;
+use rand::rngs::OsRng;
 
 mod digest;
 pub mod error;
+mod hybrid;
 mod nonconfidential;
+mod ratchet;
 mod tsp_hpke;
 
 pub use error::CryptoError;
-
-pub type Aead = hpke::aead::ChaCha20Poly1305;
-pub type Kdf = hpke::kdf::HkdfSha256;
-pub type Kem = hpke::kem::X25519HkdfSha256;
+pub(crate) use ratchet::{Ratchet, RatchetState};
+pub use tsp_hpke::HiddenSenderMessage;
 
 type ObservingClosure<'a> = &'a mut dyn FnMut(&[u8]);
 
+/// Call a generic function of [tsp_hpke] with the HPKE primitives of a cipher suite
+macro_rules! with_cipher_suite {
+    ($cipher_suite:expr, $function:ident($($arg:expr),* $(,)?)) => {
+        match $cipher_suite {
+            CipherSuite::X25519ChaCha20Poly1305 => tsp_hpke::$function::<
+                hpke::aead::ChaCha20Poly1305,
+                hpke::kdf::HkdfSha256,
+                hpke::kem::X25519HkdfSha256,
+                hybrid::Classical,
+            >($($arg),*),
+            CipherSuite::X25519Aes128Gcm => tsp_hpke::$function::<
+                hpke::aead::AesGcm128,
+                hpke::kdf::HkdfSha256,
+                hpke::kem::X25519HkdfSha256,
+                hybrid::Classical,
+            >($($arg),*),
+            CipherSuite::P256Aes128Gcm => tsp_hpke::$function::<
+                hpke::aead::AesGcm128,
+                hpke::kdf::HkdfSha256,
+                hpke::kem::DhP256HkdfSha256,
+                hybrid::Classical,
+            >($($arg),*),
+            #[cfg(feature = "pq")]
+            CipherSuite::X25519MlKem768ChaCha20Poly1305 => tsp_hpke::$function::<
+                hpke::aead::ChaCha20Poly1305,
+                hpke::kdf::HkdfSha256,
+                hpke::kem::X25519HkdfSha256,
+                hybrid::MlKem768,
+            >($($arg),*),
+        }
+    };
+}
+
+/// Messages are encrypted using the cipher suite of the receiver; since HPKE "Auth" mode
+/// also uses the key of the sender, both keys have to be on the same curve
+fn cipher_suite(
+    sender: &dyn VerifiedVid,
+    receiver: &dyn VerifiedVid,
+) -> Result<CipherSuite, CryptoError> {
+    let cipher_suite = receiver.cipher_suite();
+
+    if sender.cipher_suite().curve() != cipher_suite.curve() {
+        return Err(CryptoError::UnsupportedCipherSuite(cipher_suite));
+    }
+
+    Ok(cipher_suite)
+}
+
+/// Generate a new encryption key pair for `cipher_suite`; returns the private key and the public key
+pub fn gen_encryption_keypair(cipher_suite: CipherSuite) -> (KeyData, Vec<u8>) {
+    fn gen_keypair<Kem: hpke::Kem>() -> (KeyData, Vec<u8>) {
+        let (private_key, public_key) = Kem::gen_keypair(&mut OsRng);
+        let private_key = private_key
+            .to_bytes()
+            .as_ref()
+            .try_into()
+            .expect("private keys of all supported KEMs are 32 bytes");
+
+        (private_key, public_key.to_bytes().as_ref().to_vec())
+    }
+
+    match cipher_suite {
+        CipherSuite::X25519ChaCha20Poly1305 | CipherSuite::X25519Aes128Gcm => {
+            gen_keypair::<hpke::kem::X25519HkdfSha256>()
+        }
+        CipherSuite::P256Aes128Gcm => gen_keypair::<hpke::kem::DhP256HkdfSha256>(),
+        #[cfg(feature = "pq")]
+        CipherSuite::X25519MlKem768ChaCha20Poly1305 => {
+            hybrid::gen_ml_kem_768_keypair::<hpke::kem::X25519HkdfSha256>()
+        }
+    }
+}
+
 /// Encrypt, authenticate and sign and CESR encode a TSP message
 pub fn seal(
     sender: &dyn PrivateVid,
@@ -22,7 +97,32 @@ pub fn seal(
     nonconfidential_data: Option<NonConfidentialData>,
     payload: Payload<&[u8]>,
 ) -> Result<TSPMessage, CryptoError> {
-    tsp_hpke::seal::<Aead, Kdf, Kem>(sender, receiver, nonconfidential_data, payload, None)
+    with_cipher_suite!(
+        cipher_suite(sender, receiver)?,
+        seal(sender, receiver, nonconfidential_data, payload, None)
+    )
+}
+
+/// Encrypt, authenticate and sign and CESR encode a TSP message, and append it to `output`.
+/// Reusing the `output` buffer for subsequent messages avoids allocating memory for each message.
+pub fn seal_into(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+    output: &mut Vec<u8>,
+) -> Result<(), CryptoError> {
+    with_cipher_suite!(
+        cipher_suite(sender, receiver)?,
+        seal_into(
+            sender,
+            receiver,
+            nonconfidential_data,
+            payload,
+            None,
+            output
+        )
+    )
 }
 
 /// Encrypt, authenticate and sign and CESR encode a TSP message; also returns the hash value of the plaintext parts before encryption
@@ -33,20 +133,26 @@ pub fn seal_and_hash(
     payload: Payload<&[u8]>,
 ) -> Result<(TSPMessage, Digest), CryptoError> {
     let digest = &mut Default::default();
-    let msg = tsp_hpke::seal::<Aead, Kdf, Kem>(
-        sender,
-        receiver,
-        nonconfidential_data,
-        payload,
-        Some(&mut |bytes| *digest = sha256(bytes)),
+    let msg = with_cipher_suite!(
+        cipher_suite(sender, receiver)?,
+        seal(
+            sender,
+            receiver,
+            nonconfidential_data,
+            payload,
+            Some(&mut |bytes| *digest = sha256(bytes)),
+        )
     )?;
 
     Ok((msg, *digest))
 }
 
+/// The non-confidential data, the decrypted payload and the raw bytes of the payload
+/// that precede its message data (i.e. the entire payload for control messages);
+/// the message data is mutable, so that a nested message can be opened in place
 pub type MessageContents<'a> = (
     Option<NonConfidentialData<'a>>,
-    Payload<'a, &'a [u8]>,
+    Payload<'a, &'a mut [u8]>,
     &'a [u8],
 );
 
@@ -56,34 +162,122 @@ pub fn open<'a>(
     sender: &dyn VerifiedVid,
     tsp_message: &'a mut [u8],
 ) -> Result<MessageContents<'a>, CryptoError> {
-    tsp_hpke::open::<Aead, Kdf, Kem>(receiver, sender, tsp_message)
+    with_cipher_suite!(
+        cipher_suite(sender, receiver)?,
+        open(receiver, sender, tsp_message)
+    )
+}
+
+/// Encrypt, sign and CESR encode a TSP message with a hidden sender ("sealed sender"):
+/// the envelope only contains the receiver, while the sender is encrypted together with
+/// the payload and signed inside the ciphertext. The payload is encrypted using HPKE "Base"
+/// mode with the cipher suite of the receiver, so only the receiver can identify the sender.
+pub fn seal_hidden_sender(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+) -> Result<TSPMessage, CryptoError> {
+    with_cipher_suite!(
+        receiver.cipher_suite(),
+        seal_hidden_sender(sender, receiver, nonconfidential_data, payload, None)
+    )
 }
 
-/// Construct and sign a non-confidential TSP message
+/// Decode and decrypt a CESR encoded TSP message with a hidden sender; the signature of the
+/// sender has to be verified using [HiddenSenderMessage::verify] to obtain its contents
+pub fn open_hidden_sender<'a>(
+    receiver: &dyn PrivateVid,
+    tsp_message: &'a mut [u8],
+) -> Result<HiddenSenderMessage<'a>, CryptoError> {
+    with_cipher_suite!(
+        receiver.cipher_suite(),
+        open_hidden_sender(receiver, tsp_message)
+    )
+}
+
+/// Encrypt, authenticate and sign and CESR encode a TSP message for multiple receivers;
+/// the payload is only encrypted once, regardless of the number of receivers, using the
+/// cipher suite of the sender; all receivers have to use the same cipher suite
+pub fn seal_multicast(
+    sender: &dyn PrivateVid,
+    receivers: &[&dyn VerifiedVid],
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: &[u8],
+) -> Result<TSPMessage, CryptoError> {
+    let cipher_suite = sender.cipher_suite();
+
+    if let Some(receiver) = receivers
+        .iter()
+        .find(|receiver| receiver.cipher_suite() != cipher_suite)
+    {
+        return Err(CryptoError::UnsupportedCipherSuite(receiver.cipher_suite()));
+    }
+
+    with_cipher_suite!(
+        cipher_suite,
+        seal_multicast(sender, receivers, nonconfidential_data, payload)
+    )
+}
+
+/// Decode a CESR Authentic Confidential multicast message, verify the signature and decrypt its contents
+pub fn open_multicast<'a>(
+    receiver: &dyn PrivateVid,
+    sender: &dyn VerifiedVid,
+    tsp_message: &'a mut [u8],
+) -> Result<MessageContents<'a>, CryptoError> {
+    with_cipher_suite!(
+        cipher_suite(sender, receiver)?,
+        open_multicast(receiver, sender, tsp_message)
+    )
+}
+
+/// Construct and sign a non-confidential TSP message, optionally for a specific receiver
 pub fn sign(
     sender: &dyn PrivateVid,
     receiver: Option<&dyn VerifiedVid>,
+    nonconfidential_data: Option<NonConfidentialData>,
     payload: &[u8],
 ) -> Result<TSPMessage, CryptoError> {
-    nonconfidential::sign(sender, receiver, payload)
+    nonconfidential::sign(sender, receiver, nonconfidential_data, payload)
 }
 
-/// Decode a CESR Authentic Non-Confidential Message, verify the signature and return its contents
+/// Decode a CESR Authentic Non-Confidential Message, verify the signature and return its
+/// non-confidential data and payload
 pub fn verify<'a>(
     sender: &dyn VerifiedVid,
     tsp_message: &'a mut [u8],
-) -> Result<&'a [u8], CryptoError> {
+) -> Result<(Option<NonConfidentialData<'a>>, &'a [u8]), CryptoError> {
     nonconfidential::verify(sender, tsp_message)
 }
 
+/// Verify the outer signature of a CESR encoded TSP message, without decrypting it, and return
+/// its non-confidential data. Unlike [open], this does not need the private keys of the
+/// receiver, so intermediaries can authenticate metadata of messages that pass through them.
+/// Messages with a hidden sender have no outer signature, and can not be verified this way.
+pub fn verify_nonconfidential_data<'a>(
+    sender: &dyn VerifiedVid,
+    tsp_message: &'a [u8],
+) -> Result<Option<NonConfidentialData<'a>>, CryptoError> {
+    nonconfidential::verify_nonconfidential_data(sender, tsp_message)
+}
+
 pub use digest::sha256;
+pub(crate) use digest::sha256_concat;
 
 #[cfg(test)]
 mod tests {
-    use crate::{definitions::Payload, vid::OwnedVid};
+    use crate::{
+        cesr::{payload_parts, PayloadParts},
+        definitions::{CipherSuite, Payload},
+        vid::OwnedVid,
+    };
     use url::Url;
 
-    use super::{open, seal};
+    use super::{
+        open, open_hidden_sender, open_multicast, seal, seal_hidden_sender, seal_into,
+        seal_multicast, verify_nonconfidential_data, CryptoError,
+    };
 
     #[test]
     fn seal_open_message() {
@@ -108,6 +302,342 @@ mod tests {
             open(&alice, &bob, &mut message).unwrap();
 
         assert_eq!(received_nonconfidential_data.unwrap(), nonconfidential_data);
-        assert_eq!(received_secret_message, Payload::Content(secret_message));
+        let Payload::Content(received_secret_message) = received_secret_message else {
+            panic!("unexpected payload type");
+        };
+        assert_eq!(received_secret_message, secret_message);
+    }
+
+    #[test]
+    fn seal_into_reuses_buffer() {
+        let bob = OwnedVid::bind("did:test:bob", Url::parse("tcp:://127.0.0.1:1337").unwrap());
+        let alice = OwnedVid::bind(
+            "did:test:alice",
+            Url::parse("tcp:://127.0.0.1:1337").unwrap(),
+        );
+
+        let mut buffer = Vec::new();
+        seal_into(
+            &bob,
+            &alice,
+            None,
+            Payload::Content(b"hello world"),
+            &mut buffer,
+        )
+        .unwrap();
+
+        // the first message was allocated exactly
+        assert_eq!(buffer.len(), buffer.capacity());
+        let capacity = buffer.capacity();
+
+        for _ in 0..3 {
+            buffer.clear();
+            seal_into(
+                &bob,
+                &alice,
+                None,
+                Payload::Content(b"hello world"),
+                &mut buffer,
+            )
+            .unwrap();
+            assert_eq!(buffer.capacity(), capacity);
+
+            let (_, Payload::Content(message), _) = open(&alice, &bob, &mut buffer).unwrap() else {
+                panic!("unexpected payload type");
+            };
+            assert_eq!(message, b"hello world");
+        }
+    }
+
+    #[test]
+    fn seal_open_cipher_suites() {
+        for &cipher_suite in CipherSuite::ALL {
+            let url = Url::parse("tcp:://127.0.0.1:1337").unwrap();
+            let bob = OwnedVid::bind_with_cipher_suite("did:test:bob", url.clone(), cipher_suite);
+            let alice = OwnedVid::bind_with_cipher_suite("did:test:alice", url, cipher_suite);
+
+            let mut message = seal(&bob, &alice, None, Payload::Content(b"hello world")).unwrap();
+            let (_, Payload::Content(received), _) = open(&alice, &bob, &mut message).unwrap()
+            else {
+                panic!("unexpected payload type");
+            };
+            assert_eq!(received, b"hello world");
+
+            let mut message = seal_multicast(&bob, &[&alice], None, b"hello world").unwrap();
+            let (_, Payload::Content(received), _) =
+                open_multicast(&alice, &bob, &mut message).unwrap()
+            else {
+                panic!("unexpected payload type");
+            };
+            assert_eq!(received, b"hello world");
+        }
+    }
+
+    #[test]
+    fn cipher_suite_mismatch() {
+        let url = Url::parse("tcp:://127.0.0.1:1337").unwrap();
+        let bob = OwnedVid::bind("did:test:bob", url.clone());
+        let alice =
+            OwnedVid::bind_with_cipher_suite("did:test:alice", url, CipherSuite::P256Aes128Gcm);
+
+        assert!(matches!(
+            seal(&bob, &alice, None, Payload::Content(b"hello world")),
+            Err(CryptoError::UnsupportedCipherSuite(
+                CipherSuite::P256Aes128Gcm
+            ))
+        ));
+
+        assert!(matches!(
+            seal_multicast(&bob, &[&alice], None, b"hello world"),
+            Err(CryptoError::UnsupportedCipherSuite(
+                CipherSuite::P256Aes128Gcm
+            ))
+        ));
+    }
+
+    #[test]
+    fn verify_nonconfidential_data_without_receiver() {
+        let url = Url::parse("tcp:://127.0.0.1:1337").unwrap();
+        let bob = OwnedVid::bind("did:test:bob", url.clone());
+        let alice = OwnedVid::bind("did:test:alice", url.clone());
+        let eve = OwnedVid::bind("did:test:eve", url);
+
+        let message = seal(
+            &bob,
+            &alice,
+            Some(b"routing metadata"),
+            Payload::Content(b"hello world"),
+        )
+        .unwrap();
+
+        // only the (public) keys of the sender are needed
+        let data = verify_nonconfidential_data(bob.vid(), &message).unwrap();
+        assert_eq!(data.unwrap(), b"routing metadata");
+
+        assert!(matches!(
+            verify_nonconfidential_data(eve.vid(), &message),
+            Err(CryptoError::UnexpectedSender)
+        ));
+
+        let mut tampered = message.clone();
+        let position = tampered
+            .windows(7)
+            .position(|window| window == b"routing")
+            .unwrap();
+        tampered[position] ^= 1;
+        assert!(matches!(
+            verify_nonconfidential_data(bob.vid(), &tampered),
+            Err(CryptoError::Verify(_))
+        ));
+
+        let message =
+            seal_multicast(&bob, &[&alice], Some(b"routing metadata"), b"hello world").unwrap();
+        let data = verify_nonconfidential_data(bob.vid(), &message).unwrap();
+        assert_eq!(data.unwrap(), b"routing metadata");
+    }
+
+    #[test]
+    fn seal_open_hidden_sender() {
+        for &cipher_suite in CipherSuite::ALL {
+            let url = Url::parse("tcp:://127.0.0.1:1337").unwrap();
+            let bob = OwnedVid::bind_with_cipher_suite("did:test:bob", url.clone(), cipher_suite);
+            let alice =
+                OwnedVid::bind_with_cipher_suite("did:test:alice", url.clone(), cipher_suite);
+            let eve = OwnedVid::bind("did:test:eve", url);
+
+            let mut message = seal_hidden_sender(
+                &bob,
+                &alice,
+                Some(b"extra header data"),
+                Payload::Content(b"hello world"),
+            )
+            .unwrap();
+
+            // only the receiver is visible in the envelope
+            assert!(!message.windows(12).any(|window| window == b"did:test:bob"));
+            assert!(message
+                .windows(14)
+                .any(|window| window == b"did:test:alice"));
+
+            // the receiver learns the sender, but only trusts it after verification
+            let mut copy = message.clone();
+            let opened = open_hidden_sender(&alice, &mut copy).unwrap();
+            assert_eq!(opened.sender(), b"did:test:bob");
+            assert!(matches!(
+                opened.verify(&eve),
+                Err(CryptoError::UnexpectedSender)
+            ));
+
+            let opened = open_hidden_sender(&alice, &mut message).unwrap();
+            let (nonconfidential_data, Payload::Content(received), _) =
+                opened.verify(&bob).unwrap()
+            else {
+                panic!("unexpected payload type");
+            };
+            assert_eq!(nonconfidential_data.unwrap(), b"extra header data");
+            assert_eq!(received, b"hello world");
+        }
+    }
+
+    #[test]
+    fn hidden_sender_wrong_receiver() {
+        let url = Url::parse("tcp:://127.0.0.1:1337").unwrap();
+        let bob = OwnedVid::bind("did:test:bob", url.clone());
+        let alice = OwnedVid::bind("did:test:alice", url.clone());
+        let eve = OwnedVid::bind("did:test:eve", url);
+
+        let mut message =
+            seal_hidden_sender(&bob, &alice, None, Payload::Content(b"hello world")).unwrap();
+
+        assert!(matches!(
+            open_hidden_sender(&eve, &mut message),
+            Err(CryptoError::UnexpectedRecipient)
+        ));
+    }
+
+    /// The variants of a secret [Payload]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum SecretVariant {
+        Content,
+        CompressedContent,
+        FreshContent,
+        AcknowledgedContent,
+        ThreadedContent,
+        RatchetedContent,
+        NestedMessage,
+        RoutedMessage,
+        StreamChunk,
+        RejectRelationship,
+        CancelRelationship,
+        RequestRelationship,
+        AcceptRelationship,
+        Receipt,
+    }
+
+    impl SecretVariant {
+        /// The variant of `payload`; a new variant of [Payload] does not compile until it is
+        /// added here, and generated by [secret_variant]
+        fn of(payload: &Payload<&[u8]>) -> Self {
+            match payload {
+                Payload::Content(_) => Self::Content,
+                Payload::CompressedContent(_) => Self::CompressedContent,
+                Payload::FreshContent { .. } => Self::FreshContent,
+                Payload::AcknowledgedContent(_) => Self::AcknowledgedContent,
+                Payload::ThreadedContent { .. } => Self::ThreadedContent,
+                Payload::RatchetedContent { .. } => Self::RatchetedContent,
+                Payload::NestedMessage(_) => Self::NestedMessage,
+                Payload::RoutedMessage(..) => Self::RoutedMessage,
+                Payload::StreamChunk { .. } => Self::StreamChunk,
+                Payload::RejectRelationship { .. } => Self::RejectRelationship,
+                Payload::CancelRelationship { .. } => Self::CancelRelationship,
+                Payload::RequestRelationship { .. } => Self::RequestRelationship,
+                Payload::AcceptRelationship { .. } => Self::AcceptRelationship,
+                Payload::Receipt { .. } => Self::Receipt,
+            }
+        }
+    }
+
+    fn secret_variant() -> impl proptest::strategy::Strategy<Value = SecretVariant> {
+        use proptest::prelude::*;
+        use SecretVariant::*;
+
+        prop_oneof![
+            Just(Content),
+            Just(CompressedContent),
+            Just(FreshContent),
+            Just(AcknowledgedContent),
+            Just(ThreadedContent),
+            Just(RatchetedContent),
+            Just(NestedMessage),
+            Just(RoutedMessage),
+            Just(StreamChunk),
+            Just(RejectRelationship),
+            Just(CancelRelationship),
+            Just(RequestRelationship),
+            Just(AcceptRelationship),
+            Just(Receipt),
+        ]
+    }
+
+    /// A secret payload of the type `variant`, assembled from the parts of an arbitrary
+    /// CESR payload
+    fn secret_payload(variant: SecretVariant, parts: &PayloadParts) -> Payload<&[u8]> {
+        let data = parts.data.as_slice();
+        let hops = parts.hops.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let [first, second, _] = &parts.digests;
+
+        match variant {
+            SecretVariant::Content => Payload::Content(data),
+            SecretVariant::CompressedContent => Payload::CompressedContent(data),
+            SecretVariant::FreshContent => Payload::FreshContent {
+                message_id: *first,
+                timestamp: parts.number,
+                data,
+            },
+            SecretVariant::AcknowledgedContent => Payload::AcknowledgedContent(data),
+            SecretVariant::ThreadedContent => Payload::ThreadedContent {
+                thread_id: *first,
+                reply_to: parts.flag.then_some(*second),
+                data,
+            },
+            SecretVariant::RatchetedContent => Payload::RatchetedContent {
+                index: parts.number,
+                data,
+            },
+            SecretVariant::NestedMessage => Payload::NestedMessage(data),
+            SecretVariant::RoutedMessage => Payload::RoutedMessage(hops, data),
+            SecretVariant::StreamChunk => Payload::StreamChunk {
+                stream_id: *first,
+                index: parts.number,
+                last: parts.flag,
+                data,
+            },
+            SecretVariant::RejectRelationship => Payload::RejectRelationship { thread_id: *first },
+            SecretVariant::CancelRelationship => Payload::CancelRelationship { thread_id: *first },
+            SecretVariant::RequestRelationship => Payload::RequestRelationship {
+                route: parts.flag.then_some(hops),
+                ratchet_key: parts.flag.then_some(second),
+            },
+            SecretVariant::AcceptRelationship => Payload::AcceptRelationship {
+                thread_id: *first,
+                ratchet_secret: parts.flag.then_some(data),
+            },
+            SecretVariant::Receipt => Payload::Receipt {
+                message_digest: *first,
+            },
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn bit_flips_are_detected(
+            variant in secret_variant(),
+            parts in payload_parts(),
+            bit in proptest::prelude::any::<usize>(),
+        ) {
+            let bob = OwnedVid::bind("did:test:bob", Url::parse("tcp:://127.0.0.1:1337").unwrap());
+            let alice = OwnedVid::bind(
+                "did:test:alice",
+                Url::parse("tcp:://127.0.0.1:1337").unwrap(),
+            );
+
+            let payload = secret_payload(variant, &parts);
+            proptest::prop_assert_eq!(SecretVariant::of(&payload), variant);
+
+            let mut message = seal(&bob, &alice, None, payload).unwrap();
+
+            let bit = bit % (8 * message.len());
+            let (index, mask) = (bit / 8, 1 << (bit % 8));
+
+            // without the `strict` feature, the padding bits in the code of the
+            // (unsigned) signature are ignored
+            let signature_code = message.len() - 65;
+            proptest::prop_assume!(
+                cfg!(feature = "strict") || index != signature_code || mask >= 1 << 4
+            );
+
+            message[index] ^= mask;
+            proptest::prop_assert!(open(&alice, &bob, &mut message).is_err());
+        }
     }
 }
@@ -1,6 +1,6 @@
 use crate::{
     cesr::{DecodedEnvelope, Envelope},
-    definitions::{PrivateVid, TSPMessage, VerifiedVid},
+    definitions::{NonConfidentialData, PrivateVid, TSPMessage, VerifiedVid},
 };
 use ed25519_dalek::ed25519::signature::Signer;
 
@@ -10,19 +10,30 @@ use super::CryptoError;
 pub fn sign(
     sender: &dyn PrivateVid,
     receiver: Option<&dyn VerifiedVid>,
+    nonconfidential_data: Option<NonConfidentialData>,
     payload: &[u8],
 ) -> Result<TSPMessage, CryptoError> {
     let mut data = Vec::with_capacity(64);
 
+    // the payload takes the place of the non-confidential data, unless there is any
+    let (nonconfidential_data, signed_payload) = match nonconfidential_data {
+        Some(nonconfidential_data) => (nonconfidential_data, Some(payload)),
+        None => (payload, None),
+    };
+
     crate::cesr::encode_s_envelope(
         crate::cesr::Envelope {
             sender: sender.identifier(),
             receiver: receiver.map(|r| r.identifier()),
-            nonconfidential_data: Some(payload),
+            nonconfidential_data: Some(nonconfidential_data),
         },
         &mut data,
     )?;
 
+    if let Some(payload) = signed_payload {
+        crate::cesr::encode_signed_payload(payload, &mut data)?;
+    }
+
     // create and append signature
     let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key());
     let signature = sign_key.sign(&data).to_bytes();
@@ -31,11 +42,12 @@ pub fn sign(
     Ok(data)
 }
 
-/// Decode a CESR Authentic Non-Confidential Message, verify the signature and return its contents
+/// Decode a CESR Authentic Non-Confidential Message, verify the signature and return its
+/// non-confidential data and payload
 pub fn verify<'a>(
     sender: &dyn VerifiedVid,
     tsp_message: &'a mut [u8],
-) -> Result<&'a [u8], CryptoError> {
+) -> Result<(Option<NonConfidentialData<'a>>, &'a [u8]), CryptoError> {
     let view = crate::cesr::decode_envelope_mut(tsp_message)?;
 
     // verify outer signature
@@ -54,6 +66,7 @@ pub fn verify<'a>(
                 nonconfidential_data: Some(nonconfidential_data),
             },
         ciphertext: None,
+        plaintext,
     } = view
         .into_opened::<&[u8]>()
         .map_err(|_| crate::cesr::error::DecodeError::VidError)?
@@ -61,5 +74,29 @@ pub fn verify<'a>(
         return Err(CryptoError::MissingCiphertext);
     };
 
-    Ok(nonconfidential_data)
+    Ok(match plaintext {
+        Some(payload) => (Some(nonconfidential_data), payload),
+        None => (None, nonconfidential_data),
+    })
+}
+
+/// Verify the outer signature of a CESR encoded TSP message without decrypting it, and return
+/// its non-confidential data; only the public keys of the sender are needed for this
+pub fn verify_nonconfidential_data<'a>(
+    sender: &dyn VerifiedVid,
+    tsp_message: &'a [u8],
+) -> Result<Option<&'a [u8]>, CryptoError> {
+    let (DecodedEnvelope { envelope, .. }, verification_challenge) =
+        crate::cesr::decode_envelope::<&[u8]>(tsp_message)?;
+
+    if envelope.sender != sender.identifier().as_bytes() {
+        return Err(CryptoError::UnexpectedSender);
+    }
+
+    // verify outer signature, which also covers the non-confidential data
+    let signature = ed25519_dalek::Signature::from(verification_challenge.signature);
+    let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(sender.verifying_key())?;
+    verifying_key.verify_strict(verification_challenge.signed_data, &signature)?;
+
+    Ok(envelope.nonconfidential_data)
 }
@@ -0,0 +1,320 @@
+use crate::definitions::Digest;
+use hpke::{
+    aead::{AeadTag, ChaCha20Poly1305},
+    kdf::HkdfSha256,
+    kem::{Kem, X25519HkdfSha256},
+    Deserializable, OpModeR, OpModeS, Serializable,
+};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::collections::HashMap;
+
+use super::{digest::sha256_concat, CryptoError};
+
+type RatchetAead = ChaCha20Poly1305;
+type RatchetKdf = HkdfSha256;
+type RatchetKem = X25519HkdfSha256;
+
+/// The maximum number of keys that are kept for messages that were received out of order
+const MAX_SKIPPED_KEYS: usize = 100;
+
+/// The ratchet of a relationship, from its proposal in a relationship request onwards
+#[derive(Clone)]
+pub(crate) enum RatchetState {
+    /// We proposed a ratchet, holding the ephemeral private key of the proposal
+    Proposed(<RatchetKem as Kem>::PrivateKey),
+    /// The other party proposed a ratchet with this ephemeral public key; it is set up
+    /// once we accept the relationship
+    Offered([u8; 32]),
+    /// Both parties have set up the ratchet
+    Established(Ratchet),
+    /// A ratchet was negotiated, but its state was lost because it is not exported; the
+    /// relationship has to be formed again to set up a new ratchet
+    Lost,
+}
+
+impl RatchetState {
+    /// Propose a ratchet; returns the ephemeral public key to include in the relationship request
+    pub(crate) fn propose() -> (Self, [u8; 32]) {
+        let (private_key, public_key) = RatchetKem::gen_keypair(&mut StdRng::from_entropy());
+
+        let mut ratchet_key = [0; 32];
+        ratchet_key.copy_from_slice(&public_key.to_bytes());
+
+        (RatchetState::Proposed(private_key), ratchet_key)
+    }
+}
+
+/// A chain of keys: every key is derived from the previous chain key, which is discarded
+#[derive(Clone)]
+struct Chain {
+    key: Digest,
+    index: u64,
+}
+
+impl Chain {
+    fn new(label: &[u8], root: &[u8], thread_id: &Digest) -> Self {
+        Chain {
+            key: sha256_concat(&[label, root, thread_id]),
+            index: 0,
+        }
+    }
+
+    /// Derive the key of the next message, and advance the chain
+    fn next(&mut self) -> (u64, Digest) {
+        let message_key = sha256_concat(&[b"message", &self.key]);
+        self.key = sha256_concat(&[b"chain", &self.key]);
+
+        let index = self.index;
+        self.index += 1;
+
+        (index, message_key)
+    }
+}
+
+/// A symmetric-key ratchet: every message in a relationship is encrypted with its own key.
+///
+/// The keys are derived from a secret that was exchanged using ephemeral keys when the
+/// relationship was formed, and the state only holds the keys of future messages (and of
+/// messages that have not arrived yet); so compromising the VIDs or the current state of
+/// the ratchet does not reveal messages that were received earlier.
+#[derive(Clone)]
+pub(crate) struct Ratchet {
+    sending: Chain,
+    receiving: Chain,
+    skipped: HashMap<u64, Digest>,
+}
+
+impl Ratchet {
+    fn new(root: &[u8], thread_id: &Digest, initiator: bool) -> Self {
+        let initiating = Chain::new(b"TSP ratchet initiator", root, thread_id);
+        let responding = Chain::new(b"TSP ratchet responder", root, thread_id);
+
+        let (sending, receiving) = if initiator {
+            (initiating, responding)
+        } else {
+            (responding, initiating)
+        };
+
+        Ratchet {
+            sending,
+            receiving,
+            skipped: HashMap::new(),
+        }
+    }
+
+    /// Accept the ratchet proposed with `ratchet_key` for the relationship `thread_id`; returns
+    /// the ratchet and its initial secret, encrypted for the proposer
+    pub(crate) fn accept(
+        ratchet_key: &[u8; 32],
+        thread_id: &Digest,
+    ) -> Result<(Self, Vec<u8>), CryptoError> {
+        let mut csprng = StdRng::from_entropy();
+
+        let mut root = [0; 32];
+        csprng.fill_bytes(&mut root);
+
+        let ratchet_key = <RatchetKem as Kem>::PublicKey::from_bytes(ratchet_key)?;
+        let mut ratchet_secret = root.to_vec();
+        let (encapped_key, tag) = hpke::single_shot_seal_in_place_detached::<
+            RatchetAead,
+            RatchetKdf,
+            RatchetKem,
+            StdRng,
+        >(
+            &OpModeS::Base,
+            &ratchet_key,
+            thread_id,
+            &mut ratchet_secret,
+            &[],
+            &mut csprng,
+        )?;
+
+        ratchet_secret.extend(tag.to_bytes());
+        ratchet_secret.extend(encapped_key.to_bytes());
+
+        Ok((Self::new(&root, thread_id, false), ratchet_secret))
+    }
+
+    /// Set up the ratchet we proposed using `private_key`, with the initial secret
+    /// that was sent along with the acceptance of the relationship `thread_id`
+    pub(crate) fn complete(
+        private_key: &<RatchetKem as Kem>::PrivateKey,
+        ratchet_secret: &[u8],
+        thread_id: &Digest,
+    ) -> Result<Self, CryptoError> {
+        let mut root = ratchet_secret.to_vec();
+        let (root, tag, encapped_key) = split_sealed(&mut root)?;
+
+        hpke::single_shot_open_in_place_detached::<RatchetAead, RatchetKdf, RatchetKem>(
+            &OpModeR::Base,
+            private_key,
+            &encapped_key,
+            thread_id,
+            root,
+            &[],
+            &tag,
+        )?;
+
+        Ok(Self::new(root, thread_id, true))
+    }
+
+    /// Encrypt `message` with the next sending key; returns the index of the key and the ciphertext
+    pub(crate) fn seal(&mut self, message: &[u8]) -> Result<(u64, Vec<u8>), CryptoError> {
+        let (index, message_key) = self.sending.next();
+        let (_, public_key) = RatchetKem::derive_keypair(&message_key);
+
+        let mut data = Vec::with_capacity(
+            message.len()
+                + AeadTag::<RatchetAead>::size()
+                + <RatchetKem as Kem>::EncappedKey::size(),
+        );
+        data.extend_from_slice(message);
+
+        let (encapped_key, tag) = hpke::single_shot_seal_in_place_detached::<
+            RatchetAead,
+            RatchetKdf,
+            RatchetKem,
+            StdRng,
+        >(
+            &OpModeS::Base,
+            &public_key,
+            &index.to_be_bytes(),
+            &mut data,
+            &[],
+            &mut StdRng::from_entropy(),
+        )?;
+
+        data.extend(tag.to_bytes());
+        data.extend(encapped_key.to_bytes());
+
+        Ok((index, data))
+    }
+
+    /// Decrypt `data` in place with the receiving key number `index`; the key is discarded
+    /// once the message is decrypted
+    pub(crate) fn open<'a>(
+        &mut self,
+        index: u64,
+        data: &'a mut [u8],
+    ) -> Result<&'a mut [u8], CryptoError> {
+        // only update the state if the message can be decrypted
+        let mut next = self.clone();
+        let message_key = next.receiving_key(index)?;
+        let (private_key, _) = RatchetKem::derive_keypair(&message_key);
+
+        let (plaintext, tag, encapped_key) = split_sealed(data)?;
+        hpke::single_shot_open_in_place_detached::<RatchetAead, RatchetKdf, RatchetKem>(
+            &OpModeR::Base,
+            &private_key,
+            &encapped_key,
+            &index.to_be_bytes(),
+            plaintext,
+            &[],
+            &tag,
+        )?;
+
+        *self = next;
+
+        Ok(plaintext)
+    }
+
+    /// Find the receiving key number `index`, remembering the keys of messages that are skipped
+    fn receiving_key(&mut self, index: u64) -> Result<Digest, CryptoError> {
+        if index < self.receiving.index {
+            return self
+                .skipped
+                .remove(&index)
+                .ok_or(CryptoError::MissingRatchetKey(index));
+        }
+
+        if index - self.receiving.index > MAX_SKIPPED_KEYS as u64 {
+            return Err(CryptoError::MissingRatchetKey(index));
+        }
+
+        while self.receiving.index < index {
+            let (skipped_index, skipped_key) = self.receiving.next();
+            self.skipped.insert(skipped_index, skipped_key);
+        }
+
+        // forget the oldest keys, which are unlikely to be used anymore
+        while self.skipped.len() > MAX_SKIPPED_KEYS {
+            if let Some(&oldest) = self.skipped.keys().min() {
+                self.skipped.remove(&oldest);
+            }
+        }
+
+        Ok(self.receiving.next().1)
+    }
+}
+
+/// The ciphertext, authentication tag and encapsulated key of data sealed by the ratchet
+type Sealed<'a> = (
+    &'a mut [u8],
+    AeadTag<RatchetAead>,
+    <RatchetKem as Kem>::EncappedKey,
+);
+
+/// Split data that was sealed by the ratchet into its ciphertext, tag and encapsulated key
+fn split_sealed(data: &mut [u8]) -> Result<Sealed<'_>, CryptoError> {
+    let footer_len = AeadTag::<RatchetAead>::size() + <RatchetKem as Kem>::EncappedKey::size();
+    if data.len() < footer_len {
+        return Err(crate::cesr::error::DecodeError::UnexpectedData.into());
+    }
+
+    let (ciphertext, footer) = data.split_at_mut(data.len() - footer_len);
+    let (tag, encapped_key) = footer.split_at(AeadTag::<RatchetAead>::size());
+
+    Ok((
+        ciphertext,
+        AeadTag::from_bytes(tag)?,
+        <RatchetKem as Kem>::EncappedKey::from_bytes(encapped_key)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratchets() -> (Ratchet, Ratchet) {
+        let thread_id = [7; 32];
+        let (RatchetState::Proposed(private_key), ratchet_key) = RatchetState::propose() else {
+            unreachable!()
+        };
+
+        let (responder, ratchet_secret) = Ratchet::accept(&ratchet_key, &thread_id).unwrap();
+        let initiator = Ratchet::complete(&private_key, &ratchet_secret, &thread_id).unwrap();
+
+        (initiator, responder)
+    }
+
+    #[test]
+    fn ratchet_round_trip() {
+        let (mut alice, mut bob) = ratchets();
+
+        for message in [&b"hello"[..], b"world"] {
+            let (index, mut sealed) = alice.seal(message).unwrap();
+            assert_eq!(bob.open(index, &mut sealed).unwrap(), message);
+
+            let (index, mut sealed) = bob.seal(message).unwrap();
+            assert_eq!(alice.open(index, &mut sealed).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn ratchet_out_of_order() {
+        let (mut alice, mut bob) = ratchets();
+
+        let (first, mut first_sealed) = alice.seal(b"first").unwrap();
+        let (second, mut second_sealed) = alice.seal(b"second").unwrap();
+        let mut replayed = second_sealed.clone();
+
+        assert_eq!(bob.open(second, &mut second_sealed).unwrap(), b"second");
+        assert_eq!(bob.open(first, &mut first_sealed).unwrap(), b"first");
+
+        // keys are discarded after use
+        assert!(matches!(
+            bob.open(second, &mut replayed),
+            Err(CryptoError::MissingRatchetKey(1))
+        ));
+    }
+}
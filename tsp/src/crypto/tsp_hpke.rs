@@ -3,12 +3,16 @@ use crate::{
     definitions::{NonConfidentialData, Payload, PrivateVid, TSPMessage, VerifiedVid},
 };
 use ed25519_dalek::Signer;
-use hpke::{aead::AeadTag, Deserializable, OpModeR, OpModeS, Serializable};
+use hpke::{aead::AeadTag, Deserializable, OpModeR, OpModeS, PskBundle, Serializable};
 use rand::{rngs::StdRng, SeedableRng};
+use std::ops::Range;
 
-use super::{CryptoError, MessageContents};
+use super::{
+    hybrid::{HybridKem, PSK_ID},
+    CryptoError, MessageContents,
+};
 
-pub(crate) fn seal<A, Kdf, Kem>(
+pub(crate) fn seal<A, Kdf, Kem, Hybrid>(
     sender: &dyn PrivateVid,
     receiver: &dyn VerifiedVid,
     nonconfidential_data: Option<NonConfidentialData>,
@@ -19,86 +23,401 @@ where
     A: hpke::aead::Aead,
     Kdf: hpke::kdf::Kdf,
     Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
 {
-    let mut csprng = StdRng::from_entropy();
+    let mut data = Vec::new();
+    seal_into::<A, Kdf, Kem, Hybrid>(
+        sender,
+        receiver,
+        nonconfidential_data,
+        secret_payload,
+        plaintext_observer,
+        &mut data,
+    )?;
 
-    let mut data = Vec::with_capacity(64);
-    crate::cesr::encode_ets_envelope(
-        crate::cesr::Envelope {
-            sender: sender.identifier(),
-            receiver: Some(receiver.identifier()),
-            nonconfidential_data,
-        },
+    Ok(data)
+}
+
+/// Seal a message and append it to `output`; if `output` already has enough capacity,
+/// this does not allocate (unless the payload has to be compressed)
+pub(crate) fn seal_into<A, Kdf, Kem, Hybrid>(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    secret_payload: Payload<&[u8]>,
+    plaintext_observer: Option<super::ObservingClosure>,
+    output: &mut Vec<u8>,
+) -> Result<(), CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    seal_payload_into::<A, Kdf, Kem, Hybrid>(
+        sender,
+        receiver,
+        nonconfidential_data,
+        secret_payload,
+        plaintext_observer,
+        false,
+        output,
+    )
+}
+
+/// Seal a message with a hidden sender: the envelope only contains the receiver, the
+/// sender and its signature are encrypted together with the payload
+pub(crate) fn seal_hidden_sender<A, Kdf, Kem, Hybrid>(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    secret_payload: Payload<&[u8]>,
+    plaintext_observer: Option<super::ObservingClosure>,
+) -> Result<TSPMessage, CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    let mut data = Vec::new();
+    seal_payload_into::<A, Kdf, Kem, Hybrid>(
+        sender,
+        receiver,
+        nonconfidential_data,
+        secret_payload,
+        plaintext_observer,
+        true,
         &mut data,
     )?;
 
+    Ok(data)
+}
+
+fn seal_payload_into<A, Kdf, Kem, Hybrid>(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    secret_payload: Payload<&[u8]>,
+    plaintext_observer: Option<super::ObservingClosure>,
+    hidden_sender: bool,
+    output: &mut Vec<u8>,
+) -> Result<(), CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    let mut csprng = StdRng::from_entropy();
+
+    let compressed_data;
     let secret_payload = match secret_payload {
         Payload::Content(data) => crate::cesr::Payload::GenericMessage(data),
-        Payload::RequestRelationship { route } => crate::cesr::Payload::DirectRelationProposal {
+        Payload::CompressedContent(data) => {
+            compressed_data = crate::compression::compress(data);
+            crate::cesr::Payload::CompressedMessage(compressed_data.as_slice())
+        }
+        Payload::FreshContent {
+            ref message_id,
+            timestamp,
+            data,
+        } => crate::cesr::Payload::FreshMessage {
+            message_id,
+            timestamp,
+            data,
+        },
+        Payload::AcknowledgedContent(data) => crate::cesr::Payload::AcknowledgedMessage {
             nonce: fresh_nonce(&mut csprng),
-            hops: route.unwrap_or_else(Vec::new),
+            data,
         },
-        Payload::AcceptRelationship { ref thread_id } => {
-            crate::cesr::Payload::DirectRelationAffirm { reply: thread_id }
+        Payload::ThreadedContent {
+            ref thread_id,
+            ref reply_to,
+            data,
+        } => crate::cesr::Payload::ThreadedMessage {
+            nonce: fresh_nonce(&mut csprng),
+            thread_id,
+            reply_to: reply_to.as_ref(),
+            data,
+        },
+        Payload::RatchetedContent { index, data } => {
+            crate::cesr::Payload::RatchetedMessage { index, data }
         }
+        Payload::RequestRelationship { route, ratchet_key } => {
+            crate::cesr::Payload::DirectRelationProposal {
+                nonce: fresh_nonce(&mut csprng),
+                hops: route.unwrap_or_else(Vec::new),
+                ratchet_key,
+            }
+        }
+        Payload::AcceptRelationship {
+            ref thread_id,
+            ratchet_secret,
+        } => crate::cesr::Payload::DirectRelationAffirm {
+            reply: thread_id,
+            ratchet_secret,
+        },
+        Payload::RejectRelationship { ref thread_id } => crate::cesr::Payload::RelationshipReject {
+            nonce: fresh_nonce(&mut csprng),
+            reply: thread_id,
+        },
         Payload::CancelRelationship { ref thread_id } => crate::cesr::Payload::RelationshipCancel {
             nonce: fresh_nonce(&mut csprng),
             reply: thread_id,
         },
+        Payload::Receipt { ref message_digest } => crate::cesr::Payload::Receipt {
+            reply: message_digest,
+        },
         Payload::NestedMessage(data) => crate::cesr::Payload::NestedMessage(data),
         Payload::RoutedMessage(hops, data) => crate::cesr::Payload::RoutedMessage(hops, data),
+        Payload::StreamChunk {
+            ref stream_id,
+            index,
+            last,
+            data,
+        } => crate::cesr::Payload::StreamChunk {
+            stream_id,
+            index,
+            last,
+            data,
+        },
+    };
+
+    let envelope = crate::cesr::Envelope {
+        sender: sender.identifier(),
+        receiver: Some(receiver.identifier()),
+        nonconfidential_data,
+    };
+
+    // with a hidden sender, the sender and the signature are part of the plaintext
+    let (header_size, plaintext_size, signature_size) = if hidden_sender {
+        let sender_size = crate::cesr::hidden_sender_size(sender.identifier());
+
+        (
+            envelope.estimate_size() - sender_size,
+            sender_size + secret_payload.estimate_size() + crate::cesr::SIGNATURE_SIZE,
+            0,
+        )
+    } else {
+        (
+            envelope.estimate_size(),
+            secret_payload.estimate_size(),
+            crate::cesr::SIGNATURE_SIZE,
+        )
     };
 
-    // prepare CESR-encoded ciphertext
-    let mut cesr_message = Vec::with_capacity(
+    let ciphertext_size =
         // plaintext size
-        secret_payload.estimate_size()
+        plaintext_size
         // authenticated encryption tag length
         + AeadTag::<A>::size()
         // encapsulated key length
-        + Kem::EncappedKey::size(),
-    );
-    crate::cesr::encode_payload(secret_payload, &mut cesr_message)?;
+        + Kem::EncappedKey::size()
+        // ciphertext length of a hybrid KEM
+        + Hybrid::CIPHERTEXT_SIZE;
+
+    // make sure the entire message fits in the output buffer
+    output.reserve(header_size + crate::cesr::ciphertext_size(ciphertext_size) + signature_size);
+
+    let start = output.len();
+    let result = if hidden_sender {
+        seal_hidden_sender_in_place::<A, Kdf, Kem, Hybrid>(
+            sender,
+            receiver,
+            envelope,
+            secret_payload,
+            ciphertext_size,
+            plaintext_observer,
+            output,
+            &mut csprng,
+        )
+    } else {
+        seal_in_place::<A, Kdf, Kem, Hybrid>(
+            sender,
+            receiver,
+            envelope,
+            secret_payload,
+            ciphertext_size,
+            plaintext_observer,
+            output,
+            &mut csprng,
+        )
+    };
+
+    // do not leave a partial message in the output buffer
+    if result.is_err() {
+        output.truncate(start);
+    }
+
+    result
+}
 
-    // HPKE sender mode: "Auth"
-    let sender_decryption_key = Kem::PrivateKey::from_bytes(sender.decryption_key())?;
-    let sender_encryption_key = Kem::PublicKey::from_bytes(sender.encryption_key())?;
-    let mode = OpModeS::Auth((&sender_decryption_key, &sender_encryption_key));
+/// Encode the envelope and payload of a message in `output`, encrypt the payload in place
+/// and sign the message
+#[allow(clippy::too_many_arguments)]
+fn seal_in_place<A, Kdf, Kem, Hybrid>(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    envelope: crate::cesr::Envelope<&str>,
+    secret_payload: crate::cesr::Payload<&[u8], &[u8]>,
+    ciphertext_size: usize,
+    plaintext_observer: Option<super::ObservingClosure>,
+    output: &mut Vec<u8>,
+    csprng: &mut StdRng,
+) -> Result<(), CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    let start = output.len();
+    crate::cesr::encode_ets_envelope(envelope, output)?;
+    let header_end = output.len();
+
+    // the plaintext is encoded directly after the ciphertext header, and encrypted in place
+    crate::cesr::encode_ciphertext_header(receiver.cipher_suite(), ciphertext_size, output)?;
+    let plaintext_start = output.len();
+    crate::cesr::encode_payload(secret_payload, output)?;
+
+    // HPKE sender mode: "Auth", or "AuthPSK" with the shared secret of a hybrid KEM
+    let sender_decryption_key = Hybrid::private_key::<Kem>(sender.decryption_key())?;
+    let (sender_encryption_key, _) = Hybrid::public_key::<Kem>(sender.encryption_key())?;
 
     // recipient public key
-    let message_receiver = Kem::PublicKey::from_bytes(receiver.encryption_key())?;
+    let (message_receiver, hybrid_receiver) = Hybrid::public_key::<Kem>(receiver.encryption_key())?;
+    let (hybrid_ciphertext, psk) = Hybrid::encapsulate(hybrid_receiver, csprng)?.unzip();
+
+    let mode = match &psk {
+        Some(psk) => OpModeS::AuthPsk(
+            (&sender_decryption_key, &sender_encryption_key),
+            PskBundle {
+                psk,
+                psk_id: PSK_ID,
+            },
+        ),
+        None => OpModeS::Auth((&sender_decryption_key, &sender_encryption_key)),
+    };
+
+    let (header, plaintext) = output.split_at_mut(plaintext_start);
 
     // this callback allows "observing" the raw bytes of the plaintext before encryption, for hash computations
     if let Some(func) = plaintext_observer {
-        func(&cesr_message);
+        func(plaintext);
     }
 
     // perform encryption
     let (encapped_key, tag) = hpke::single_shot_seal_in_place_detached::<A, Kdf, Kem, StdRng>(
         &mode,
         &message_receiver,
-        &data,
-        &mut cesr_message,
+        &header[start..header_end],
+        plaintext,
         &[],
-        &mut csprng,
+        csprng,
     )?;
 
-    // append the authentication tag and encapsulated key to the end of the ciphertext
-    cesr_message.extend(tag.to_bytes());
-    cesr_message.extend(encapped_key.to_bytes());
+    // append the authentication tag and encapsulated key(s) to the end of the ciphertext
+    output.extend(tag.to_bytes());
+    output.extend(encapped_key.to_bytes());
+    output.extend(hybrid_ciphertext.unwrap_or_default());
 
-    // encode and append the ciphertext to the envelope data
-    crate::cesr::encode_ciphertext(&cesr_message, &mut data)?;
+    // the ciphertext header was encoded with the estimated size of the ciphertext
+    if output.len() - plaintext_start != ciphertext_size {
+        return Err(CryptoError::CiphertextSize);
+    }
 
     // create and append outer signature
     let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key());
-    let signature = sign_key.sign(&data).to_bytes();
-    crate::cesr::encode_signature(&signature, &mut data);
+    let signature = sign_key.sign(&output[start..]).to_bytes();
+    crate::cesr::encode_signature(&signature, output);
 
-    Ok(data)
+    Ok(())
 }
 
-pub(crate) fn open<'a, A, Kdf, Kem>(
+/// Encode the envelope and the plaintext of a message with a hidden sender in `output`:
+/// the plaintext consists of the sender, the payload and a signature over the envelope,
+/// the sender and the payload; it is encrypted in place using HPKE "Base" mode, so that
+/// the sender is not revealed by its keys either
+#[allow(clippy::too_many_arguments)]
+fn seal_hidden_sender_in_place<A, Kdf, Kem, Hybrid>(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    envelope: crate::cesr::Envelope<&str>,
+    secret_payload: crate::cesr::Payload<&[u8], &[u8]>,
+    ciphertext_size: usize,
+    plaintext_observer: Option<super::ObservingClosure>,
+    output: &mut Vec<u8>,
+    csprng: &mut StdRng,
+) -> Result<(), CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    let start = output.len();
+    crate::cesr::encode_hidden_sender_envelope(
+        receiver.identifier(),
+        envelope.nonconfidential_data,
+        output,
+    )?;
+    let header_end = output.len();
+
+    crate::cesr::encode_ciphertext_header(receiver.cipher_suite(), ciphertext_size, output)?;
+    let plaintext_start = output.len();
+    crate::cesr::encode_hidden_sender(envelope.sender, output)?;
+    let payload_start = output.len();
+    crate::cesr::encode_payload(secret_payload, output)?;
+
+    // only the payload is observed, like for a message with a visible sender
+    if let Some(func) = plaintext_observer {
+        func(&output[payload_start..]);
+    }
+
+    // the signature covers everything that precedes it, and is encrypted as well
+    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key());
+    let signature = sign_key.sign(&output[start..]).to_bytes();
+    crate::cesr::encode_signature(&signature, output);
+
+    // HPKE sender mode: "Base", or "PSK" with the shared secret of a hybrid KEM
+    let (message_receiver, hybrid_receiver) = Hybrid::public_key::<Kem>(receiver.encryption_key())?;
+    let (hybrid_ciphertext, psk) = Hybrid::encapsulate(hybrid_receiver, csprng)?.unzip();
+
+    let mode = match &psk {
+        Some(psk) => OpModeS::Psk(PskBundle {
+            psk,
+            psk_id: PSK_ID,
+        }),
+        None => OpModeS::Base,
+    };
+
+    let (header, plaintext) = output.split_at_mut(plaintext_start);
+
+    // perform encryption
+    let (encapped_key, tag) = hpke::single_shot_seal_in_place_detached::<A, Kdf, Kem, StdRng>(
+        &mode,
+        &message_receiver,
+        &header[start..header_end],
+        plaintext,
+        &[],
+        csprng,
+    )?;
+
+    // append the authentication tag and encapsulated key(s) to the end of the ciphertext
+    output.extend(tag.to_bytes());
+    output.extend(encapped_key.to_bytes());
+    output.extend(hybrid_ciphertext.unwrap_or_default());
+
+    // the ciphertext header was encoded with the estimated size of the ciphertext
+    if output.len() - plaintext_start != ciphertext_size {
+        return Err(CryptoError::CiphertextSize);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn open<'a, A, Kdf, Kem, Hybrid>(
     receiver: &dyn PrivateVid,
     sender: &dyn VerifiedVid,
     tsp_message: &'a mut [u8],
@@ -107,6 +426,7 @@ where
     A: hpke::aead::Aead,
     Kdf: hpke::kdf::Kdf,
     Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
 {
     let view = crate::cesr::decode_envelope_mut(tsp_message)?;
 
@@ -116,11 +436,20 @@ where
     let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(sender.verifying_key())?;
     verifying_key.verify_strict(verification_challange.signed_data, &signature)?;
 
+    // the message has to be encrypted using the cipher suite of the receiver
+    if let Some(cipher_suite) = view
+        .cipher_suite()
+        .filter(|cipher_suite| *cipher_suite != receiver.cipher_suite())
+    {
+        return Err(CryptoError::UnsupportedCipherSuite(cipher_suite));
+    }
+
     // decode envelope
     let DecodedEnvelope {
         raw_header: info,
         envelope,
         ciphertext: Some(ciphertext),
+        ..
     } = view
         .into_opened::<&[u8]>()
         .map_err(|_| crate::cesr::error::DecodeError::VidError)?
@@ -133,20 +462,37 @@ where
         return Err(CryptoError::UnexpectedRecipient);
     }
 
-    // split encapsulated key and authenticated encryption tag length
-    let (ciphertext, footer) =
-        ciphertext.split_at_mut(ciphertext.len() - AeadTag::<A>::size() - Kem::EncappedKey::size());
-    let (tag, encapped_key) = footer.split_at(footer.len() - Kem::EncappedKey::size());
+    // split encapsulated key(s) and authenticated encryption tag length
+    let footer_len = AeadTag::<A>::size() + Kem::EncappedKey::size() + Hybrid::CIPHERTEXT_SIZE;
+    if ciphertext.len() < footer_len {
+        return Err(crate::cesr::error::DecodeError::UnexpectedData.into());
+    }
+    let (ciphertext, footer) = ciphertext.split_at_mut(ciphertext.len() - footer_len);
+    let (tag, encapped_key) = footer.split_at(AeadTag::<A>::size());
+    let (encapped_key, hybrid_ciphertext) = encapped_key.split_at(Kem::EncappedKey::size());
 
     // construct correct key types
-    let sender_encryption_key = Kem::PublicKey::from_bytes(sender.encryption_key())?;
-    let receiver_decryption_key = Kem::PrivateKey::from_bytes(receiver.decryption_key())?;
+    let (sender_encryption_key, _) = Hybrid::public_key::<Kem>(sender.encryption_key())?;
+    let receiver_decryption_key = Hybrid::private_key::<Kem>(receiver.decryption_key())?;
     let encapped_key = Kem::EncappedKey::from_bytes(encapped_key)?;
     let tag = AeadTag::from_bytes(tag)?;
 
+    // HPKE receiver mode: "Auth", or "AuthPSK" with the shared secret of a hybrid KEM
+    let psk = Hybrid::decapsulate(receiver.decryption_key(), hybrid_ciphertext)?;
+    let mode = match &psk {
+        Some(psk) => OpModeR::AuthPsk(
+            &sender_encryption_key,
+            PskBundle {
+                psk,
+                psk_id: PSK_ID,
+            },
+        ),
+        None => OpModeR::Auth(&sender_encryption_key),
+    };
+
     // decrypt the ciphertext
     hpke::single_shot_open_in_place_detached::<A, Kdf, Kem>(
-        &OpModeR::Auth(&sender_encryption_key),
+        &mode,
         &receiver_decryption_key,
         &encapped_key,
         info,
@@ -155,30 +501,428 @@ where
         &tag,
     )?;
 
-    let secret_payload = match crate::cesr::decode_payload(ciphertext)? {
+    let (secret_payload, raw_bytes) = decode_secret_payload(ciphertext)?;
+
+    Ok((envelope.nonconfidential_data, secret_payload, raw_bytes))
+}
+
+/// Decode a decrypted payload; also returns the raw bytes that precede its message data
+fn decode_secret_payload(plaintext: &mut [u8]) -> Result<(Payload<&mut [u8]>, &[u8]), CryptoError> {
+    let (secret_payload, raw_bytes) = crate::cesr::decode_payload_mut(plaintext)?;
+
+    let secret_payload = match secret_payload {
         crate::cesr::Payload::GenericMessage(data) => Payload::Content(data),
-        crate::cesr::Payload::DirectRelationProposal { hops, .. } => Payload::RequestRelationship {
+        crate::cesr::Payload::CompressedMessage(data) => Payload::CompressedContent(data),
+        crate::cesr::Payload::FreshMessage {
+            message_id: &message_id,
+            timestamp,
+            data,
+        } => Payload::FreshContent {
+            message_id,
+            timestamp,
+            data,
+        },
+        crate::cesr::Payload::AcknowledgedMessage { data, .. } => {
+            Payload::AcknowledgedContent(data)
+        }
+        crate::cesr::Payload::ThreadedMessage {
+            thread_id: &thread_id,
+            reply_to,
+            data,
+            ..
+        } => Payload::ThreadedContent {
+            thread_id,
+            reply_to: reply_to.copied(),
+            data,
+        },
+        crate::cesr::Payload::RatchetedMessage { index, data } => {
+            Payload::RatchetedContent { index, data }
+        }
+        crate::cesr::Payload::DirectRelationProposal {
+            hops, ratchet_key, ..
+        } => Payload::RequestRelationship {
             route: if hops.is_empty() {
                 None
             } else {
                 Some(hops.to_vec())
             },
+            ratchet_key,
+        },
+        crate::cesr::Payload::DirectRelationAffirm {
+            reply: &thread_id,
+            ratchet_secret,
+        } => Payload::AcceptRelationship {
+            thread_id,
+            ratchet_secret,
         },
-        crate::cesr::Payload::DirectRelationAffirm { reply: &thread_id } => {
-            Payload::AcceptRelationship { thread_id }
+        // nested relationships are not negotiated using these messages (yet)
+        crate::cesr::Payload::NestedRelationProposal { .. }
+        | crate::cesr::Payload::NestedRelationAffirm { .. } => {
+            return Err(crate::cesr::error::DecodeError::UnexpectedMsgType.into())
         }
-        crate::cesr::Payload::NestedRelationProposal { .. } => todo!(),
-        crate::cesr::Payload::NestedRelationAffirm { .. } => todo!(),
+        crate::cesr::Payload::RelationshipReject {
+            reply: &thread_id, ..
+        } => Payload::RejectRelationship { thread_id },
         crate::cesr::Payload::RelationshipCancel {
             reply: &thread_id, ..
         } => Payload::CancelRelationship { thread_id },
+        crate::cesr::Payload::Receipt {
+            reply: &message_digest,
+        } => Payload::Receipt { message_digest },
         crate::cesr::Payload::NestedMessage(data) => Payload::NestedMessage(data),
         crate::cesr::Payload::RoutedMessage(hops, data) => {
             Payload::RoutedMessage(hops.to_vec(), data)
         }
+        crate::cesr::Payload::StreamChunk {
+            stream_id: &stream_id,
+            index,
+            last,
+            data,
+        } => Payload::StreamChunk {
+            stream_id,
+            index,
+            last,
+            data,
+        },
+    };
+
+    Ok((secret_payload, raw_bytes))
+}
+
+pub(crate) fn open_hidden_sender<'a, A, Kdf, Kem, Hybrid>(
+    receiver: &dyn PrivateVid,
+    tsp_message: &'a mut [u8],
+) -> Result<HiddenSenderMessage<'a>, CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    let envelope = crate::cesr::decode_hidden_sender_envelope(tsp_message)?;
+
+    // the message has to be encrypted using the cipher suite of the receiver
+    if envelope.cipher_suite != receiver.cipher_suite() {
+        return Err(CryptoError::UnsupportedCipherSuite(envelope.cipher_suite));
+    }
+
+    // verify the message was intended for the specified receiver
+    if tsp_message[envelope.receiver.clone()] != *receiver.identifier().as_bytes() {
+        return Err(CryptoError::UnexpectedRecipient);
+    }
+
+    let (header, ciphertext) = tsp_message.split_at_mut(envelope.ciphertext.start);
+    let ciphertext = &mut ciphertext[..envelope.ciphertext.len()];
+
+    // split encapsulated key(s) and authenticated encryption tag length
+    let footer_len = AeadTag::<A>::size() + Kem::EncappedKey::size() + Hybrid::CIPHERTEXT_SIZE;
+    if ciphertext.len() < footer_len {
+        return Err(crate::cesr::error::DecodeError::UnexpectedData.into());
+    }
+    let plaintext_len = ciphertext.len() - footer_len;
+    let (plaintext, footer) = ciphertext.split_at_mut(plaintext_len);
+    let (tag, encapped_key) = footer.split_at(AeadTag::<A>::size());
+    let (encapped_key, hybrid_ciphertext) = encapped_key.split_at(Kem::EncappedKey::size());
+
+    // HPKE receiver mode: "Base", or "PSK" with the shared secret of a hybrid KEM
+    let psk = Hybrid::decapsulate(receiver.decryption_key(), hybrid_ciphertext)?;
+    let mode = match &psk {
+        Some(psk) => OpModeR::Psk(PskBundle {
+            psk,
+            psk_id: PSK_ID,
+        }),
+        None => OpModeR::Base,
+    };
+
+    // decrypt the ciphertext
+    hpke::single_shot_open_in_place_detached::<A, Kdf, Kem>(
+        &mode,
+        &Hybrid::private_key::<Kem>(receiver.decryption_key())?,
+        &Kem::EncappedKey::from_bytes(encapped_key)?,
+        &header[envelope.associated_data],
+        plaintext,
+        &[],
+        &AeadTag::from_bytes(tag)?,
+    )?;
+
+    let (sender, payload, signature) = crate::cesr::decode_hidden_sender(plaintext)?;
+
+    // the ranges of the sender and payload are relative to the start of the message
+    let offset = envelope.ciphertext.start;
+
+    Ok(HiddenSenderMessage {
+        data: &mut tsp_message[..offset + payload.end],
+        sender: offset + sender.start..offset + sender.end,
+        nonconfidential_data: envelope.nonconfidential_data,
+        payload: offset + payload.start..offset + payload.end,
+        signature,
+    })
+}
+
+/// A decrypted message with a hidden sender, of which the signature has not been verified
+/// yet: the VID of the sender is only known after decryption, see [HiddenSenderMessage::sender]
+pub struct HiddenSenderMessage<'a> {
+    data: &'a mut [u8],
+    sender: Range<usize>,
+    nonconfidential_data: Option<Range<usize>>,
+    payload: Range<usize>,
+    signature: crate::cesr::Signature,
+}
+
+impl<'a> HiddenSenderMessage<'a> {
+    /// The VID of the (claimed) sender of this message
+    pub fn sender(&self) -> &[u8] {
+        &self.data[self.sender.clone()]
+    }
+
+    /// Verify the signature of the sender, and return the contents of the message
+    pub fn verify(self, sender: &dyn VerifiedVid) -> Result<MessageContents<'a>, CryptoError> {
+        if self.sender() != sender.identifier().as_bytes() {
+            return Err(CryptoError::UnexpectedSender);
+        }
+
+        // the signature covers the envelope, the sender and the payload
+        let signature = ed25519_dalek::Signature::from(&self.signature);
+        let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(sender.verifying_key())?;
+        verifying_key.verify_strict(self.data, &signature)?;
+
+        let (header, payload) = self.data.split_at_mut(self.payload.start);
+        let header: &'a [u8] = header;
+        let (secret_payload, raw_bytes) = decode_secret_payload(payload)?;
+
+        Ok((
+            self.nonconfidential_data.map(|range| &header[range]),
+            secret_payload,
+            raw_bytes,
+        ))
+    }
+}
+
+pub(crate) fn seal_multicast<A, Kdf, Kem, Hybrid>(
+    sender: &dyn PrivateVid,
+    receivers: &[&dyn VerifiedVid],
+    nonconfidential_data: Option<NonConfidentialData>,
+    secret_payload: &[u8],
+) -> Result<TSPMessage, CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    let mut csprng = StdRng::from_entropy();
+
+    // a multicast message has no receiver in its envelope
+    let mut data = Vec::with_capacity(64);
+    crate::cesr::encode_ets_envelope(
+        crate::cesr::Envelope {
+            sender: sender.identifier(),
+            receiver: None,
+            nonconfidential_data,
+        },
+        &mut data,
+    )?;
+
+    // the payload is encrypted once, using a fresh key pair for this message only
+    let (content_decryption_key, content_encryption_key) = Kem::gen_keypair(&mut csprng);
+
+    let mut cesr_message =
+        Vec::with_capacity(secret_payload.len() + AeadTag::<A>::size() + Kem::EncappedKey::size());
+    crate::cesr::encode_payload(
+        crate::cesr::Payload::<_, &[u8]>::GenericMessage(secret_payload),
+        &mut cesr_message,
+    )?;
+
+    let (encapped_key, tag) = hpke::single_shot_seal_in_place_detached::<A, Kdf, Kem, StdRng>(
+        &OpModeS::Base,
+        &content_encryption_key,
+        &data,
+        &mut cesr_message,
+        &[],
+        &mut csprng,
+    )?;
+
+    cesr_message.extend(tag.to_bytes());
+    cesr_message.extend(encapped_key.to_bytes());
+
+    // HPKE sender mode: "Auth", or "AuthPSK" with the shared secret of a hybrid KEM
+    let sender_decryption_key = Hybrid::private_key::<Kem>(sender.decryption_key())?;
+    let (sender_encryption_key, _) = Hybrid::public_key::<Kem>(sender.encryption_key())?;
+
+    // the private key of the payload is encrypted for every receiver individually
+    let mut recipients = Vec::with_capacity(receivers.len());
+    for receiver in receivers {
+        let (message_receiver, hybrid_receiver) =
+            Hybrid::public_key::<Kem>(receiver.encryption_key())?;
+        let (hybrid_ciphertext, psk) = Hybrid::encapsulate(hybrid_receiver, &mut csprng)?.unzip();
+
+        let mode = match &psk {
+            Some(psk) => OpModeS::AuthPsk(
+                (&sender_decryption_key, &sender_encryption_key),
+                PskBundle {
+                    psk,
+                    psk_id: PSK_ID,
+                },
+            ),
+            None => OpModeS::Auth((&sender_decryption_key, &sender_encryption_key)),
+        };
+
+        let mut wrapped_key = content_decryption_key.to_bytes().as_ref().to_vec();
+        let (encapped_key, tag) = hpke::single_shot_seal_in_place_detached::<A, Kdf, Kem, StdRng>(
+            &mode,
+            &message_receiver,
+            &data,
+            &mut wrapped_key,
+            receiver.identifier().as_bytes(),
+            &mut csprng,
+        )?;
+
+        wrapped_key.extend(tag.to_bytes());
+        wrapped_key.extend(encapped_key.to_bytes());
+        wrapped_key.extend(hybrid_ciphertext.unwrap_or_default());
+
+        recipients.push((receiver.identifier(), wrapped_key));
+    }
+
+    let mut ciphertext = Vec::with_capacity(cesr_message.len() + 128 * recipients.len());
+    crate::cesr::encode_recipients(&recipients, &mut ciphertext)?;
+    ciphertext.extend(cesr_message);
+
+    crate::cesr::encode_ciphertext(sender.cipher_suite(), &ciphertext, &mut data)?;
+
+    // create and append outer signature
+    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key());
+    let signature = sign_key.sign(&data).to_bytes();
+    crate::cesr::encode_signature(&signature, &mut data);
+
+    Ok(data)
+}
+
+pub(crate) fn open_multicast<'a, A, Kdf, Kem, Hybrid>(
+    receiver: &dyn PrivateVid,
+    sender: &dyn VerifiedVid,
+    tsp_message: &'a mut [u8],
+) -> Result<MessageContents<'a>, CryptoError>
+where
+    A: hpke::aead::Aead,
+    Kdf: hpke::kdf::Kdf,
+    Kem: hpke::kem::Kem,
+    Hybrid: HybridKem,
+{
+    let view = crate::cesr::decode_envelope_mut(tsp_message)?;
+
+    // verify outer signature
+    let verification_challange = view.as_challenge();
+    let signature = ed25519_dalek::Signature::from(verification_challange.signature);
+    let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(sender.verifying_key())?;
+    verifying_key.verify_strict(verification_challange.signed_data, &signature)?;
+
+    // the message has to be encrypted using the cipher suite of the receiver
+    if let Some(cipher_suite) = view
+        .cipher_suite()
+        .filter(|cipher_suite| *cipher_suite != receiver.cipher_suite())
+    {
+        return Err(CryptoError::UnsupportedCipherSuite(cipher_suite));
+    }
+
+    // decode envelope
+    let DecodedEnvelope {
+        raw_header: info,
+        envelope,
+        ciphertext: Some(ciphertext),
+        ..
+    } = view
+        .into_opened::<&[u8]>()
+        .map_err(|_| crate::cesr::error::DecodeError::VidError)?
+    else {
+        return Err(CryptoError::MissingCiphertext);
+    };
+
+    if envelope.receiver.is_some() {
+        return Err(CryptoError::UnexpectedRecipient);
+    }
+
+    // find the content key that was encrypted for us
+    let (mut wrapped_key, header_len) = {
+        let mut stream: &[u8] = ciphertext;
+        let recipients = crate::cesr::decode_recipients(&mut stream)?;
+
+        let Some((_, wrapped_key)) = recipients
+            .into_iter()
+            .find(|(vid, _)| *vid == receiver.identifier().as_bytes())
+        else {
+            return Err(CryptoError::UnexpectedRecipient);
+        };
+
+        (wrapped_key.to_vec(), ciphertext.len() - stream.len())
+    };
+    let ciphertext = &mut ciphertext[header_len..];
+
+    let (sender_encryption_key, _) = Hybrid::public_key::<Kem>(sender.encryption_key())?;
+    let receiver_decryption_key = Hybrid::private_key::<Kem>(receiver.decryption_key())?;
+    let footer_len = AeadTag::<A>::size() + Kem::EncappedKey::size();
+
+    let content_decryption_key = {
+        if wrapped_key.len() != Kem::PrivateKey::size() + footer_len + Hybrid::CIPHERTEXT_SIZE {
+            return Err(crate::cesr::error::DecodeError::UnexpectedData.into());
+        }
+        let (wrapped_key, footer) = wrapped_key.split_at_mut(Kem::PrivateKey::size());
+        let (tag, encapped_key) = footer.split_at(AeadTag::<A>::size());
+        let (encapped_key, hybrid_ciphertext) = encapped_key.split_at(Kem::EncappedKey::size());
+
+        let psk = Hybrid::decapsulate(receiver.decryption_key(), hybrid_ciphertext)?;
+        let mode = match &psk {
+            Some(psk) => OpModeR::AuthPsk(
+                &sender_encryption_key,
+                PskBundle {
+                    psk,
+                    psk_id: PSK_ID,
+                },
+            ),
+            None => OpModeR::Auth(&sender_encryption_key),
+        };
+
+        hpke::single_shot_open_in_place_detached::<A, Kdf, Kem>(
+            &mode,
+            &receiver_decryption_key,
+            &Kem::EncappedKey::from_bytes(encapped_key)?,
+            info,
+            wrapped_key,
+            receiver.identifier().as_bytes(),
+            &AeadTag::from_bytes(tag)?,
+        )?;
+
+        Kem::PrivateKey::from_bytes(wrapped_key)?
+    };
+
+    // split encapsulated key and authenticated encryption tag length
+    if ciphertext.len() < footer_len {
+        return Err(crate::cesr::error::DecodeError::UnexpectedData.into());
+    }
+    let (ciphertext, footer) = ciphertext.split_at_mut(ciphertext.len() - footer_len);
+    let (tag, encapped_key) = footer.split_at(AeadTag::<A>::size());
+
+    hpke::single_shot_open_in_place_detached::<A, Kdf, Kem>(
+        &OpModeR::Base,
+        &content_decryption_key,
+        &Kem::EncappedKey::from_bytes(encapped_key)?,
+        info,
+        ciphertext,
+        &[],
+        &AeadTag::from_bytes(tag)?,
+    )?;
+
+    let (secret_payload, raw_bytes) = crate::cesr::decode_payload_mut::<&[u8]>(ciphertext)?;
+
+    let crate::cesr::Payload::GenericMessage(data) = secret_payload else {
+        return Err(crate::cesr::error::DecodeError::UnexpectedMsgType.into());
     };
 
-    Ok((envelope.nonconfidential_data, secret_payload, ciphertext))
+    Ok((
+        envelope.nonconfidential_data,
+        Payload::Content(data),
+        raw_bytes,
+    ))
 }
 
 /// Generate N random bytes using the provided RNG
@@ -4,10 +4,14 @@ use std::fmt::Debug;
 #[cfg(feature = "async")]
 use futures::Stream;
 
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
 pub type KeyData = [u8; 32];
 pub type Digest = [u8; 32];
 pub type PrivateKeyData<'a> = &'a KeyData;
 pub type PublicKeyData<'a> = &'a KeyData;
+pub type EncryptionKeyData<'a> = &'a [u8];
 pub type VidData<'a> = &'a [u8];
 pub type NonConfidentialData<'a> = &'a [u8];
 pub type TSPMessage = Vec<u8>;
@@ -21,52 +25,311 @@ pub enum MessageType {
     SignedAndEncrypted,
 }
 
+/// The envelope of a received TSP message that contained a nested message
+#[derive(Debug)]
+pub struct ReceivedEnvelope<Bytes: AsRef<[u8]> = Vec<u8>> {
+    pub sender: String,
+    pub receiver: String,
+    pub nonconfidential_data: Option<Bytes>,
+}
+
+impl<Bytes: AsRef<[u8]>> ReceivedEnvelope<Bytes> {
+    /// Copy the contents of a (borrowed) envelope into an owned envelope
+    pub fn into_owned(self) -> ReceivedEnvelope {
+        ReceivedEnvelope {
+            sender: self.sender,
+            receiver: self.receiver,
+            nonconfidential_data: self.nonconfidential_data.map(|data| data.as_ref().to_vec()),
+        }
+    }
+}
+
+/// The place of a received generic message in a conversation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageThread {
+    /// Identifies the conversation
+    pub thread_id: Digest,
+    /// The digest of the message that this message replies to
+    pub reply_to: Option<Digest>,
+    /// The digest of this message, which a reply can refer to
+    pub message_digest: Digest,
+}
+
+/// A received and opened TSP message; by default this owns its contents,
+/// `ReceivedTspMessage<&[u8]>` borrows them from the buffer that was opened
 #[derive(Debug)]
-pub enum ReceivedTspMessage {
+pub enum ReceivedTspMessage<Bytes: AsRef<[u8]> = Vec<u8>> {
     GenericMessage {
         sender: String,
-        nonconfidential_data: Option<Vec<u8>>,
-        message: Vec<u8>,
+        receiver: Option<String>,
+        nonconfidential_data: Option<Bytes>,
+        message: Bytes,
         message_type: MessageType,
+        /// The envelopes this message was nested in, outermost first. Only generic messages
+        /// report their envelopes: other payloads are never sent nested by this library, and
+        /// are returned without their envelopes if another party nests them.
+        nested_in: Vec<ReceivedEnvelope<Bytes>>,
+        /// If the sender requested a receipt, the digest of the message to acknowledge
+        receipt_requested: Option<Digest>,
+        /// The conversation this message is part of, if any
+        thread: Option<MessageThread>,
+    },
+    /// A confidential message that was sent to a group of receivers, of which `receiver` is ours
+    MulticastMessage {
+        sender: String,
+        receiver: String,
+        nonconfidential_data: Option<Bytes>,
+        message: Bytes,
+    },
+    /// A generic message whose contents are still compressed, as returned by
+    /// [crate::Store::open_message_borrowed]; [crate::Store::open_message] decompresses the
+    /// contents and returns a [ReceivedTspMessage::GenericMessage] instead
+    CompressedMessage {
+        sender: String,
+        receiver: String,
+        nonconfidential_data: Option<Bytes>,
+        message: Bytes,
     },
     RequestRelationship {
         sender: String,
-        route: Option<Vec<Vec<u8>>>,
+        route: Option<Vec<Bytes>>,
         thread_id: Digest,
     },
     AcceptRelationship {
         sender: String,
     },
+    /// The relationship that we requested from `sender` was declined
+    RejectRelationship {
+        sender: String,
+    },
     CancelRelationship {
         sender: String,
     },
+    /// A confirmation that the message with digest `message_digest` was received by `sender`
+    Receipt {
+        sender: String,
+        message_digest: Digest,
+    },
     ForwardRequest {
         sender: String,
         next_hop: String,
-        route: Vec<Vec<u8>>,
-        opaque_payload: Vec<u8>,
+        route: Vec<Bytes>,
+        opaque_payload: Bytes,
+    },
+    /// A part of a streamed payload; see [crate::AsyncStore::receive_stream]
+    StreamChunk {
+        sender: String,
+        stream_id: Digest,
+        index: u64,
+        last: bool,
+        data: Bytes,
     },
 }
 
+impl<Bytes: AsRef<[u8]>> ReceivedTspMessage<Bytes> {
+    /// Copy the contents of a (borrowed) message into an owned message
+    pub fn into_owned(self) -> ReceivedTspMessage {
+        match self {
+            ReceivedTspMessage::GenericMessage {
+                sender,
+                receiver,
+                nonconfidential_data,
+                message,
+                message_type,
+                nested_in,
+                receipt_requested,
+                thread,
+            } => ReceivedTspMessage::GenericMessage {
+                sender,
+                receiver,
+                nonconfidential_data: nonconfidential_data.map(|data| data.as_ref().to_vec()),
+                message: message.as_ref().to_vec(),
+                message_type,
+                nested_in: nested_in
+                    .into_iter()
+                    .map(ReceivedEnvelope::into_owned)
+                    .collect(),
+                receipt_requested,
+                thread,
+            },
+            ReceivedTspMessage::MulticastMessage {
+                sender,
+                receiver,
+                nonconfidential_data,
+                message,
+            } => ReceivedTspMessage::MulticastMessage {
+                sender,
+                receiver,
+                nonconfidential_data: nonconfidential_data.map(|data| data.as_ref().to_vec()),
+                message: message.as_ref().to_vec(),
+            },
+            ReceivedTspMessage::CompressedMessage {
+                sender,
+                receiver,
+                nonconfidential_data,
+                message,
+            } => ReceivedTspMessage::CompressedMessage {
+                sender,
+                receiver,
+                nonconfidential_data: nonconfidential_data.map(|data| data.as_ref().to_vec()),
+                message: message.as_ref().to_vec(),
+            },
+            ReceivedTspMessage::RequestRelationship {
+                sender,
+                route,
+                thread_id,
+            } => ReceivedTspMessage::RequestRelationship {
+                sender,
+                route: route.map(|hops| hops.iter().map(|vid| vid.as_ref().to_vec()).collect()),
+                thread_id,
+            },
+            ReceivedTspMessage::AcceptRelationship { sender } => {
+                ReceivedTspMessage::AcceptRelationship { sender }
+            }
+            ReceivedTspMessage::RejectRelationship { sender } => {
+                ReceivedTspMessage::RejectRelationship { sender }
+            }
+            ReceivedTspMessage::CancelRelationship { sender } => {
+                ReceivedTspMessage::CancelRelationship { sender }
+            }
+            ReceivedTspMessage::Receipt {
+                sender,
+                message_digest,
+            } => ReceivedTspMessage::Receipt {
+                sender,
+                message_digest,
+            },
+            ReceivedTspMessage::ForwardRequest {
+                sender,
+                next_hop,
+                route,
+                opaque_payload,
+            } => ReceivedTspMessage::ForwardRequest {
+                sender,
+                next_hop,
+                route: route.iter().map(|vid| vid.as_ref().to_vec()).collect(),
+                opaque_payload: opaque_payload.as_ref().to_vec(),
+            },
+            ReceivedTspMessage::StreamChunk {
+                sender,
+                stream_id,
+                index,
+                last,
+                data,
+            } => ReceivedTspMessage::StreamChunk {
+                sender,
+                stream_id,
+                index,
+                last,
+                data: data.as_ref().to_vec(),
+            },
+        }
+    }
+
+    /// Copy the contents of a (borrowed) message into an owned message, like
+    /// [ReceivedTspMessage::into_owned], and decompress compressed contents
+    pub(crate) fn into_decompressed(self) -> Result<ReceivedTspMessage, crate::Error> {
+        match self {
+            ReceivedTspMessage::CompressedMessage {
+                sender,
+                receiver,
+                nonconfidential_data,
+                message,
+            } => Ok(ReceivedTspMessage::GenericMessage {
+                sender,
+                receiver: Some(receiver),
+                nonconfidential_data: nonconfidential_data.map(|data| data.as_ref().to_vec()),
+                message: crate::compression::decompress(
+                    message.as_ref(),
+                    crate::compression::MAX_DECOMPRESSED_SIZE,
+                )?,
+                message_type: MessageType::SignedAndEncrypted,
+                nested_in: Vec::new(),
+                receipt_requested: None,
+                thread: None,
+            }),
+            message => Ok(message.into_owned()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Payload<'a, Bytes: AsRef<[u8]>> {
     Content(Bytes),
+    /// Content that is compressed before it is encrypted; note that an opened payload
+    /// contains the compressed data
+    CompressedContent(Bytes),
+    /// Content with a unique identifier and the time it was sent, so that the receiver can
+    /// detect replayed or stale messages
+    FreshContent {
+        message_id: Digest,
+        timestamp: u64,
+        data: Bytes,
+    },
+    /// Content for which the sender requests a receipt
+    AcknowledgedContent(Bytes),
+    /// Content that is part of the conversation `thread_id`, optionally replying to the
+    /// message with digest `reply_to`
+    ThreadedContent {
+        thread_id: Digest,
+        reply_to: Option<Digest>,
+        data: Bytes,
+    },
+    /// Content that is encrypted once more with the key number `index` of the ratchet of a
+    /// relationship, so that it stays confidential if the keys of a VID are compromised later
+    RatchetedContent {
+        index: u64,
+        data: Bytes,
+    },
     NestedMessage(Bytes),
     RoutedMessage(Vec<VidData<'a>>, Bytes),
-    CancelRelationship { thread_id: Digest },
-    RequestRelationship { route: Option<Vec<VidData<'a>>> },
-    AcceptRelationship { thread_id: Digest },
+    StreamChunk {
+        stream_id: Digest,
+        index: u64,
+        last: bool,
+        data: Bytes,
+    },
+    /// Decline the relationship that was requested with digest `thread_id`
+    RejectRelationship {
+        thread_id: Digest,
+    },
+    CancelRelationship {
+        thread_id: Digest,
+    },
+    /// Request a relationship; a ratchet for the relationship is proposed by including
+    /// an ephemeral public key
+    RequestRelationship {
+        route: Option<Vec<VidData<'a>>>,
+        ratchet_key: Option<&'a [u8; 32]>,
+    },
+    /// Accept a relationship; if a ratchet was proposed, its initial secret is included,
+    /// encrypted for the ephemeral public key of the proposal
+    AcceptRelationship {
+        thread_id: Digest,
+        ratchet_secret: Option<&'a [u8]>,
+    },
+    Receipt {
+        message_digest: Digest,
+    },
 }
 
 impl<'a, Bytes: AsRef<[u8]>> Payload<'a, Bytes> {
     pub fn as_bytes(&self) -> &[u8] {
         match self {
             Payload::Content(bytes) => bytes.as_ref(),
+            Payload::CompressedContent(bytes) => bytes.as_ref(),
+            Payload::FreshContent { data, .. } => data.as_ref(),
+            Payload::AcknowledgedContent(bytes) => bytes.as_ref(),
+            Payload::ThreadedContent { data, .. } => data.as_ref(),
+            Payload::RatchetedContent { data, .. } => data.as_ref(),
             Payload::NestedMessage(bytes) => bytes.as_ref(),
             Payload::RoutedMessage(_, bytes) => bytes.as_ref(),
+            Payload::StreamChunk { data, .. } => data.as_ref(),
+            Payload::RejectRelationship { .. } => &[],
             Payload::CancelRelationship { .. } => &[],
             Payload::RequestRelationship { .. } => &[],
             Payload::AcceptRelationship { .. } => &[],
+            Payload::Receipt { .. } => &[],
         }
     }
 }
@@ -77,6 +340,33 @@ impl<'a, Bytes: AsRef<[u8]>> fmt::Display for Payload<'a, Bytes> {
             Payload::Content(bytes) => {
                 write!(f, "Content: {}", String::from_utf8_lossy(bytes.as_ref()))
             }
+            Payload::CompressedContent(bytes) => {
+                write!(f, "Compressed Content: {} bytes", bytes.as_ref().len())
+            }
+            Payload::FreshContent {
+                timestamp, data, ..
+            } => write!(
+                f,
+                "Content (sent at {timestamp}): {}",
+                String::from_utf8_lossy(data.as_ref())
+            ),
+            Payload::AcknowledgedContent(bytes) => write!(
+                f,
+                "Content (receipt requested): {}",
+                String::from_utf8_lossy(bytes.as_ref())
+            ),
+            Payload::ThreadedContent { data, .. } => write!(
+                f,
+                "Threaded Content: {}",
+                String::from_utf8_lossy(data.as_ref())
+            ),
+            Payload::RatchetedContent { index, data } => {
+                write!(
+                    f,
+                    "Ratcheted Content {index}: {} bytes",
+                    data.as_ref().len()
+                )
+            }
             Payload::NestedMessage(bytes) => write!(
                 f,
                 "Nested Message: {}",
@@ -93,13 +383,88 @@ impl<'a, Bytes: AsRef<[u8]>> fmt::Display for Payload<'a, Bytes> {
                 }
                 write!(f, "]")
             }
+            Payload::StreamChunk { index, data, .. } => {
+                write!(f, "Stream Chunk {index}: {} bytes", data.as_ref().len())
+            }
+            Payload::RejectRelationship { thread_id: _ } => write!(f, "Reject Relationship"),
             Payload::CancelRelationship { thread_id: _ } => write!(f, "Cancel Relationship"),
-            Payload::RequestRelationship { route: _ } => write!(f, "Request Relationship"),
-            Payload::AcceptRelationship { thread_id: _ } => write!(f, "Accept Relationship"),
+            Payload::RequestRelationship { .. } => write!(f, "Request Relationship"),
+            Payload::AcceptRelationship { .. } => write!(f, "Accept Relationship"),
+            Payload::Receipt { message_digest: _ } => write!(f, "Receipt"),
         }
     }
 }
 
+/// The HPKE cipher suite (KEM, KDF and AEAD) that is used to encrypt messages for a VID;
+/// cipher suites are identified by their JOSE HPKE algorithm names. Which cipher suites are
+/// available depends on the enabled features (e.g. `pq`)
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CipherSuite {
+    /// DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and ChaCha20Poly1305
+    #[default]
+    #[cfg_attr(feature = "serialize", serde(rename = "HPKE-4"))]
+    X25519ChaCha20Poly1305,
+    /// DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and AES-128-GCM
+    #[cfg_attr(feature = "serialize", serde(rename = "HPKE-3"))]
+    X25519Aes128Gcm,
+    /// DHKEM(P-256, HKDF-SHA256), HKDF-SHA256 and AES-128-GCM
+    #[cfg_attr(feature = "serialize", serde(rename = "HPKE-0"))]
+    P256Aes128Gcm,
+    /// DHKEM(X25519, HKDF-SHA256) combined with ML-KEM-768, HKDF-SHA256 and ChaCha20Poly1305;
+    /// the ML-KEM shared secret is used as pre-shared key, to protect confidentiality
+    /// against "harvest now, decrypt later" attacks
+    #[cfg(feature = "pq")]
+    #[cfg_attr(feature = "serialize", serde(rename = "HPKE-X25519MLKEM768"))]
+    X25519MlKem768ChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    pub const ALL: &'static [CipherSuite] = &[
+        CipherSuite::X25519ChaCha20Poly1305,
+        CipherSuite::X25519Aes128Gcm,
+        CipherSuite::P256Aes128Gcm,
+        #[cfg(feature = "pq")]
+        CipherSuite::X25519MlKem768ChaCha20Poly1305,
+    ];
+
+    /// The JOSE algorithm name of this cipher suite, e.g. "HPKE-4"
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherSuite::X25519ChaCha20Poly1305 => "HPKE-4",
+            CipherSuite::X25519Aes128Gcm => "HPKE-3",
+            CipherSuite::P256Aes128Gcm => "HPKE-0",
+            #[cfg(feature = "pq")]
+            CipherSuite::X25519MlKem768ChaCha20Poly1305 => "HPKE-X25519MLKEM768",
+        }
+    }
+
+    /// Look up a cipher suite by its JOSE algorithm name
+    pub fn from_name(name: &str) -> Option<CipherSuite> {
+        CipherSuite::ALL
+            .iter()
+            .copied()
+            .find(|suite| suite.name() == name)
+    }
+
+    /// The (JSON Web Key) name of the curve of the encryption keys of this cipher suite
+    pub fn curve(self) -> &'static str {
+        match self {
+            CipherSuite::X25519ChaCha20Poly1305 | CipherSuite::X25519Aes128Gcm => "X25519",
+            CipherSuite::P256Aes128Gcm => "P-256",
+            #[cfg(feature = "pq")]
+            CipherSuite::X25519MlKem768ChaCha20Poly1305 => "X25519MLKEM768",
+        }
+    }
+}
+
+impl fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 pub trait VerifiedVid: Send + Sync {
     /// A identifier of the Vid as bytes (for inclusion in TSP packets)
     fn identifier(&self) -> &str;
@@ -110,8 +475,11 @@ pub trait VerifiedVid: Send + Sync {
     /// The verification key that can check signatures made by this Vid
     fn verifying_key(&self) -> PublicKeyData;
 
-    /// The encryption key associated with this Vid
-    fn encryption_key(&self) -> PublicKeyData;
+    /// The encryption key associated with this Vid; its size depends on the cipher suite
+    fn encryption_key(&self) -> EncryptionKeyData;
+
+    /// The cipher suite that has to be used to encrypt messages for this Vid
+    fn cipher_suite(&self) -> CipherSuite;
 }
 
 pub trait PrivateVid: VerifiedVid + Send + Sync {
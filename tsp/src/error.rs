@@ -17,6 +17,14 @@ pub enum Error {
     #[error("Error: {0}")]
     FromUtf8(#[from] std::string::FromUtf8Error),
     #[error("Error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Error: could not decompress payload: {0}")]
+    Decompression(String),
+    #[error("Error: replayed or stale message: {0}")]
+    Replay(String),
+    #[error("Error: no receipt was received in time")]
+    MissingReceipt,
+    #[error("Error: {0}")]
     InvalidRoute(String),
     #[error("Error: {0}")]
     Relationship(String),
@@ -26,6 +34,10 @@ pub enum Error {
     MissingVid(String),
     #[error("Error: unresolved vid {0}")]
     UnverifiedVid(String),
+    #[error(
+        "Error: nested message from {0} to {1} does not match the relationship of its envelope"
+    )]
+    NestedRelationMismatch(String, String),
     #[error("Internal error")]
     Internal,
 }
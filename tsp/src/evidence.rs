@@ -0,0 +1,77 @@
+use crate::{
+    crypto::CryptoError,
+    definitions::{NonConfidentialData, VerifiedVid},
+    Vid,
+};
+
+#[cfg(feature = "serialize")]
+use crate::vid::deserialize::serde_key_bytes;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// Proof of who sent a TSP message, intended for archival.
+///
+/// It holds the message exactly as it was received and a snapshot of the VID of its sender;
+/// evidence is only created for a message with a valid outer signature. The snapshot alone
+/// does not prove that the keys belong to the sender, as anyone can write a VID with other
+/// keys in it: [Evidence::verify] rechecks the signature against a copy of the sender VID
+/// that is trusted independently of the evidence, e.g. a `did:peer` (whose keys are part of
+/// the identifier) or a VID from a local database or pinned trust anchor. This needs neither
+/// the network nor the private keys of the receiver.
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Debug)]
+pub struct Evidence {
+    #[cfg_attr(feature = "serialize", serde(with = "serde_key_bytes"))]
+    message: Vec<u8>,
+    sender: Vid,
+    created: u64,
+}
+
+impl Evidence {
+    /// Create evidence for the raw `message` sent by `sender`, if its outer signature is valid
+    pub(crate) fn new(sender: Vid, message: &[u8]) -> Result<Self, CryptoError> {
+        crate::crypto::verify_nonconfidential_data(&sender, message)?;
+
+        Ok(Self {
+            message: message.to_vec(),
+            sender,
+            created: crate::freshness::now(),
+        })
+    }
+
+    /// The TSP message, as it was received
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// The VID of the sender, as it was known when the evidence was created
+    pub fn sender(&self) -> &Vid {
+        &self.sender
+    }
+
+    /// When the evidence was created, in seconds since the UNIX epoch
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    /// Recheck the outer signature of the message against `trusted_sender`, and return its
+    /// non-confidential data. The identifier and verifying key of `trusted_sender` have to
+    /// match the snapshot of the sender VID in this evidence; its encryption key may have
+    /// been rotated since, as it plays no part in the signature.
+    pub fn verify(
+        &self,
+        trusted_sender: &dyn VerifiedVid,
+    ) -> Result<Option<NonConfidentialData<'_>>, CryptoError> {
+        if trusted_sender.identifier() != self.sender.identifier()
+            || trusted_sender.verifying_key() != self.sender.verifying_key()
+        {
+            return Err(CryptoError::UnexpectedSender);
+        }
+
+        crate::crypto::verify_nonconfidential_data(trusted_sender, &self.message)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{definitions::Digest, error::Error};
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How long (in seconds) after it was sent a message is accepted
+pub(crate) const MAX_MESSAGE_AGE: u64 = 300;
+
+/// How far (in seconds) the timestamp of a message may lie in the future, to allow for clock skew
+pub(crate) const MAX_CLOCK_SKEW: u64 = 30;
+
+/// The maximum number of message identifiers that are remembered per sender, i.e. the number
+/// of messages a sender can send within [MAX_MESSAGE_AGE]
+const REPLAY_CACHE_SIZE: usize = 100_000;
+
+/// The current time in seconds since the UNIX epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// Remembers the identifiers of recently received messages of a sender, to detect replays.
+///
+/// Identifiers are remembered until their messages have expired, after which a replay is
+/// rejected as stale. The cache is bounded: while it is full, new messages are rejected until
+/// older messages expire, so that a replayed message cannot go unnoticed.
+#[derive(Debug, Default)]
+pub(crate) struct ReplayCache {
+    seen: HashSet<Digest>,
+    by_age: BTreeSet<(u64, Digest)>,
+}
+
+impl ReplayCache {
+    /// Check that the message identified by `message_id` and sent at `timestamp` is fresh,
+    /// and remember it
+    pub(crate) fn check(
+        &mut self,
+        message_id: &Digest,
+        timestamp: u64,
+        now: u64,
+    ) -> Result<(), Error> {
+        if timestamp > now.saturating_add(MAX_CLOCK_SKEW) {
+            return Err(Error::Replay("message timestamp lies in the future".into()));
+        }
+
+        if timestamp.saturating_add(MAX_MESSAGE_AGE) < now {
+            return Err(Error::Replay("message is too old".into()));
+        }
+
+        // expired messages are rejected as stale, so they do not have to be remembered
+        while let Some(&(timestamp, message_id)) = self.by_age.first() {
+            if timestamp.saturating_add(MAX_MESSAGE_AGE) >= now {
+                break;
+            }
+
+            self.by_age.pop_first();
+            self.seen.remove(&message_id);
+        }
+
+        if self.seen.contains(message_id) {
+            return Err(Error::Replay("message was already received".into()));
+        }
+
+        if self.seen.len() >= REPLAY_CACHE_SIZE {
+            return Err(Error::Replay(
+                "too many messages were received recently".into(),
+            ));
+        }
+
+        self.seen.insert(*message_id);
+        self.by_age.insert((timestamp, *message_id));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ReplayCache, MAX_CLOCK_SKEW, MAX_MESSAGE_AGE, REPLAY_CACHE_SIZE};
+    use crate::Error;
+
+    /// A message identifier that is unique for `i`
+    fn message_id(i: u64) -> [u8; 32] {
+        let mut message_id = [0; 32];
+        message_id[..8].copy_from_slice(&i.to_be_bytes());
+
+        message_id
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut cache = ReplayCache::default();
+        let now = 1_000_000;
+
+        cache.check(&[1; 32], now, now).unwrap();
+        cache.check(&[2; 32], now, now).unwrap();
+
+        assert!(matches!(
+            cache.check(&[1; 32], now, now + 1),
+            Err(Error::Replay(_))
+        ));
+    }
+
+    #[test]
+    fn test_stale() {
+        let mut cache = ReplayCache::default();
+        let now = 1_000_000;
+
+        assert!(cache
+            .check(&[1; 32], now - MAX_MESSAGE_AGE - 1, now)
+            .is_err());
+        assert!(cache
+            .check(&[2; 32], now + MAX_CLOCK_SKEW + 1, now)
+            .is_err());
+        assert!(cache.check(&[3; 32], now + MAX_CLOCK_SKEW, now).is_ok());
+    }
+
+    #[test]
+    fn test_same_timestamp() {
+        let mut cache = ReplayCache::default();
+        let now = 1_000_000;
+
+        // many messages can be sent within the same second, and arrive out of order
+        for i in 0..5000 {
+            cache.check(&message_id(i), now - i % 2, now).unwrap();
+        }
+
+        for i in 0..5000 {
+            assert!(cache.check(&message_id(i), now - i % 2, now).is_err());
+        }
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut cache = ReplayCache::default();
+        let now = 1_000_000;
+
+        for i in 0..REPLAY_CACHE_SIZE as u64 {
+            cache.check(&message_id(i), now - 100, now).unwrap();
+        }
+
+        // the cache is full until the messages in it have expired
+        let next = message_id(REPLAY_CACHE_SIZE as u64);
+        assert!(cache.check(&next, now, now).is_err());
+        assert!(cache
+            .check(&next, now, now - 100 + MAX_MESSAGE_AGE)
+            .is_err());
+        assert_eq!(cache.seen.len(), REPLAY_CACHE_SIZE);
+
+        let later = now - 100 + MAX_MESSAGE_AGE + 1;
+        assert!(cache.check(&next, now, later).is_ok());
+        assert_eq!(cache.seen.len(), 1);
+
+        // the first message has been forgotten, but a replay is still detected
+        assert!(cache.check(&message_id(0), now - 100, later).is_err());
+    }
+}
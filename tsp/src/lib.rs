@@ -73,7 +73,9 @@ pub mod cesr;
 ///   - generating confidential messages encrypted using
 ///     [HPKE-Auth](https://datatracker.ietf.org/doc/rfc9180/);
 ///     using DHKEM(X25519, HKDF-SHA256) as asymmetric primitives and
-///     ChaCha20/Poly1305 as underlying AEAD encrypting scheme,
+///     ChaCha20/Poly1305 as underlying AEAD encrypting scheme by default
+///     (a VID can select DHKEM(P-256, HKDF-SHA256) and/or AES-128-GCM instead,
+///     or a hybrid X25519+ML-KEM-768 key encapsulation with the `pq` feature),
 ///     and signed using Ed25519 to achieve **non-repudiation**
 ///     (more precisely "strong receiver-unforgeability under chosen
 pub mod crypto;
@@ -81,6 +83,18 @@ pub mod crypto;
 /// Defines several common data structures, traits and error types that are used throughout the project.
 pub mod definitions;
 mod error;
+
+/// Compression of message contents before encryption
+mod compression;
+
+/// Archival evidence of who sent a message
+mod evidence;
+
+/// Detection of replayed and stale messages
+mod freshness;
+
+/// The state of relationships between VIDs
+mod relationship;
 mod store;
 
 /// Contains code for handling *verified identifiers* and identities.
@@ -95,14 +109,26 @@ pub mod transport;
 #[cfg(feature = "async")]
 mod async_store;
 
+#[cfg(feature = "async")]
+mod stream;
+
 #[cfg(feature = "async")]
 #[cfg(test)]
 mod test;
 
 #[cfg(feature = "async")]
-pub use async_store::AsyncStore;
+pub use async_store::{AsyncStore, PendingReceipt};
+
+#[cfg(feature = "async")]
+pub use stream::PayloadReader;
 
-pub use definitions::{Payload, PrivateVid, ReceivedTspMessage, VerifiedVid};
+pub use definitions::{
+    MessageThread, Payload, PrivateVid, ReceivedEnvelope, ReceivedTspMessage, VerifiedVid,
+};
 pub use error::Error;
+pub use evidence::Evidence;
+pub use relationship::{
+    RelationshipDecision, RelationshipEvent, RelationshipPolicy, RelationshipStatus,
+};
 pub use store::{ExportVid, Store};
 pub use vid::{OwnedVid, Vid};
@@ -0,0 +1,440 @@
+use crate::{definitions::Digest, error::Error};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The state of our relationship with a VID.
+///
+/// Relationships are formed and ended by exchanging control messages; the digest that is
+/// kept in a state identifies the relationship request (the "thread") it belongs to.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelationshipStatus {
+    _Controlled,
+    /// Both parties agreed on the relationship
+    Bidirectional(Digest),
+    /// We requested a relationship, which the other party has not accepted (yet)
+    Unidirectional(Digest),
+    /// The other party requested a relationship, which we have not accepted (yet)
+    PendingIncoming(Digest),
+    /// A request for a relationship was rejected
+    Rejected,
+    /// The relationship was cancelled by either party
+    Cancelled,
+    /// A request for a relationship was not answered in time
+    Expired,
+    Unrelated,
+}
+
+impl RelationshipStatus {
+    /// The digest of the relationship request that this relationship is formed with,
+    /// if it is (being) formed
+    pub fn thread_id(&self) -> Option<Digest> {
+        match self {
+            RelationshipStatus::Bidirectional(thread_id)
+            | RelationshipStatus::Unidirectional(thread_id)
+            | RelationshipStatus::PendingIncoming(thread_id) => Some(*thread_id),
+            _ => None,
+        }
+    }
+
+    /// Determine the state after `action`, or fail if the action is not allowed in this state
+    pub(crate) fn transition(self, action: RelationshipAction) -> Result<Self, Error> {
+        use RelationshipAction as Action;
+        use RelationshipStatus as Status;
+
+        let invalid = |reason: &str| Err(Error::Relationship(reason.into()));
+
+        match (action, self) {
+            (Action::Request(_), Status::Bidirectional(_) | Status::_Controlled) => {
+                invalid("a relationship already exists")
+            }
+            (Action::Request(thread_id), _) => Ok(Status::Unidirectional(thread_id)),
+
+            (Action::ReceiveRequest(_), Status::Bidirectional(_) | Status::_Controlled) => {
+                invalid("received a request for a relationship that already exists")
+            }
+            // both parties requested the relationship at the same time: both keep the request
+            // with the lowest thread id, so that they agree on the relationship that is formed
+            (Action::ReceiveRequest(thread_id), Status::Unidirectional(digest))
+                if thread_id >= digest =>
+            {
+                invalid("received a request for a relationship that we requested first")
+            }
+            (Action::ReceiveRequest(thread_id), _) => Ok(Status::PendingIncoming(thread_id)),
+
+            (Action::Accept(thread_id), Status::PendingIncoming(digest)) if thread_id == digest => {
+                Ok(Status::Bidirectional(thread_id))
+            }
+            (Action::Accept(_), Status::PendingIncoming(_)) => {
+                invalid("attempt to accept a relationship that was not requested")
+            }
+            (Action::Accept(_), _) => invalid("there is no request for a relationship to accept"),
+
+            (Action::ReceiveAccept(thread_id), Status::Unidirectional(digest)) => {
+                if thread_id == digest {
+                    Ok(Status::Bidirectional(thread_id))
+                } else {
+                    invalid("attempt to change the terms of the relationship")
+                }
+            }
+            (Action::ReceiveAccept(_), _) => {
+                invalid("received confirmation of a relation that we did not want")
+            }
+
+            (Action::Reject(thread_id), Status::PendingIncoming(digest)) if thread_id == digest => {
+                Ok(Status::Rejected)
+            }
+            (Action::Reject(_), Status::PendingIncoming(_)) => {
+                invalid("attempt to reject a relationship that was not requested")
+            }
+            (Action::Reject(_), _) => invalid("there is no request for a relationship to reject"),
+
+            (Action::ReceiveReject(thread_id), Status::Unidirectional(digest)) => {
+                if thread_id == digest {
+                    Ok(Status::Rejected)
+                } else {
+                    invalid("invalid attempt to reject the relationship")
+                }
+            }
+            (Action::ReceiveReject(_), _) => {
+                invalid("received a rejection of a relation that we did not request")
+            }
+
+            (Action::Expire, Status::Unidirectional(_) | Status::PendingIncoming(_)) => {
+                Ok(Status::Expired)
+            }
+            (Action::Expire, _) => {
+                invalid("there is no request for a relationship that can expire")
+            }
+
+            (Action::Cancel, status) if status.thread_id().is_some() => Ok(Status::Cancelled),
+            (Action::Cancel, _) => invalid("there is no relationship to cancel"),
+
+            (Action::ReceiveCancel(thread_id), status) => match status.thread_id() {
+                Some(digest) if digest == thread_id => Ok(Status::Cancelled),
+                Some(_) => invalid("invalid attempt to end the relationship"),
+                None => invalid("attempt to end a relationship that does not exist"),
+            },
+        }
+    }
+
+    /// Whether a relationship is formed or being formed in this state
+    pub(crate) fn is_active(&self) -> bool {
+        self.thread_id().is_some()
+    }
+
+    /// Whether a relationship was requested, but the request has not been answered (yet)
+    pub(crate) fn is_pending(&self) -> bool {
+        matches!(
+            self,
+            RelationshipStatus::Unidirectional(_) | RelationshipStatus::PendingIncoming(_)
+        )
+    }
+}
+
+/// Something we, or the other party, did to a relationship
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RelationshipAction {
+    /// We sent the relationship request with digest `thread_id`
+    Request(Digest),
+    /// We received the relationship request with digest `thread_id`
+    ReceiveRequest(Digest),
+    /// We accept the relationship requested with digest `thread_id`
+    Accept(Digest),
+    /// The other party accepted the relationship requested with digest `thread_id`
+    ReceiveAccept(Digest),
+    /// We decline the relationship requested with digest `thread_id`
+    Reject(Digest),
+    /// The other party declined the relationship requested with digest `thread_id`
+    ReceiveReject(Digest),
+    /// The request for the relationship was not answered in time
+    Expire,
+    /// We cancel the relationship
+    Cancel,
+    /// The other party cancelled the relationship requested with digest `thread_id`
+    ReceiveCancel(Digest),
+}
+
+/// A change of the state of a relationship, see [crate::Store::relationship_events]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelationshipEvent {
+    /// The VID the relationship is with
+    pub vid: String,
+    /// The state before the change
+    pub previous: RelationshipStatus,
+    /// The state after the change
+    pub status: RelationshipStatus,
+}
+
+/// A subscriber to relationship events; it returns `false` when it is no longer interested
+pub(crate) type Subscriber = Box<dyn Fn(&RelationshipEvent) -> bool + Send + Sync>;
+
+/// What to do with an incoming relationship request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelationshipDecision {
+    /// Accept the relationship
+    Accept,
+    /// Decline the relationship, informing the sender of the request
+    Reject,
+    /// Leave the request to the application
+    Defer,
+    /// Drop the request, without informing the application or the sender
+    Ignore,
+}
+
+/// A callback that decides about a relationship request, given the VID that received it
+/// and the VID that sent it
+type Decide = Arc<dyn Fn(&str, &str) -> RelationshipDecision + Send + Sync>;
+
+/// Limits the number of requests that are considered within a period of time
+#[derive(Clone)]
+struct RateLimit {
+    max_requests: usize,
+    period: Duration,
+    requests: VecDeque<Instant>,
+}
+
+impl RateLimit {
+    /// Record a request; returns whether it is within the limit
+    fn admit(&mut self, now: Instant) -> bool {
+        while self
+            .requests
+            .front()
+            .is_some_and(|&request| now.duration_since(request) >= self.period)
+        {
+            self.requests.pop_front();
+        }
+
+        if self.requests.len() >= self.max_requests {
+            return false;
+        }
+
+        self.requests.push_back(now);
+
+        true
+    }
+}
+
+/// Decides automatically about incoming relationship requests, so that unattended services
+/// can form relationships; see [crate::AsyncStore::set_relationship_policy].
+///
+/// Requests from denied VIDs are rejected, and requests from allowed VIDs are accepted.
+/// Other requests are subject to the rate limit, if any, and ignored when it is exceeded;
+/// the remaining requests are decided by the callback, or left to the application.
+///
+/// ```
+/// use std::time::Duration;
+/// use tsp::{RelationshipDecision, RelationshipPolicy};
+///
+/// let policy = RelationshipPolicy::new()
+///     .allow("did:web:did.tsp-test.org:user:alice")
+///     .deny("did:web:did.tsp-test.org:user:mallory")
+///     .rate_limit(10, Duration::from_secs(60))
+///     .callback(|_receiver, _sender| RelationshipDecision::Reject);
+/// ```
+#[derive(Clone, Default)]
+pub struct RelationshipPolicy {
+    allowed: HashSet<String>,
+    denied: HashSet<String>,
+    rate_limit: Option<RateLimit>,
+    callback: Option<Decide>,
+}
+
+impl RelationshipPolicy {
+    /// Create a policy that leaves all requests to the application
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Accept relationship requests from `vid`
+    pub fn allow(mut self, vid: impl Into<String>) -> Self {
+        self.allowed.insert(vid.into());
+        self
+    }
+
+    /// Reject relationship requests from `vid`
+    pub fn deny(mut self, vid: impl Into<String>) -> Self {
+        self.denied.insert(vid.into());
+        self
+    }
+
+    /// Ignore requests from VIDs that are not allowed or denied, if more than `max_requests`
+    /// of them arrive within `period`
+    pub fn rate_limit(mut self, max_requests: usize, period: Duration) -> Self {
+        self.rate_limit = Some(RateLimit {
+            max_requests,
+            period,
+            requests: VecDeque::new(),
+        });
+        self
+    }
+
+    /// Decide about requests from VIDs that are not allowed or denied using `callback`,
+    /// which is called with the VID that received the request and the VID that sent it
+    pub fn callback(
+        mut self,
+        callback: impl Fn(&str, &str) -> RelationshipDecision + Send + Sync + 'static,
+    ) -> Self {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Decide about the relationship request that `receiver` received from `sender`
+    pub(crate) fn decide(&mut self, receiver: &str, sender: &str) -> RelationshipDecision {
+        if self.denied.contains(sender) {
+            return RelationshipDecision::Reject;
+        }
+
+        if self.allowed.contains(sender) {
+            return RelationshipDecision::Accept;
+        }
+
+        if let Some(rate_limit) = &mut self.rate_limit {
+            if !rate_limit.admit(Instant::now()) {
+                return RelationshipDecision::Ignore;
+            }
+        }
+
+        match &self.callback {
+            Some(callback) => callback(receiver, sender),
+            None => RelationshipDecision::Defer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        RelationshipAction as Action, RelationshipDecision as Decision, RelationshipPolicy,
+        RelationshipStatus as Status,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn relationship_transitions() {
+        let thread_id = [1; 32];
+        let other_thread_id = [2; 32];
+
+        // forming a relationship, from both sides
+        let requested = Status::Unrelated
+            .transition(Action::Request(thread_id))
+            .unwrap();
+        assert_eq!(requested, Status::Unidirectional(thread_id));
+        assert_eq!(
+            requested
+                .transition(Action::ReceiveAccept(thread_id))
+                .unwrap(),
+            Status::Bidirectional(thread_id)
+        );
+        assert!(requested
+            .transition(Action::ReceiveAccept(other_thread_id))
+            .is_err());
+
+        let pending = Status::Unrelated
+            .transition(Action::ReceiveRequest(thread_id))
+            .unwrap();
+        assert_eq!(pending, Status::PendingIncoming(thread_id));
+        assert!(pending.transition(Action::Accept(other_thread_id)).is_err());
+        let formed = pending.transition(Action::Accept(thread_id)).unwrap();
+        assert_eq!(formed, Status::Bidirectional(thread_id));
+
+        // a relationship can not be formed twice
+        assert!(formed.transition(Action::Request(other_thread_id)).is_err());
+        assert!(formed
+            .transition(Action::ReceiveRequest(other_thread_id))
+            .is_err());
+        assert!(formed.transition(Action::ReceiveAccept(thread_id)).is_err());
+
+        // ending a relationship
+        assert!(formed
+            .transition(Action::ReceiveCancel(other_thread_id))
+            .is_err());
+        let cancelled = formed.transition(Action::ReceiveCancel(thread_id)).unwrap();
+        assert_eq!(cancelled, Status::Cancelled);
+        assert_eq!(
+            formed.transition(Action::Cancel).unwrap(),
+            Status::Cancelled
+        );
+        assert!(cancelled.transition(Action::Cancel).is_err());
+        assert!(cancelled.transition(Action::Accept(thread_id)).is_err());
+
+        // declining a relationship, from both sides
+        assert!(pending.transition(Action::Reject(other_thread_id)).is_err());
+        assert_eq!(
+            pending.transition(Action::Reject(thread_id)).unwrap(),
+            Status::Rejected
+        );
+        assert!(requested
+            .transition(Action::ReceiveReject(other_thread_id))
+            .is_err());
+        assert_eq!(
+            requested
+                .transition(Action::ReceiveReject(thread_id))
+                .unwrap(),
+            Status::Rejected
+        );
+        assert!(formed.transition(Action::ReceiveReject(thread_id)).is_err());
+
+        // only requests can expire
+        assert_eq!(
+            requested.transition(Action::Expire).unwrap(),
+            Status::Expired
+        );
+        assert_eq!(pending.transition(Action::Expire).unwrap(), Status::Expired);
+        assert!(formed.transition(Action::Expire).is_err());
+
+        // simultaneous requests resolve to the request with the lowest thread id
+        let (lower, higher) = (
+            Status::Unidirectional(thread_id),
+            Status::Unidirectional(other_thread_id),
+        );
+        assert!(lower
+            .transition(Action::ReceiveRequest(other_thread_id))
+            .is_err());
+        assert_eq!(
+            higher
+                .transition(Action::ReceiveRequest(thread_id))
+                .unwrap(),
+            Status::PendingIncoming(thread_id)
+        );
+
+        // a new relationship can be requested after the previous one ended
+        assert_eq!(
+            cancelled
+                .transition(Action::Request(other_thread_id))
+                .unwrap(),
+            Status::Unidirectional(other_thread_id)
+        );
+    }
+
+    #[test]
+    fn relationship_policy() {
+        let mut policy = RelationshipPolicy::new();
+        assert_eq!(policy.decide("bob", "alice"), Decision::Defer);
+
+        let mut policy = RelationshipPolicy::new()
+            .allow("alice")
+            .deny("mallory")
+            .rate_limit(2, Duration::from_secs(3600))
+            .callback(|receiver, sender| {
+                if receiver == "bob" && sender == "carol" {
+                    Decision::Accept
+                } else {
+                    Decision::Reject
+                }
+            });
+
+        assert_eq!(policy.decide("bob", "mallory"), Decision::Reject);
+        assert_eq!(policy.decide("bob", "carol"), Decision::Accept);
+        assert_eq!(policy.decide("bob", "dave"), Decision::Reject);
+
+        // the rate limit is exceeded, except for allowed VIDs
+        assert_eq!(policy.decide("bob", "carol"), Decision::Ignore);
+        assert_eq!(policy.decide("bob", "alice"), Decision::Accept);
+    }
+}
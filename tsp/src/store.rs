@@ -1,10 +1,17 @@
 use crate::{
     cesr::EnvelopeType,
-    crypto::CryptoError,
-    definitions::{Digest, MessageType, Payload, PrivateVid, ReceivedTspMessage, VerifiedVid},
+    crypto::{CryptoError, Ratchet, RatchetState},
+    definitions::{
+        Digest, MessageThread, MessageType, Payload, PrivateVid, ReceivedEnvelope,
+        ReceivedTspMessage, VerifiedVid,
+    },
     error::Error,
+    freshness::ReplayCache,
+    relationship::{
+        RelationshipAction, RelationshipDecision, RelationshipEvent, RelationshipStatus, Subscriber,
+    },
     vid::VidError,
-    OwnedVid, Vid,
+    Evidence, OwnedVid, Vid,
 };
 #[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
@@ -12,18 +19,10 @@ use std::{
     collections::HashMap,
     fmt::Debug,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 use url::Url;
 
-#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
-#[derive(Clone, Copy, Debug)]
-pub enum RelationshipStatus {
-    _Controlled,
-    Bidirectional(Digest),
-    Unidirectional(Digest),
-    Unrelated,
-}
-
 /// VID and its key material, intended for serialization
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
@@ -34,6 +33,9 @@ pub struct ExportVid {
     relation_vid: Option<String>,
     parent_vid: Option<String>,
     tunnel: Option<Box<[String]>>,
+    /// Whether a ratchet was negotiated for the relationship; its state is not exported
+    #[cfg_attr(feature = "serialize", serde(default))]
+    ratcheted: bool,
 }
 
 #[derive(Clone)]
@@ -44,6 +46,12 @@ pub(crate) struct VidContext {
     pub(crate) relation_vid: Option<String>,
     pub(crate) parent_vid: Option<String>,
     pub(crate) tunnel: Option<Box<[String]>>,
+    /// The ratchet of the relationship with this VID, if one was negotiated; it is only
+    /// kept in memory, and is [RatchetState::Lost] after an import
+    pub(crate) ratchet: Option<RatchetState>,
+    /// When the relationship request with this VID was sent or received, if it was not
+    /// answered yet; in seconds since the UNIX epoch
+    pub(crate) pending_since: Option<u64>,
 }
 
 impl VidContext {
@@ -58,8 +66,14 @@ impl VidContext {
         self.relation_vid = relation_vid.map(|r| r.to_string());
     }
 
-    /// Set the relation status for this VID.
+    /// Set the relation status for this VID; ending the relationship discards its ratchet
     fn set_relation_status(&mut self, relation_status: RelationshipStatus) {
+        if !relation_status.is_active() {
+            self.ratchet = None;
+        }
+
+        self.pending_since = relation_status.is_pending().then(crate::freshness::now);
+
         self.relation_status = relation_status;
     }
 
@@ -86,6 +100,32 @@ impl VidContext {
     pub(crate) fn get_route(&self) -> Option<&[String]> {
         self.tunnel.as_deref()
     }
+
+    /// Refuse to send content that is not encrypted with the ratchet of the relationship
+    /// with this VID, if the relationship has one
+    fn check_ratchet(&self, payload: &Payload<&[u8]>) -> Result<(), Error> {
+        let ratcheted = matches!(
+            self.ratchet,
+            Some(RatchetState::Established(_) | RatchetState::Lost)
+        );
+        let content = matches!(
+            payload,
+            Payload::Content(_)
+                | Payload::CompressedContent(_)
+                | Payload::FreshContent { .. }
+                | Payload::AcknowledgedContent(_)
+                | Payload::ThreadedContent { .. }
+                | Payload::StreamChunk { .. }
+        );
+
+        if ratcheted && content {
+            return Err(Error::Relationship(
+                "this message type can not be sent over a ratcheted relationship".into(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// Holds private ands verified VIDs
@@ -97,6 +137,8 @@ impl VidContext {
 #[derive(Default, Clone)]
 pub struct Store {
     pub(crate) vids: Arc<RwLock<HashMap<String, VidContext>>>,
+    replay_caches: Arc<RwLock<HashMap<String, ReplayCache>>>,
+    relationship_subscribers: Arc<RwLock<Vec<Subscriber>>>,
 }
 
 /// This database is used to store and resolve VIDs
@@ -119,6 +161,7 @@ impl Store {
                     relation_vid: context.relation_vid.clone(),
                     parent_vid: context.parent_vid.clone(),
                     tunnel: context.tunnel.clone(),
+                    ratcheted: context.ratchet.is_some(),
                 })
             })
             .collect()
@@ -139,6 +182,9 @@ impl Store {
                     relation_vid: vid.relation_vid,
                     parent_vid: vid.parent_vid,
                     tunnel: vid.tunnel,
+                    ratchet: vid.ratcheted.then_some(RatchetState::Lost),
+                    // imported requests can expire from now on
+                    pending_since: vid.relation_status.is_pending().then(crate::freshness::now),
                 },
             );
 
@@ -157,6 +203,8 @@ impl Store {
                 relation_vid: None,
                 parent_vid: None,
                 tunnel: None,
+                ratchet: None,
+                pending_since: None,
             },
         );
 
@@ -176,6 +224,8 @@ impl Store {
                 relation_vid: None,
                 parent_vid: None,
                 tunnel: None,
+                ratchet: None,
+                pending_since: None,
             },
         );
 
@@ -212,19 +262,6 @@ impl Store {
         Ok(self.vids.read()?.keys().cloned().collect())
     }
 
-    /// Sets the relationship status for a VID
-    pub fn set_relation_status_for_vid(
-        &self,
-        vid: &str,
-        relation_status: RelationshipStatus,
-    ) -> Result<(), Error> {
-        self.modify_vid(vid, |resolved| {
-            resolved.set_relation_status(relation_status);
-
-            Ok(())
-        })
-    }
-
     /// Adds a route to an already existing vid, making it a nested Vid
     pub fn set_route_for_vid(&self, vid: &str, route: &[&str]) -> Result<(), Error> {
         if route.len() == 1 {
@@ -290,15 +327,493 @@ impl Store {
         receiver: &str,
         nonconfidential_data: Option<&[u8]>,
         message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let ratcheted = self.ratchet_message(receiver, message)?;
+        let payload = match &ratcheted {
+            Some((index, data)) => Payload::RatchetedContent {
+                index: *index,
+                data: data.as_slice(),
+            },
+            None => Payload::Content(message),
+        };
+
+        self.seal_message_payload(sender, receiver, nonconfidential_data, payload)
+    }
+
+    /// Encrypt `message` with the ratchet of the relationship with `vid`, if there is one.
+    ///
+    /// The ratchet is stepped before the message is sealed, so a key is never used twice;
+    /// if sealing fails afterwards, its index is skipped, and the receiver keeps the key of
+    /// the skipped index until it is forgotten.
+    fn ratchet_message(&self, vid: &str, message: &[u8]) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        match self
+            .vids
+            .write()?
+            .get_mut(vid)
+            .and_then(|context| context.ratchet.as_mut())
+        {
+            Some(RatchetState::Established(ratchet)) => Ok(Some(ratchet.seal(message)?)),
+            Some(RatchetState::Lost) => Err(Error::Relationship(
+                "the ratchet of the relationship was lost, it has to be formed again".into(),
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    /// Subscribe to changes of the state of relationships, which are caused by sending
+    /// or receiving relationship control messages
+    pub fn relationship_events(
+        &self,
+    ) -> Result<std::sync::mpsc::Receiver<RelationshipEvent>, Error> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.subscribe_relationships(Box::new(move |event| sender.send(event.clone()).is_ok()))?;
+
+        Ok(receiver)
+    }
+
+    /// Notify `subscriber` of every change of the state of a relationship
+    pub(crate) fn subscribe_relationships(&self, subscriber: Subscriber) -> Result<(), Error> {
+        self.relationship_subscribers.write()?.push(subscriber);
+
+        Ok(())
+    }
+
+    /// Apply `action` to the relationship with `vid`, if it is allowed in the current state
+    /// of the relationship, and notify the subscribers; returns the previous state
+    pub(crate) fn update_relationship(
+        &self,
+        vid: &str,
+        action: RelationshipAction,
+    ) -> Result<RelationshipStatus, Error> {
+        self.update_relationship_with(vid, action, |_| Ok(()))
+    }
+
+    /// Apply `action` like [Store::update_relationship]; `update` can modify the context
+    /// of the VID once the action is known to be allowed
+    fn update_relationship_with(
+        &self,
+        vid: &str,
+        action: RelationshipAction,
+        update: impl FnOnce(&mut VidContext) -> Result<(), Error>,
+    ) -> Result<RelationshipStatus, Error> {
+        let event = {
+            let mut vids = self.vids.write()?;
+            let Some(context) = vids.get_mut(vid) else {
+                return Err(Error::UnverifiedVid(vid.to_string()));
+            };
+
+            let previous = context.relation_status;
+            let status = previous.transition(action)?;
+
+            update(context)?;
+            context.set_relation_status(status);
+
+            RelationshipEvent {
+                vid: vid.to_string(),
+                previous,
+                status,
+            }
+        };
+
+        self.notify_relationship_subscribers(&event)?;
+
+        Ok(event.previous)
+    }
+
+    /// Notify the subscribers of a change of the state of a relationship
+    fn notify_relationship_subscribers(&self, event: &RelationshipEvent) -> Result<(), Error> {
+        // subscribers that are no longer interested are dropped
+        self.relationship_subscribers
+            .write()?
+            .retain(|subscriber| subscriber(event));
+
+        Ok(())
+    }
+
+    /// Record that we requested the relationship `thread_id` with `vid`, proposing `ratchet`
+    pub(crate) fn request_relationship(
+        &self,
+        vid: &str,
+        thread_id: Digest,
+        ratchet: Option<RatchetState>,
+    ) -> Result<(), Error> {
+        self.update_relationship_with(vid, RelationshipAction::Request(thread_id), |context| {
+            context.ratchet = ratchet;
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    /// Check that `action` is allowed in the current state of the relationship with `vid`,
+    /// without applying it; returns the current state
+    fn check_relationship(
+        &self,
+        vid: &str,
+        action: RelationshipAction,
+    ) -> Result<RelationshipStatus, Error> {
+        let status = self.get_vid(vid)?.relation_status;
+        status.transition(action)?;
+
+        Ok(status)
+    }
+
+    /// Accept the relationship `thread_id` that `vid` requested, setting up the `ratchet`
+    /// that was prepared by [Store::seal_relationship_accept]
+    pub(crate) fn accept_relationship(
+        &self,
+        vid: &str,
+        thread_id: Digest,
+        ratchet: Option<Ratchet>,
+    ) -> Result<(), Error> {
+        self.update_relationship_with(vid, RelationshipAction::Accept(thread_id), |context| {
+            context.ratchet = ratchet.map(RatchetState::Established);
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    /// Cancel the relationship with `vid`; returns the digest of the request that formed it
+    pub(crate) fn cancel_relationship(&self, vid: &str) -> Result<Digest, Error> {
+        let previous = self.update_relationship(vid, RelationshipAction::Cancel)?;
+
+        // only relationships with a thread id can be cancelled
+        previous.thread_id().ok_or(Error::Internal)
+    }
+
+    /// Decline the relationship `thread_id` that `vid` requested
+    pub(crate) fn reject_relationship(&self, vid: &str, thread_id: Digest) -> Result<(), Error> {
+        self.update_relationship(vid, RelationshipAction::Reject(thread_id))?;
+
+        Ok(())
+    }
+
+    /// Let relationship requests that were sent or received more than `timeout` ago,
+    /// and were not answered, expire; returns the VIDs whose requests expired
+    pub fn expire_relationship_requests(&self, timeout: Duration) -> Result<Vec<String>, Error> {
+        let deadline = crate::freshness::now().saturating_sub(timeout.as_secs());
+
+        // requests expire under the same lock as they are found, so that they can not be
+        // answered in between; relationships that can not expire are skipped
+        let events = self
+            .vids
+            .write()?
+            .iter_mut()
+            .filter(|(_, context)| context.pending_since.is_some_and(|since| since < deadline))
+            .filter_map(|(vid, context)| {
+                let previous = context.relation_status;
+                let status = previous.transition(RelationshipAction::Expire).ok()?;
+                context.set_relation_status(status);
+
+                Some(RelationshipEvent {
+                    vid: vid.clone(),
+                    previous,
+                    status,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for event in &events {
+            self.notify_relationship_subscribers(event)?;
+        }
+
+        Ok(events.into_iter().map(|event| event.vid).collect())
+    }
+
+    /// Seal a TSP message like [Store::seal_message], but compress the message before it is
+    /// encrypted. The receiver decompresses the message transparently.
+    ///
+    /// Compression is opt-in: the length of a compressed message can reveal information about
+    /// its contents if it mixes secret and attacker-controlled data.
+    ///
+    /// Compressed messages can not be sent over a nested or ratcheted relationship.
+    pub fn seal_compressed_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
     ) -> Result<(url::Url, Vec<u8>), Error> {
         self.seal_message_payload(
             sender,
             receiver,
             nonconfidential_data,
+            Payload::CompressedContent(message),
+        )
+    }
+
+    /// Seal a TSP message like [Store::seal_message], but hide the sender from anyone but the
+    /// receiver ("sealed sender"): the envelope only contains the receiver, while the sender
+    /// and its signature are encrypted together with the message.
+    ///
+    /// The message is always sent directly to the receiver, regardless of a configured
+    /// route or nested relationship; it can not be sent over a ratcheted relationship.
+    pub fn seal_message_hidden_sender(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let sender = self.get_private_vid(sender)?;
+        let receiver_context = self.get_vid(receiver)?;
+        receiver_context.check_ratchet(&Payload::Content(message))?;
+        let receiver = receiver_context.vid;
+
+        let tsp_message = crate::crypto::seal_hidden_sender(
+            &*sender,
+            &*receiver,
+            nonconfidential_data,
             Payload::Content(message),
+        )?;
+
+        Ok((receiver.endpoint().clone(), tsp_message))
+    }
+
+    /// Seal a TSP message like [Store::seal_message], but add a unique message identifier
+    /// and the current time to the confidential payload. The receiver rejects the message
+    /// if it was already received, or if it is too old.
+    ///
+    /// Fresh messages can not be sent over a nested or ratcheted relationship.
+    pub fn seal_fresh_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        self.seal_message_payload(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::FreshContent {
+                message_id: rand::random(),
+                timestamp: crate::freshness::now(),
+                data: message,
+            },
+        )
+    }
+
+    /// Seal a TSP message like [Store::seal_message], but request a receipt from the receiver.
+    /// Also returns the digest of the message, which the receipt will refer to.
+    ///
+    /// Receipts can not be requested for messages that are sent over a nested or ratcheted
+    /// relationship.
+    pub fn seal_message_with_receipt(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>, Digest), Error> {
+        let mut digest = Default::default();
+        let (endpoint, tsp_message) = self.seal_message_payload_and_hash(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::AcknowledgedContent(message),
+            Some(&mut digest),
+        )?;
+
+        Ok((endpoint, tsp_message, digest))
+    }
+
+    /// Seal a TSP message like [Store::seal_message], as part of the conversation `thread_id`;
+    /// optionally as a reply to the message with digest `reply_to`.
+    /// Also returns the digest of the message, which a reply can refer to.
+    ///
+    /// Threaded messages can not be sent over a nested or ratcheted relationship.
+    pub fn seal_threaded_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        thread_id: Digest,
+        reply_to: Option<Digest>,
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>, Digest), Error> {
+        let mut digest = Default::default();
+        let (endpoint, tsp_message) = self.seal_message_payload_and_hash(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::ThreadedContent {
+                thread_id,
+                reply_to,
+                data: message,
+            },
+            Some(&mut digest),
+        )?;
+
+        Ok((endpoint, tsp_message, digest))
+    }
+
+    /// Seal a receipt for the message with digest `message_digest` that `sender` received
+    /// from `receiver`
+    pub fn seal_receipt(
+        &self,
+        sender: &str,
+        receiver: &str,
+        message_digest: Digest,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        self.seal_message_payload(sender, receiver, None, Payload::Receipt { message_digest })
+    }
+
+    /// Seal the acceptance of the relationship `thread_id` that `receiver` requested from
+    /// `sender`; if a ratchet was proposed, it is prepared and returned as well. The
+    /// relationship only changes once the acceptance is sent (see [Store::accept_relationship])
+    pub(crate) fn seal_relationship_accept(
+        &self,
+        sender: &str,
+        receiver: &str,
+        thread_id: Digest,
+    ) -> Result<(url::Url, Vec<u8>, Option<Ratchet>), Error> {
+        self.check_relationship(receiver, RelationshipAction::Accept(thread_id))?;
+
+        let (ratchet, ratchet_secret) = match self.get_vid(receiver)?.ratchet {
+            Some(RatchetState::Offered(ratchet_key)) => {
+                let (ratchet, secret) = Ratchet::accept(&ratchet_key, &thread_id)?;
+                (Some(ratchet), Some(secret))
+            }
+            // do not accept the relationship without the ratchet that was offered
+            Some(RatchetState::Lost) => {
+                return Err(Error::Relationship(
+                    "the ratchet that was offered was lost, it has to be requested again".into(),
+                ))
+            }
+            _ => (None, None),
+        };
+
+        let (endpoint, tsp_message) = self.seal_message_payload(
+            sender,
+            receiver,
+            None,
+            Payload::AcceptRelationship {
+                thread_id,
+                ratchet_secret: ratchet_secret.as_deref(),
+            },
+        )?;
+
+        Ok((endpoint, tsp_message, ratchet))
+    }
+
+    /// Seal the rejection of the relationship `thread_id` that `receiver` requested from
+    /// `sender`; the relationship only changes once the rejection is sent
+    /// (see [Store::reject_relationship])
+    pub(crate) fn seal_relationship_reject(
+        &self,
+        sender: &str,
+        receiver: &str,
+        thread_id: Digest,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        self.check_relationship(receiver, RelationshipAction::Reject(thread_id))?;
+
+        self.seal_message_payload(
+            sender,
+            receiver,
+            None,
+            Payload::RejectRelationship { thread_id },
+        )
+    }
+
+    /// Seal the cancellation of the relationship between `sender` and `receiver`; the
+    /// relationship only changes once the cancellation is sent
+    /// (see [Store::cancel_relationship])
+    pub(crate) fn seal_relationship_cancel(
+        &self,
+        sender: &str,
+        receiver: &str,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let status = self.check_relationship(receiver, RelationshipAction::Cancel)?;
+
+        // only relationships with a thread id can be cancelled
+        let thread_id = status.thread_id().ok_or(Error::Internal)?;
+
+        self.seal_message_payload(
+            sender,
+            receiver,
+            None,
+            Payload::CancelRelationship { thread_id },
         )
     }
 
+    /// Seal a request for a direct relationship from `sender` to `receiver`, and record the
+    /// request; also returns its thread id. The relationship changes as soon as the request is
+    /// sealed, as the caller sends it: if it can not be sent, it can be withdrawn with
+    /// [Store::make_relationship_cancel]. Use [crate::AsyncStore] to send relationship control
+    /// messages and only change the relationship once they are sent.
+    pub fn make_relationship_request(
+        &self,
+        sender: &str,
+        receiver: &str,
+    ) -> Result<(url::Url, Vec<u8>, Digest), Error> {
+        self.check_relationship(receiver, RelationshipAction::Request(Default::default()))?;
+
+        let mut thread_id = Default::default();
+        let (endpoint, tsp_message) = self.seal_message_payload_and_hash(
+            sender,
+            receiver,
+            None,
+            Payload::RequestRelationship {
+                route: None,
+                ratchet_key: None,
+            },
+            Some(&mut thread_id),
+        )?;
+
+        self.request_relationship(receiver, thread_id, None)?;
+
+        Ok((endpoint, tsp_message, thread_id))
+    }
+
+    /// Seal the acceptance of the relationship `thread_id` that `receiver` requested from
+    /// `sender`, and accept it, like [Store::make_relationship_request]
+    pub fn make_relationship_accept(
+        &self,
+        sender: &str,
+        receiver: &str,
+        thread_id: Digest,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let (endpoint, tsp_message, ratchet) =
+            self.seal_relationship_accept(sender, receiver, thread_id)?;
+
+        self.accept_relationship(receiver, thread_id, ratchet)?;
+
+        Ok((endpoint, tsp_message))
+    }
+
+    /// Seal the rejection of the relationship `thread_id` that `receiver` requested from
+    /// `sender`, and reject it, like [Store::make_relationship_request]
+    pub fn make_relationship_reject(
+        &self,
+        sender: &str,
+        receiver: &str,
+        thread_id: Digest,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let (endpoint, tsp_message) = self.seal_relationship_reject(sender, receiver, thread_id)?;
+
+        self.reject_relationship(receiver, thread_id)?;
+
+        Ok((endpoint, tsp_message))
+    }
+
+    /// Seal the cancellation of the relationship between `sender` and `receiver`, and cancel
+    /// it, like [Store::make_relationship_request]
+    pub fn make_relationship_cancel(
+        &self,
+        sender: &str,
+        receiver: &str,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let (endpoint, tsp_message) = self.seal_relationship_cancel(sender, receiver)?;
+
+        self.cancel_relationship(receiver)?;
+
+        Ok((endpoint, tsp_message))
+    }
+
     /// Seal a TSP message.
     pub(crate) fn seal_message_payload(
         &self,
@@ -306,9 +821,23 @@ impl Store {
         receiver: &str,
         nonconfidential_data: Option<&[u8]>,
         payload: Payload<&[u8]>,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        self.seal_message_payload_and_hash(sender, receiver, nonconfidential_data, payload, None)
+    }
+
+    /// Seal a TSP message; if `digest` is given, the hash of the plaintext payload that is
+    /// encrypted for the receiver is stored in it
+    fn seal_message_payload_and_hash(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        payload: Payload<&[u8]>,
+        digest: Option<&mut Digest>,
     ) -> Result<(url::Url, Vec<u8>), Error> {
         let sender = self.get_private_vid(sender)?;
         let receiver_context = self.get_vid(receiver)?;
+        receiver_context.check_ratchet(&payload)?;
 
         // send routed mode
         if let Some(intermediaries) = receiver_context.get_route() {
@@ -321,11 +850,12 @@ impl Store {
                 (Some(first_sender), Some(inner_sender)) => {
                     let inner_sender = self.get_private_vid(inner_sender)?;
 
-                    let tsp_message: Vec<u8> = crate::crypto::seal(
+                    let tsp_message: Vec<u8> = seal_and_hash(
                         &*inner_sender,
                         &*receiver_context.vid,
                         nonconfidential_data,
                         payload,
+                        digest,
                     )?;
 
                     let first_sender = self.get_private_vid(first_sender)?;
@@ -361,6 +891,14 @@ impl Store {
                 return Err(VidError::ResolveVid("missing sender VID for receiver").into());
             };
 
+            // the inner message of a nested relationship only carries the message content, any
+            // other payload would lose its meaning (and protection, like its freshness)
+            if !matches!(payload, Payload::Content(_)) {
+                return Err(Error::Relationship(
+                    "this message type can not be sent over a nested relationship".into(),
+                ));
+            }
+
             let sender_context = self.get_vid(inner_sender)?;
 
             let Some(parent_sender) = sender_context.get_parent_vid() else {
@@ -371,6 +909,7 @@ impl Store {
             let inner_message = crate::crypto::sign(
                 &*inner_sender,
                 Some(&*receiver_context.vid),
+                None,
                 payload.as_bytes(),
             )?;
 
@@ -388,16 +927,109 @@ impl Store {
         }
 
         // send direct mode
-        let tsp_message = crate::crypto::seal(
+        let tsp_message = seal_and_hash(
             &*sender,
             &*receiver_context.vid,
             nonconfidential_data,
             payload,
+            digest,
         )?;
 
         Ok((receiver_context.vid.endpoint().clone(), tsp_message))
     }
 
+    /// Encrypt a message for all of the `receivers` at once; the resulting TSP message can be
+    /// sent to each of them, and only the content key is encrypted per receiver
+    pub fn seal_multicast(
+        &self,
+        sender: &str,
+        receivers: impl IntoIterator<Item = impl AsRef<str>>,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let sender = self.get_private_vid(sender)?;
+        let receivers = receivers
+            .into_iter()
+            .map(|vid| {
+                let context = self.get_vid(vid.as_ref())?;
+                context.check_ratchet(&Payload::Content(message))?;
+
+                Ok(context.vid)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let receivers = receivers.iter().map(|vid| &**vid).collect::<Vec<_>>();
+
+        let tsp_message =
+            crate::crypto::seal_multicast(&*sender, &receivers, nonconfidential_data, message)?;
+
+        Ok(tsp_message)
+    }
+
+    /// Verify the outer signature of a TSP `message` without decrypting it, and return its
+    /// sender and non-confidential data. Only the sender has to be a verified VID, so
+    /// intermediaries can authenticate metadata of messages that are not addressed to them.
+    pub fn verify_nonconfidential_data<'a>(
+        &self,
+        message: &'a [u8],
+    ) -> Result<(String, Option<&'a [u8]>), Error> {
+        let (sender, _) = crate::cesr::get_sender_receiver(message)?;
+        let sender = String::from_utf8(sender.to_vec())?;
+
+        let Ok(sender_vid) = self.get_verified_vid(&sender) else {
+            return Err(Error::UnverifiedVid(sender));
+        };
+
+        let nonconfidential_data =
+            crate::crypto::verify_nonconfidential_data(&*sender_vid, message)?;
+
+        Ok((sender, nonconfidential_data))
+    }
+
+    /// Create archival [Evidence] of who sent the raw TSP `message`; `message` has to be the
+    /// original bytes of the message, as it was before it was opened. The sender is taken
+    /// from the message, and has to be a verified VID that signed the message.
+    pub fn create_evidence(&self, message: &[u8]) -> Result<Evidence, Error> {
+        let (sender, _) = crate::cesr::get_sender_receiver(message)?;
+        let sender = std::str::from_utf8(sender)?;
+
+        let Ok(sender_vid) = self.get_verified_vid(sender) else {
+            return Err(Error::UnverifiedVid(sender.to_string()));
+        };
+
+        Ok(Evidence::new(Vid::from_verified_vid(sender_vid), message)?)
+    }
+
+    /// Verify [Evidence] against the VID of its sender in this store, without resolving the
+    /// sender again; returns the non-confidential data of the message (see [Evidence::verify])
+    pub fn verify_evidence<'a>(&self, evidence: &'a Evidence) -> Result<Option<&'a [u8]>, Error> {
+        let sender = evidence.sender().identifier();
+
+        let Ok(sender_vid) = self.get_verified_vid(sender) else {
+            return Err(Error::UnverifiedVid(sender.to_string()));
+        };
+
+        Ok(evidence.verify(&*sender_vid)?)
+    }
+
+    /// Sign a unencrypted message for `receiver`: the message and the non-confidential data
+    /// are authenticated, but not encrypted. The receiver only accepts the message if it
+    /// is addressed to one of its VIDs.
+    pub fn sign_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let sender = self.get_private_vid(sender)?;
+        let receiver = self.get_verified_vid(receiver)?;
+
+        let tsp_message =
+            crate::crypto::sign(&*sender, Some(&*receiver), nonconfidential_data, message)?;
+
+        Ok((receiver.endpoint().clone(), tsp_message))
+    }
+
     /// Sign a unencrypted message, without a specified recipient
     pub fn sign_anycast(&self, sender: &str, message: &[u8]) -> Result<Vec<u8>, Error> {
         self.sign_anycast_payload(sender, Payload::Content(message))
@@ -410,7 +1042,7 @@ impl Store {
         payload: Payload<&[u8]>,
     ) -> Result<Vec<u8>, Error> {
         let sender = self.get_private_vid(sender)?;
-        let message = crate::crypto::sign(&*sender, None, payload.as_bytes())?;
+        let message = crate::crypto::sign(&*sender, None, None, payload.as_bytes())?;
 
         Ok(message)
     }
@@ -496,154 +1128,463 @@ impl Store {
         }
     }
 
+    /// Check that a nested message from `inner_sender` to `inner_receiver` belongs to the relationship
+    /// between `outer_sender` and `outer_receiver`, i.e. the inner VIDs are children of the outer VIDs
+    /// and the inner receiver (if it has a relation set) expects messages from the inner sender.
+    fn check_nested_relation(
+        &self,
+        outer_sender: &str,
+        outer_receiver: &str,
+        inner_sender: &str,
+        inner_receiver: Option<&str>,
+    ) -> Result<(), Error> {
+        let mismatch = || {
+            Error::NestedRelationMismatch(
+                inner_sender.to_string(),
+                inner_receiver.unwrap_or_default().to_string(),
+            )
+        };
+
+        let Some(inner_receiver) = inner_receiver else {
+            return Err(mismatch());
+        };
+
+        let sender_context = self.get_vid(inner_sender)?;
+        let receiver_context = self.get_vid(inner_receiver)?;
+
+        if receiver_context.get_parent_vid() != Some(outer_receiver)
+            || sender_context.get_parent_vid() != Some(outer_sender)
+        {
+            return Err(mismatch());
+        }
+
+        if receiver_context
+            .get_relation_vid()
+            .is_some_and(|relation| relation != inner_sender)
+        {
+            return Err(mismatch());
+        }
+
+        Ok(())
+    }
+
     /// Decode an encrypted `message``, which has to be addressed to one of the VIDs in `receivers`, and has to have
     /// `verified_vids` as one of the senders.
-    pub fn open_message(self, message: &mut [u8]) -> Result<ReceivedTspMessage, Error> {
+    pub fn open_message(&self, message: &mut [u8]) -> Result<ReceivedTspMessage, Error> {
+        self.open_message_borrowed(message)?.into_decompressed()
+    }
+
+    /// Decode an encrypted `message` in place, like [Store::open_message], but without copying
+    /// its contents: the returned message borrows from `message`. Nested messages are opened
+    /// in place as well. Compressed contents are returned as they are, in a
+    /// [ReceivedTspMessage::CompressedMessage].
+    ///
+    /// With the `cesr-t` feature, messages in the CESR text domain are accepted as well.
+    pub fn open_message_borrowed<'a>(
+        &self,
+        message: &'a mut [u8],
+    ) -> Result<ReceivedTspMessage<&'a [u8]>, Error> {
+        self.open_message_deciding(message, &mut |_, _| RelationshipDecision::Defer)
+    }
+
+    /// Decode an encrypted `message` in place, like [Store::open_message_borrowed]; before a
+    /// relationship request changes the relationship, `decide` is asked about it, given the
+    /// VID that received the request and its sender. An ignored request leaves the
+    /// relationship unchanged.
+    pub(crate) fn open_message_deciding<'a>(
+        &self,
+        message: &'a mut [u8],
+        decide: &mut dyn FnMut(&str, &str) -> RelationshipDecision,
+    ) -> Result<ReceivedTspMessage<&'a [u8]>, Error> {
+        #[cfg(feature = "cesr-t")]
+        let message = crate::cesr::to_binary(message)
+            .ok_or(crate::cesr::error::DecodeError::UnexpectedData)?;
+
         let probed_message = crate::cesr::probe(message)?;
 
-        match probed_message {
-            EnvelopeType::EncryptedMessage {
-                sender,
-                receiver: intended_receiver,
-            } => {
-                let intended_receiver = std::str::from_utf8(intended_receiver)?;
+        let (sender, intended_receiver, (nonconfidential_data, payload, raw_bytes)) =
+            match probed_message {
+                EnvelopeType::EncryptedMessage {
+                    sender,
+                    receiver: intended_receiver,
+                } => {
+                    let intended_receiver = std::str::from_utf8(intended_receiver)?;
 
-                let Ok(intended_receiver) = self.get_private_vid(intended_receiver) else {
-                    return Err(CryptoError::UnexpectedRecipient.into());
-                };
+                    let Ok(intended_receiver) = self.get_private_vid(intended_receiver) else {
+                        return Err(CryptoError::UnexpectedRecipient.into());
+                    };
 
-                let sender = String::from_utf8(sender.to_vec())?;
+                    let sender = String::from_utf8(sender.to_vec())?;
 
-                let Ok(sender_vid) = self.get_verified_vid(&sender) else {
-                    return Err(Error::UnverifiedVid(sender));
-                };
+                    let Ok(sender_vid) = self.get_verified_vid(&sender) else {
+                        return Err(Error::UnverifiedVid(sender));
+                    };
 
-                let (nonconfidential_data, payload, raw_bytes) =
-                    crate::crypto::open(&*intended_receiver, &*sender_vid, message)?;
+                    let contents = crate::crypto::open(&*intended_receiver, &*sender_vid, message)?;
 
-                match payload {
-                    Payload::Content(message) => Ok(ReceivedTspMessage::GenericMessage {
-                        sender,
-                        nonconfidential_data: nonconfidential_data.map(|v| v.to_vec()),
-                        message: message.to_owned(),
-                        message_type: MessageType::SignedAndEncrypted,
-                    }),
-                    Payload::NestedMessage(message) => {
-                        // TODO: do not allocate
-                        let mut inner = message.to_owned();
-
-                        let mut received_message = self.open_message(&mut inner)?;
-                        if let ReceivedTspMessage::GenericMessage {
-                            ref mut message_type,
-                            ..
-                        } = received_message
-                        {
-                            *message_type = MessageType::SignedAndEncrypted;
-                        }
+                    (sender, intended_receiver, contents)
+                }
+                EnvelopeType::HiddenSenderMessage {
+                    receiver: intended_receiver,
+                } => {
+                    let intended_receiver = std::str::from_utf8(intended_receiver)?;
 
-                        Ok(received_message)
-                    }
-                    Payload::RoutedMessage(hops, message) => {
-                        let next_hop = std::str::from_utf8(hops[0])?;
+                    let Ok(intended_receiver) = self.get_private_vid(intended_receiver) else {
+                        return Err(CryptoError::UnexpectedRecipient.into());
+                    };
 
-                        let Ok(next_hop) = self.get_verified_vid(next_hop) else {
-                            return Err(Error::UnverifiedVid(next_hop.to_string()));
-                        };
+                    // the sender is only known after decryption
+                    let opened = crate::crypto::open_hidden_sender(&*intended_receiver, message)?;
+                    let sender = String::from_utf8(opened.sender().to_vec())?;
 
-                        Ok(ReceivedTspMessage::ForwardRequest {
-                            sender,
-                            next_hop: next_hop.identifier().to_string(),
-                            route: hops[1..].iter().map(|x| x.to_vec()).collect(),
-                            opaque_payload: message.to_owned(),
-                        })
-                    }
-                    Payload::RequestRelationship { route } => {
-                        Ok(ReceivedTspMessage::RequestRelationship {
-                            sender,
-                            route: route.map(|vec| vec.iter().map(|vid| vid.to_vec()).collect()),
-                            thread_id: crate::crypto::sha256(raw_bytes),
-                        })
+                    let Ok(sender_vid) = self.get_verified_vid(&sender) else {
+                        return Err(Error::UnverifiedVid(sender));
+                    };
+
+                    (sender, intended_receiver, opened.verify(&*sender_vid)?)
+                }
+                EnvelopeType::MulticastMessage { sender } => {
+                    let sender = String::from_utf8(sender.to_vec())?;
+
+                    let Ok(sender_vid) = self.get_verified_vid(&sender) else {
+                        return Err(Error::UnverifiedVid(sender));
+                    };
+
+                    let mut receiver = None;
+                    for vid in crate::cesr::get_multicast_recipients(message)? {
+                        let vid = std::str::from_utf8(vid)?;
+                        if let Ok(private_vid) = self.get_private_vid(vid) {
+                            receiver = Some(private_vid);
+                            break;
+                        }
                     }
-                    Payload::AcceptRelationship { thread_id } => {
-                        let mut vids = self.vids.write()?;
-                        let Some(context) = vids.get_mut(&sender) else {
-                            //TODO: should we inform the user of who sent this?
-                            return Err(Error::Relationship(
-                                "received confirmation of a relation with an unknown entity".into(),
-                            ));
-                        };
 
-                        let RelationshipStatus::Unidirectional(digest) = context.relation_status
-                        else {
-                            return Err(Error::Relationship(
-                                "received confirmation of a relation that we did not want".into(),
-                            ));
-                        };
+                    let Some(receiver) = receiver else {
+                        return Err(CryptoError::UnexpectedRecipient.into());
+                    };
 
-                        if thread_id != digest {
-                            return Err(Error::Relationship(
-                                "attempt to change the terms of the relationship".into(),
-                            ));
-                        }
+                    let (nonconfidential_data, payload, _) =
+                        crate::crypto::open_multicast(&*receiver, &*sender_vid, message)?;
 
-                        context.relation_status = RelationshipStatus::Bidirectional(digest);
+                    let Payload::Content(message) = payload else {
+                        return Err(crate::cesr::error::DecodeError::UnexpectedMsgType.into());
+                    };
 
-                        Ok(ReceivedTspMessage::AcceptRelationship { sender })
-                    }
-                    Payload::CancelRelationship { thread_id } => {
-                        if let Some(context) = self.vids.write()?.get_mut(&sender) {
-                            match context.relation_status {
-                                RelationshipStatus::Bidirectional(digest)
-                                | RelationshipStatus::Unidirectional(digest) => {
-                                    if thread_id != digest {
-                                        return Err(Error::Relationship(
-                                            "invalid attempt to end the relationship".into(),
-                                        ));
-                                    }
-                                    context.relation_status = RelationshipStatus::Unrelated;
-                                }
-                                _ => todo!(),
+                    return Ok(ReceivedTspMessage::MulticastMessage {
+                        sender,
+                        receiver: receiver.identifier().to_string(),
+                        nonconfidential_data,
+                        message,
+                    });
+                }
+                EnvelopeType::SignedMessage {
+                    sender,
+                    receiver: intended_receiver,
+                } => {
+                    let receiver = match intended_receiver {
+                        Some(intended_receiver) => {
+                            let intended_receiver = std::str::from_utf8(intended_receiver)?;
+
+                            if !self.has_private_vid(intended_receiver)? {
+                                return Err(CryptoError::UnexpectedRecipient.into());
                             }
+
+                            Some(intended_receiver.to_string())
                         }
+                        None => None,
+                    };
 
-                        Ok(ReceivedTspMessage::CancelRelationship { sender })
-                    }
+                    let sender = String::from_utf8(sender.to_vec())?;
+
+                    let Ok(sender_vid) = self.get_verified_vid(&sender) else {
+                        return Err(Error::UnverifiedVid(sender.to_string()));
+                    };
+
+                    let (nonconfidential_data, payload) =
+                        crate::crypto::verify(&*sender_vid, message)?;
+
+                    return Ok(ReceivedTspMessage::GenericMessage {
+                        sender,
+                        receiver,
+                        nonconfidential_data,
+                        message: payload,
+                        message_type: MessageType::Signed,
+                        nested_in: Vec::new(),
+                        receipt_requested: None,
+                        thread: None,
+                    });
                 }
-            }
-            EnvelopeType::SignedMessage {
+            };
+
+        match payload {
+            Payload::Content(message) => Ok(ReceivedTspMessage::GenericMessage {
                 sender,
-                receiver: intended_receiver,
+                receiver: Some(intended_receiver.identifier().to_string()),
+                nonconfidential_data,
+                message,
+                message_type: MessageType::SignedAndEncrypted,
+                nested_in: Vec::new(),
+                receipt_requested: None,
+                thread: None,
+            }),
+            Payload::FreshContent {
+                message_id,
+                timestamp,
+                data,
             } => {
-                if let Some(intended_receiver) = intended_receiver {
-                    let intended_receiver = std::str::from_utf8(intended_receiver)?;
+                self.replay_caches
+                    .write()?
+                    .entry(sender.clone())
+                    .or_default()
+                    .check(&message_id, timestamp, crate::freshness::now())?;
 
-                    if !self.has_private_vid(intended_receiver)? {
-                        return Err(CryptoError::UnexpectedRecipient.into());
-                    }
-                };
+                Ok(ReceivedTspMessage::GenericMessage {
+                    sender,
+                    receiver: Some(intended_receiver.identifier().to_string()),
+                    nonconfidential_data,
+                    message: data,
+                    message_type: MessageType::SignedAndEncrypted,
+                    nested_in: Vec::new(),
+                    receipt_requested: None,
+                    thread: None,
+                })
+            }
+            Payload::AcknowledgedContent(message) => {
+                // the same digest as the sender obtained using `seal_and_hash`
+                let digest = crate::crypto::sha256_concat(&[raw_bytes, message]);
+
+                Ok(ReceivedTspMessage::GenericMessage {
+                    sender,
+                    receiver: Some(intended_receiver.identifier().to_string()),
+                    nonconfidential_data,
+                    message,
+                    message_type: MessageType::SignedAndEncrypted,
+                    nested_in: Vec::new(),
+                    receipt_requested: Some(digest),
+                    thread: None,
+                })
+            }
+            Payload::ThreadedContent {
+                thread_id,
+                reply_to,
+                data,
+            } => Ok(ReceivedTspMessage::GenericMessage {
+                sender,
+                receiver: Some(intended_receiver.identifier().to_string()),
+                nonconfidential_data,
+                message: data,
+                message_type: MessageType::SignedAndEncrypted,
+                nested_in: Vec::new(),
+                receipt_requested: None,
+                thread: Some(MessageThread {
+                    thread_id,
+                    reply_to,
+                    message_digest: crate::crypto::sha256_concat(&[raw_bytes, data]),
+                }),
+            }),
+            Payload::Receipt { message_digest } => Ok(ReceivedTspMessage::Receipt {
+                sender,
+                message_digest,
+            }),
+            // the contents are decompressed when they are copied (see [Store::open_message])
+            Payload::CompressedContent(message) => Ok(ReceivedTspMessage::CompressedMessage {
+                sender,
+                receiver: intended_receiver.identifier().to_string(),
+                nonconfidential_data,
+                message,
+            }),
+            Payload::NestedMessage(message) => {
+                // the inner message is checked before it is opened, so that inner messages of any
+                // type that do not belong to this relationship can not change any state
+                let (inner_sender, inner_receiver) = crate::cesr::get_sender_receiver(message)?;
+                self.check_nested_relation(
+                    &sender,
+                    intended_receiver.identifier(),
+                    std::str::from_utf8(inner_sender)?,
+                    inner_receiver.map(std::str::from_utf8).transpose()?,
+                )?;
+
+                let mut received_message = self.open_message_deciding(message, &mut *decide)?;
+                if let ReceivedTspMessage::GenericMessage {
+                    ref mut message_type,
+                    ref mut nested_in,
+                    ..
+                } = received_message
+                {
+                    *message_type = MessageType::SignedAndEncrypted;
+                    nested_in.insert(
+                        0,
+                        ReceivedEnvelope {
+                            sender,
+                            receiver: intended_receiver.identifier().to_string(),
+                            nonconfidential_data,
+                        },
+                    );
+                }
+
+                Ok(received_message)
+            }
+            Payload::RoutedMessage(hops, message) => {
+                let next_hop = std::str::from_utf8(hops[0])?;
 
-                let sender = String::from_utf8(sender.to_vec())?;
+                let Ok(next_hop) = self.get_verified_vid(next_hop) else {
+                    return Err(Error::UnverifiedVid(next_hop.to_string()));
+                };
 
-                let Ok(sender_vid) = self.get_verified_vid(&sender) else {
-                    return Err(Error::UnverifiedVid(sender.to_string()));
+                Ok(ReceivedTspMessage::ForwardRequest {
+                    sender,
+                    next_hop: next_hop.identifier().to_string(),
+                    route: hops[1..].to_vec(),
+                    opaque_payload: message,
+                })
+            }
+            Payload::StreamChunk {
+                stream_id,
+                index,
+                last,
+                data,
+            } => Ok(ReceivedTspMessage::StreamChunk {
+                sender,
+                stream_id,
+                index,
+                last,
+                data,
+            }),
+            Payload::RatchetedContent { index, data } => {
+                let mut vids = self.vids.write()?;
+                let Some(RatchetState::Established(ratchet)) = vids
+                    .get_mut(&sender)
+                    .and_then(|context| context.ratchet.as_mut())
+                else {
+                    return Err(Error::Relationship(
+                        "received a ratcheted message outside of a ratcheted relationship".into(),
+                    ));
                 };
 
-                let payload = crate::crypto::verify(&*sender_vid, message)?;
+                let message: &[u8] = ratchet.open(index, data)?;
 
                 Ok(ReceivedTspMessage::GenericMessage {
                     sender,
-                    nonconfidential_data: None,
-                    message: payload.to_owned(),
-                    message_type: MessageType::Signed,
+                    receiver: Some(intended_receiver.identifier().to_string()),
+                    nonconfidential_data,
+                    message,
+                    message_type: MessageType::SignedAndEncrypted,
+                    nested_in: Vec::new(),
+                    receipt_requested: None,
+                    thread: None,
+                })
+            }
+            Payload::RequestRelationship { route, ratchet_key } => {
+                let thread_id = crate::crypto::sha256(raw_bytes);
+
+                // an ignored request leaves no trace in the relationship
+                let decision = decide(intended_receiver.identifier(), &sender);
+
+                // a ratchet that is offered is set up when the relationship is accepted
+                if decision != RelationshipDecision::Ignore {
+                    self.update_relationship_with(
+                        &sender,
+                        RelationshipAction::ReceiveRequest(thread_id),
+                        |context| {
+                            if let Some(RatchetState::Established(_)) = context.ratchet {
+                                return Err(Error::Relationship(
+                                    "received a request for a relationship that has a ratchet"
+                                        .into(),
+                                ));
+                            }
+
+                            context.ratchet = ratchet_key.map(|key| RatchetState::Offered(*key));
+
+                            Ok(())
+                        },
+                    )?;
+                }
+
+                Ok(ReceivedTspMessage::RequestRelationship {
+                    sender,
+                    route,
+                    thread_id,
                 })
             }
+            Payload::AcceptRelationship {
+                thread_id,
+                ratchet_secret,
+            } => {
+                self.update_relationship_with(
+                    &sender,
+                    RelationshipAction::ReceiveAccept(thread_id),
+                    |context| {
+                        context.ratchet = match (context.ratchet.take(), ratchet_secret) {
+                            (Some(RatchetState::Proposed(private_key)), Some(ratchet_secret)) => {
+                                Some(RatchetState::Established(Ratchet::complete(
+                                    &private_key,
+                                    ratchet_secret,
+                                    &thread_id,
+                                )?))
+                            }
+                            (Some(RatchetState::Lost), Some(_)) => {
+                                return Err(Error::Relationship(
+                                    "the ratchet that we proposed was lost".into(),
+                                ))
+                            }
+                            (_, Some(_)) => {
+                                return Err(Error::Relationship(
+                                    "received a ratchet that we did not propose".into(),
+                                ))
+                            }
+                            // the other party does not use the ratchet we proposed
+                            (_, None) => None,
+                        };
+
+                        Ok(())
+                    },
+                )?;
+
+                Ok(ReceivedTspMessage::AcceptRelationship { sender })
+            }
+            Payload::RejectRelationship { thread_id } => {
+                self.update_relationship(&sender, RelationshipAction::ReceiveReject(thread_id))?;
+
+                Ok(ReceivedTspMessage::RejectRelationship { sender })
+            }
+            Payload::CancelRelationship { thread_id } => {
+                self.update_relationship(&sender, RelationshipAction::ReceiveCancel(thread_id))?;
+
+                Ok(ReceivedTspMessage::CancelRelationship { sender })
+            }
+        }
+    }
+}
+
+/// Seal a TSP message; if `digest` is given, the hash of the plaintext payload is stored in it
+fn seal_and_hash(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<&[u8]>,
+    payload: Payload<&[u8]>,
+    digest: Option<&mut Digest>,
+) -> Result<Vec<u8>, CryptoError> {
+    match digest {
+        Some(digest) => {
+            let (tsp_message, message_digest) =
+                crate::crypto::seal_and_hash(sender, receiver, nonconfidential_data, payload)?;
+            *digest = message_digest;
+
+            Ok(tsp_message)
         }
+        None => crate::crypto::seal(sender, receiver, nonconfidential_data, payload),
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{definitions::MessageType, OwnedVid, ReceivedTspMessage, Store, VerifiedVid};
+    use crate::{
+        crypto::{CryptoError, RatchetState},
+        definitions::{MessageType, Payload},
+        relationship::{RelationshipDecision, RelationshipEvent, RelationshipStatus},
+        Error, Evidence, OwnedVid, ReceivedTspMessage, Store, VerifiedVid,
+    };
+    use std::time::Duration;
 
     fn new_vid() -> OwnedVid {
         OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap())
@@ -716,4 +1657,1199 @@ mod test {
             panic!("unexpected message type");
         }
     }
+
+    #[test]
+    fn test_sign_message() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, signed) = store
+            .sign_message(
+                alice.identifier(),
+                bob.identifier(),
+                Some(b"metadata"),
+                b"hello world",
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            receiver,
+            nonconfidential_data,
+            message,
+            message_type,
+            ..
+        } = store.open_message(&mut signed.clone()).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(receiver.as_deref(), Some(bob.identifier()));
+        assert_eq!(nonconfidential_data.as_deref(), Some(&b"metadata"[..]));
+        assert_eq!(message, b"hello world");
+        assert_eq!(message_type, MessageType::Signed);
+
+        // only the receiver accepts the message
+        let other_store = Store::new();
+        other_store.add_private_vid(new_vid()).unwrap();
+        other_store.add_verified_vid(alice.vid().clone()).unwrap();
+        assert!(matches!(
+            other_store.open_message(&mut signed.clone()),
+            Err(Error::Crypto(CryptoError::UnexpectedRecipient))
+        ));
+    }
+
+    #[test]
+    fn test_open_seal_hidden_sender() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, sealed) = store
+            .seal_message_hidden_sender(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+
+        // the sender can only be identified after decryption
+        let mut probed = sealed.clone();
+        assert!(matches!(
+            crate::cesr::probe(&mut probed).unwrap(),
+            crate::cesr::EnvelopeType::HiddenSenderMessage { receiver }
+                if receiver == bob.identifier().as_bytes()
+        ));
+
+        let received = store.open_message(&mut sealed.clone()).unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            receiver,
+            message,
+            message_type,
+            ..
+        } = received
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(receiver.as_deref(), Some(bob.identifier()));
+        assert_eq!(message, b"hello world");
+        assert_eq!(message_type, MessageType::SignedAndEncrypted);
+
+        // the sender has to be known to verify its signature
+        store.forget_vid(alice.identifier()).unwrap();
+        assert!(matches!(
+            store.open_message(&mut sealed.clone()),
+            Err(Error::UnverifiedVid(_))
+        ));
+    }
+
+    #[test]
+    fn test_cancel_without_relationship() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, mut sealed) = store
+            .seal_message_payload(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                Payload::CancelRelationship { thread_id: [0; 32] },
+            )
+            .unwrap();
+
+        assert!(matches!(
+            store.open_message(&mut sealed),
+            Err(Error::Relationship(_))
+        ));
+    }
+
+    #[test]
+    fn test_open_seal_compressed() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let message = "hello world ".repeat(100);
+
+        let (_, sealed) = store
+            .seal_compressed_message(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                message.as_bytes(),
+            )
+            .unwrap();
+
+        assert!(sealed.len() < message.len());
+
+        let received = store.open_message(&mut sealed.clone()).unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            message: received_message,
+            ..
+        } = received
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(received_message, message.as_bytes());
+
+        // the borrowed message is not decompressed
+        let mut sealed = sealed;
+        let buffer = sealed.as_ptr_range();
+        let ReceivedTspMessage::CompressedMessage {
+            message: compressed,
+            ..
+        } = store.open_message_borrowed(&mut sealed).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert!(buffer.contains(&compressed.as_ptr()));
+        assert_eq!(
+            crate::compression::decompress(compressed, message.len()).unwrap(),
+            message.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_open_seal_fresh() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, sealed) = store
+            .seal_fresh_message(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+
+        let received = store.open_message(&mut sealed.clone()).unwrap();
+
+        let ReceivedTspMessage::GenericMessage { message, .. } = received else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(message, b"hello world");
+
+        // the same message can not be opened twice
+        assert!(matches!(
+            store.open_message(&mut sealed.clone()),
+            Err(Error::Replay(_))
+        ));
+
+        // but a new message with the same content can
+        let (_, sealed) = store
+            .seal_fresh_message(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+
+        assert!(store.open_message(&mut sealed.clone()).is_ok());
+    }
+
+    #[test]
+    fn test_receipt() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, sealed, digest) = store
+            .seal_message_with_receipt(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+
+        let received = store.open_message(&mut sealed.clone()).unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            receiver: Some(receiver),
+            receipt_requested: Some(message_digest),
+            ..
+        } = received
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(message_digest, digest);
+
+        let (_, mut receipt) = store
+            .seal_receipt(&receiver, &sender, message_digest)
+            .unwrap();
+
+        let ReceivedTspMessage::Receipt {
+            sender,
+            message_digest,
+        } = store.open_message(&mut receipt).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(sender, bob.identifier());
+        assert_eq!(message_digest, digest);
+
+        // sending the same message again must not result in the same digest
+        let (_, _, other_digest) = store
+            .seal_message_with_receipt(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+
+        assert_ne!(other_digest, digest);
+    }
+
+    #[test]
+    fn test_threaded_messages() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let thread_id = [7; 32];
+
+        let (_, sealed, digest) = store
+            .seal_threaded_message(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                thread_id,
+                None,
+                b"how are you?",
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            thread: Some(thread),
+            ..
+        } = store.open_message(&mut sealed.clone()).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(thread.thread_id, thread_id);
+        assert_eq!(thread.reply_to, None);
+        assert_eq!(thread.message_digest, digest);
+
+        let (_, _, second_digest) = store
+            .seal_threaded_message(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                thread_id,
+                None,
+                b"how are you?",
+            )
+            .unwrap();
+
+        assert_ne!(digest, second_digest);
+
+        let (_, mut reply, _) = store
+            .seal_threaded_message(
+                bob.identifier(),
+                alice.identifier(),
+                None,
+                thread.thread_id,
+                Some(thread.message_digest),
+                b"fine, thanks",
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            message,
+            thread: Some(thread),
+            ..
+        } = store.open_message(&mut reply).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(message, b"fine, thanks");
+        assert_eq!(thread.thread_id, thread_id);
+        assert_eq!(thread.reply_to, Some(digest));
+    }
+
+    #[cfg(feature = "cesr-t")]
+    #[test]
+    fn test_open_text() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, sealed) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+
+        let mut text = crate::cesr::to_text(&sealed).into_bytes();
+        assert!(text.is_ascii());
+
+        let ReceivedTspMessage::GenericMessage { message, .. } =
+            store.open_message(&mut text).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(message, b"hello world");
+    }
+
+    #[test]
+    fn test_open_nested_borrowed() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let nested_alice = new_vid();
+        let nested_bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+        store.add_private_vid(nested_alice.clone()).unwrap();
+        store.add_private_vid(nested_bob.clone()).unwrap();
+
+        store
+            .set_parent_for_vid(nested_alice.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(nested_bob.identifier(), Some(nested_alice.identifier()))
+            .unwrap();
+
+        let message = b"hello nested world";
+
+        let (_, mut sealed) = store
+            .seal_message(
+                nested_alice.identifier(),
+                nested_bob.identifier(),
+                None,
+                message,
+            )
+            .unwrap();
+
+        let buffer = sealed.as_ptr_range();
+        let received = store.open_message_borrowed(&mut sealed).unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            receiver,
+            message: received_message,
+            message_type,
+            nested_in,
+            ..
+        } = received
+        else {
+            panic!("unexpected message type");
+        };
+
+        assert_eq!(sender, nested_alice.identifier());
+        assert_eq!(receiver.as_deref(), Some(nested_bob.identifier()));
+        assert_eq!(received_message, message);
+        assert_eq!(message_type, MessageType::SignedAndEncrypted);
+        assert!(buffer.contains(&received_message.as_ptr()));
+
+        assert_eq!(nested_in.len(), 1);
+        assert_eq!(nested_in[0].sender, alice.identifier());
+        assert_eq!(nested_in[0].receiver, bob.identifier());
+    }
+
+    #[test]
+    fn test_open_nested_wrong_parent() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let carol = new_vid();
+        let nested_alice = new_vid();
+        let nested_bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+        store.add_private_vid(carol.clone()).unwrap();
+        store.add_private_vid(nested_alice.clone()).unwrap();
+        store.add_private_vid(nested_bob.clone()).unwrap();
+
+        store
+            .set_parent_for_vid(nested_alice.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(nested_bob.identifier(), Some(nested_alice.identifier()))
+            .unwrap();
+
+        let (_, sealed) = store
+            .seal_message(
+                nested_alice.identifier(),
+                nested_bob.identifier(),
+                None,
+                b"hello nested world",
+            )
+            .unwrap();
+
+        // the inner receiver now belongs to a different relationship than the envelope
+        store
+            .set_parent_for_vid(nested_bob.identifier(), Some(carol.identifier()))
+            .unwrap();
+
+        assert!(matches!(
+            store.open_message(&mut sealed.clone()),
+            Err(Error::NestedRelationMismatch(..))
+        ));
+
+        // the inner receiver expects messages from someone else
+        store
+            .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(nested_bob.identifier(), Some(carol.identifier()))
+            .unwrap();
+
+        assert!(matches!(
+            store.open_message(&mut sealed.clone()),
+            Err(Error::NestedRelationMismatch(..))
+        ));
+
+        store
+            .set_relation_for_vid(nested_bob.identifier(), Some(nested_alice.identifier()))
+            .unwrap();
+
+        assert!(store.open_message(&mut sealed.clone()).is_ok());
+    }
+
+    #[test]
+    fn test_nested_plain_content_only() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let nested_alice = new_vid();
+        let nested_bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+        store.add_private_vid(nested_alice.clone()).unwrap();
+        store.add_private_vid(nested_bob.clone()).unwrap();
+
+        store
+            .set_parent_for_vid(nested_alice.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(nested_bob.identifier(), Some(nested_alice.identifier()))
+            .unwrap();
+
+        let (sender, receiver) = (nested_alice.identifier(), nested_bob.identifier());
+
+        // the inner message would lose the meaning of these payloads
+        assert!(matches!(
+            store.seal_fresh_message(sender, receiver, None, b"hello"),
+            Err(Error::Relationship(_))
+        ));
+        assert!(matches!(
+            store.seal_compressed_message(sender, receiver, None, b"hello"),
+            Err(Error::Relationship(_))
+        ));
+        assert!(matches!(
+            store.seal_message_with_receipt(sender, receiver, None, b"hello"),
+            Err(Error::Relationship(_))
+        ));
+        assert!(matches!(
+            store.seal_message_payload(
+                sender,
+                receiver,
+                None,
+                Payload::StreamChunk {
+                    stream_id: [0; 32],
+                    index: 0,
+                    last: true,
+                    data: b"hello",
+                },
+            ),
+            Err(Error::Relationship(_))
+        ));
+
+        assert!(store.seal_message(sender, receiver, None, b"hello").is_ok());
+    }
+
+    #[test]
+    fn test_open_nested_control_message_wrong_parent() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let carol = new_vid();
+        let nested_alice = new_vid();
+        let nested_bob = new_vid();
+
+        store.add_private_vid(bob.clone()).unwrap();
+        store.add_private_vid(nested_bob.clone()).unwrap();
+        store.add_verified_vid(alice.vid().clone()).unwrap();
+        store.add_verified_vid(carol.vid().clone()).unwrap();
+        store.add_verified_vid(nested_alice.vid().clone()).unwrap();
+
+        store
+            .set_parent_for_vid(nested_alice.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+            .unwrap();
+
+        let inner = crate::crypto::seal(
+            &nested_alice,
+            nested_bob.vid(),
+            None,
+            Payload::RequestRelationship {
+                route: None,
+                ratchet_key: None,
+            },
+        )
+        .unwrap();
+
+        // carol wraps a relationship request of a VID that is nested in alice
+        let mut sealed =
+            crate::crypto::seal(&carol, bob.vid(), None, Payload::NestedMessage(&inner)).unwrap();
+
+        assert!(matches!(
+            store.open_message(&mut sealed),
+            Err(Error::NestedRelationMismatch(..))
+        ));
+        assert_eq!(
+            store
+                .get_vid(nested_alice.identifier())
+                .unwrap()
+                .relation_status,
+            RelationshipStatus::Unrelated
+        );
+
+        let sealed =
+            crate::crypto::seal(&alice, bob.vid(), None, Payload::NestedMessage(&inner)).unwrap();
+
+        // a nested request is decided about by the inner receiver
+        let mut decided = None;
+        store
+            .open_message_deciding(&mut sealed.clone(), &mut |receiver, sender| {
+                decided = Some((receiver.to_string(), sender.to_string()));
+
+                RelationshipDecision::Ignore
+            })
+            .unwrap();
+        assert_eq!(
+            decided,
+            Some((
+                nested_bob.identifier().to_string(),
+                nested_alice.identifier().to_string()
+            ))
+        );
+        assert_eq!(
+            store
+                .get_vid(nested_alice.identifier())
+                .unwrap()
+                .relation_status,
+            RelationshipStatus::Unrelated
+        );
+
+        // the envelope of a nested control message is not reported
+        let ReceivedTspMessage::RequestRelationship { sender, .. } =
+            store.open_message(&mut sealed.clone()).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, nested_alice.identifier());
+    }
+
+    #[test]
+    fn test_verify_nonconfidential_data() {
+        let store = Store::new();
+        let intermediary = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, sealed) = store
+            .seal_message(
+                alice.identifier(),
+                bob.identifier(),
+                Some(b"metadata"),
+                b"hello world",
+            )
+            .unwrap();
+
+        assert!(matches!(
+            intermediary.verify_nonconfidential_data(&sealed),
+            Err(Error::UnverifiedVid(_))
+        ));
+
+        // the intermediary only knows the public keys of the sender
+        intermediary.add_verified_vid(alice.vid().clone()).unwrap();
+        let (sender, data) = intermediary.verify_nonconfidential_data(&sealed).unwrap();
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(data, Some(&b"metadata"[..]));
+    }
+
+    #[test]
+    fn test_ratcheted_relationship() {
+        let alice_store = Store::new();
+        let bob_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        alice_store.add_private_vid(alice.clone()).unwrap();
+        alice_store.add_verified_vid(bob.vid().clone()).unwrap();
+        bob_store.add_private_vid(bob.clone()).unwrap();
+        bob_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let bob_events = bob_store.relationship_events().unwrap();
+
+        // alice requests a relationship and proposes a ratchet
+        let (ratchet, ratchet_key) = RatchetState::propose();
+        let (mut request, thread_id) = crate::crypto::seal_and_hash(
+            &alice,
+            bob.vid(),
+            None,
+            Payload::RequestRelationship {
+                route: None,
+                ratchet_key: Some(&ratchet_key),
+            },
+        )
+        .unwrap();
+        alice_store
+            .request_relationship(bob.identifier(), thread_id, Some(ratchet))
+            .unwrap();
+
+        let ReceivedTspMessage::RequestRelationship {
+            thread_id: received_thread_id,
+            ..
+        } = bob_store.open_message(&mut request).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(received_thread_id, thread_id);
+        assert_eq!(
+            bob_events.try_recv().unwrap(),
+            RelationshipEvent {
+                vid: alice.identifier().to_string(),
+                previous: RelationshipStatus::Unrelated,
+                status: RelationshipStatus::PendingIncoming(thread_id),
+            }
+        );
+
+        // bob accepts the relationship and the ratchet
+        let (_, mut accept, ratchet) = bob_store
+            .seal_relationship_accept(bob.identifier(), alice.identifier(), thread_id)
+            .unwrap();
+        assert!(ratchet.is_some());
+        assert!(bob_events.try_recv().is_err());
+
+        bob_store
+            .accept_relationship(alice.identifier(), thread_id, ratchet)
+            .unwrap();
+        assert_eq!(
+            bob_events.try_recv().unwrap().status,
+            RelationshipStatus::Bidirectional(thread_id)
+        );
+
+        assert!(matches!(
+            alice_store.open_message(&mut accept).unwrap(),
+            ReceivedTspMessage::AcceptRelationship { .. }
+        ));
+
+        for _ in 0..2 {
+            let (_, mut sealed) = alice_store
+                .seal_message(alice.identifier(), bob.identifier(), None, b"hello bob")
+                .unwrap();
+
+            // the keys of bob are not enough to read the message
+            let other_store = Store::new();
+            other_store.add_private_vid(bob.clone()).unwrap();
+            other_store.add_verified_vid(alice.vid().clone()).unwrap();
+            assert!(matches!(
+                other_store.open_message(&mut sealed.clone()),
+                Err(Error::Relationship(_))
+            ));
+
+            let ReceivedTspMessage::GenericMessage { message, .. } =
+                bob_store.open_message(&mut sealed).unwrap()
+            else {
+                panic!("unexpected message type");
+            };
+            assert_eq!(message, b"hello bob");
+
+            let (_, mut sealed) = bob_store
+                .seal_message(bob.identifier(), alice.identifier(), None, b"hello alice")
+                .unwrap();
+
+            let ReceivedTspMessage::GenericMessage { message, .. } =
+                alice_store.open_message(&mut sealed).unwrap()
+            else {
+                panic!("unexpected message type");
+            };
+            assert_eq!(message, b"hello alice");
+        }
+
+        // content is never sent without the ratchet
+        assert!(matches!(
+            alice_store.seal_fresh_message(alice.identifier(), bob.identifier(), None, b"hello"),
+            Err(Error::Relationship(_))
+        ));
+        assert!(matches!(
+            alice_store.seal_message_hidden_sender(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                b"hello"
+            ),
+            Err(Error::Relationship(_))
+        ));
+        assert!(matches!(
+            alice_store.seal_multicast(alice.identifier(), [bob.identifier()], None, b"hello"),
+            Err(Error::Relationship(_))
+        ));
+
+        // a message that fails to be sealed skips an index of the ratchet
+        assert!(matches!(
+            alice_store.seal_message(bob.identifier(), bob.identifier(), None, b"hello"),
+            Err(Error::MissingPrivateVid(_))
+        ));
+
+        let (_, mut sealed) = alice_store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello again")
+            .unwrap();
+        let ReceivedTspMessage::GenericMessage { message, .. } =
+            bob_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(message, b"hello again");
+
+        // the ratchet is not exported, so it has to be set up again after an import
+        let imported_store = Store::new();
+        imported_store
+            .import(alice_store.export().unwrap())
+            .unwrap();
+        assert!(matches!(
+            imported_store.seal_message(alice.identifier(), bob.identifier(), None, b"hello"),
+            Err(Error::Relationship(_))
+        ));
+        assert!(imported_store.cancel_relationship(bob.identifier()).is_ok());
+        assert!(imported_store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .is_ok());
+
+        // ending the relationship discards the ratchet
+        assert_eq!(
+            alice_store.cancel_relationship(bob.identifier()).unwrap(),
+            thread_id
+        );
+        let (_, mut sealed) = alice_store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+        let other_store = Store::new();
+        other_store.add_private_vid(bob.clone()).unwrap();
+        other_store.add_verified_vid(alice.vid().clone()).unwrap();
+        assert!(other_store.open_message(&mut sealed).is_ok());
+    }
+
+    #[test]
+    fn test_rejected_relationship() {
+        let alice_store = Store::new();
+        let bob_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        alice_store.add_private_vid(alice.clone()).unwrap();
+        alice_store.add_verified_vid(bob.vid().clone()).unwrap();
+        bob_store.add_private_vid(bob.clone()).unwrap();
+        bob_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let (mut request, thread_id) = crate::crypto::seal_and_hash(
+            &alice,
+            bob.vid(),
+            None,
+            Payload::RequestRelationship {
+                route: None,
+                ratchet_key: None,
+            },
+        )
+        .unwrap();
+        alice_store
+            .request_relationship(bob.identifier(), thread_id, None)
+            .unwrap();
+        bob_store.open_message(&mut request).unwrap();
+
+        // bob declines, and alice no longer waits for an answer
+        assert!(bob_store
+            .seal_relationship_reject(bob.identifier(), alice.identifier(), [0; 32])
+            .is_err());
+        let (_, mut reject) = bob_store
+            .seal_relationship_reject(bob.identifier(), alice.identifier(), thread_id)
+            .unwrap();
+
+        // the relationship only changes once the rejection was sent
+        assert_eq!(
+            bob_store
+                .get_vid(alice.identifier())
+                .unwrap()
+                .relation_status,
+            RelationshipStatus::PendingIncoming(thread_id)
+        );
+        bob_store
+            .reject_relationship(alice.identifier(), thread_id)
+            .unwrap();
+        assert_eq!(
+            bob_store
+                .get_vid(alice.identifier())
+                .unwrap()
+                .relation_status,
+            RelationshipStatus::Rejected
+        );
+
+        let alice_events = alice_store.relationship_events().unwrap();
+        assert!(matches!(
+            alice_store.open_message(&mut reject.clone()).unwrap(),
+            ReceivedTspMessage::RejectRelationship { .. }
+        ));
+        assert_eq!(
+            alice_events.try_recv().unwrap(),
+            RelationshipEvent {
+                vid: bob.identifier().to_string(),
+                previous: RelationshipStatus::Unidirectional(thread_id),
+                status: RelationshipStatus::Rejected,
+            }
+        );
+
+        // a rejection is only accepted once
+        assert!(matches!(
+            alice_store.open_message(&mut reject),
+            Err(Error::Relationship(_))
+        ));
+
+        // requests that are not answered expire
+        alice_store
+            .request_relationship(bob.identifier(), thread_id, None)
+            .unwrap();
+        assert_eq!(
+            alice_events.try_recv().unwrap().status,
+            RelationshipStatus::Unidirectional(thread_id)
+        );
+        assert!(alice_store
+            .expire_relationship_requests(Duration::from_secs(60))
+            .unwrap()
+            .is_empty());
+
+        alice_store
+            .modify_vid(bob.identifier(), |context| {
+                context.pending_since = Some(0);
+
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            alice_store
+                .expire_relationship_requests(Duration::from_secs(60))
+                .unwrap(),
+            vec![bob.identifier().to_string()]
+        );
+        assert_eq!(
+            alice_events.try_recv().unwrap().status,
+            RelationshipStatus::Expired
+        );
+
+        // relationships that can not expire are skipped
+        bob_store
+            .modify_vid(alice.identifier(), |context| {
+                context.pending_since = Some(0);
+
+                Ok(())
+            })
+            .unwrap();
+        assert!(bob_store
+            .expire_relationship_requests(Duration::from_secs(60))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_make_relationship() {
+        let alice_store = Store::new();
+        let bob_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        alice_store.add_private_vid(alice.clone()).unwrap();
+        alice_store.add_verified_vid(bob.vid().clone()).unwrap();
+        bob_store.add_private_vid(bob.clone()).unwrap();
+        bob_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let status = |store: &Store, vid: &OwnedVid| {
+            store.get_vid(vid.identifier()).unwrap().relation_status
+        };
+
+        let (_, mut request, thread_id) = alice_store
+            .make_relationship_request(alice.identifier(), bob.identifier())
+            .unwrap();
+        assert_eq!(
+            status(&alice_store, &bob),
+            RelationshipStatus::Unidirectional(thread_id)
+        );
+
+        let ReceivedTspMessage::RequestRelationship {
+            thread_id: received_thread_id,
+            ..
+        } = bob_store.open_message(&mut request).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(received_thread_id, thread_id);
+
+        // only a request that was received can be accepted
+        assert!(bob_store
+            .make_relationship_accept(bob.identifier(), alice.identifier(), [0; 32])
+            .is_err());
+        let (_, mut accept) = bob_store
+            .make_relationship_accept(bob.identifier(), alice.identifier(), thread_id)
+            .unwrap();
+        assert_eq!(
+            status(&bob_store, &alice),
+            RelationshipStatus::Bidirectional(thread_id)
+        );
+
+        alice_store.open_message(&mut accept).unwrap();
+        assert_eq!(
+            status(&alice_store, &bob),
+            RelationshipStatus::Bidirectional(thread_id)
+        );
+        assert!(alice_store
+            .make_relationship_request(alice.identifier(), bob.identifier())
+            .is_err());
+
+        let (_, mut cancel) = alice_store
+            .make_relationship_cancel(alice.identifier(), bob.identifier())
+            .unwrap();
+        assert_eq!(status(&alice_store, &bob), RelationshipStatus::Cancelled);
+
+        bob_store.open_message(&mut cancel).unwrap();
+        assert_eq!(status(&bob_store, &alice), RelationshipStatus::Cancelled);
+
+        // a new request can be rejected
+        let (_, mut request, thread_id) = bob_store
+            .make_relationship_request(bob.identifier(), alice.identifier())
+            .unwrap();
+        alice_store.open_message(&mut request).unwrap();
+
+        let (_, mut reject) = alice_store
+            .make_relationship_reject(alice.identifier(), bob.identifier(), thread_id)
+            .unwrap();
+        assert_eq!(status(&alice_store, &bob), RelationshipStatus::Rejected);
+
+        bob_store.open_message(&mut reject).unwrap();
+        assert_eq!(status(&bob_store, &alice), RelationshipStatus::Rejected);
+    }
+
+    #[test]
+    fn test_ignored_relationship_request() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, sealed) = store
+            .seal_message_payload(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                Payload::RequestRelationship {
+                    route: None,
+                    ratchet_key: None,
+                },
+            )
+            .unwrap();
+
+        // the request is decided about by the VID it was addressed to
+        let mut decided = None;
+        let received = store
+            .open_message_deciding(&mut sealed.clone(), &mut |receiver, sender| {
+                decided = Some((receiver.to_string(), sender.to_string()));
+
+                RelationshipDecision::Ignore
+            })
+            .unwrap();
+        assert!(matches!(
+            received,
+            ReceivedTspMessage::RequestRelationship { .. }
+        ));
+        assert_eq!(
+            decided,
+            Some((bob.identifier().to_string(), alice.identifier().to_string()))
+        );
+
+        // an ignored request does not change the relationship
+        assert_eq!(
+            store.get_vid(alice.identifier()).unwrap().relation_status,
+            RelationshipStatus::Unrelated
+        );
+
+        let ReceivedTspMessage::RequestRelationship { thread_id, .. } =
+            store.open_message(&mut sealed.clone()).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(
+            store.get_vid(alice.identifier()).unwrap().relation_status,
+            RelationshipStatus::PendingIncoming(thread_id)
+        );
+    }
+
+    #[test]
+    fn test_evidence() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, sealed) = store
+            .seal_message(
+                alice.identifier(),
+                bob.identifier(),
+                Some(b"metadata"),
+                b"hello world",
+            )
+            .unwrap();
+
+        let evidence = store.create_evidence(&sealed).unwrap();
+        assert_eq!(evidence.sender().identifier(), alice.identifier());
+        assert_eq!(evidence.message(), &sealed[..]);
+        assert_eq!(
+            store.verify_evidence(&evidence).unwrap(),
+            Some(&b"metadata"[..])
+        );
+
+        // the evidence can be verified after the sender is forgotten
+        store.forget_vid(alice.identifier()).unwrap();
+        assert!(matches!(
+            store.verify_evidence(&evidence),
+            Err(Error::UnverifiedVid(_))
+        ));
+        assert_eq!(
+            evidence.verify(alice.vid()).unwrap(),
+            Some(&b"metadata"[..])
+        );
+
+        // also after the sender rotated its encryption key
+        struct Rotated(OwnedVid, OwnedVid);
+
+        impl VerifiedVid for Rotated {
+            fn identifier(&self) -> &str {
+                self.0.identifier()
+            }
+
+            fn endpoint(&self) -> &url::Url {
+                self.0.endpoint()
+            }
+
+            fn verifying_key(&self) -> crate::definitions::PublicKeyData {
+                self.0.verifying_key()
+            }
+
+            fn encryption_key(&self) -> crate::definitions::EncryptionKeyData {
+                self.1.encryption_key()
+            }
+
+            fn cipher_suite(&self) -> crate::definitions::CipherSuite {
+                self.0.cipher_suite()
+            }
+        }
+
+        let rotated = Rotated(alice.clone(), new_vid());
+        assert_ne!(rotated.encryption_key(), alice.encryption_key());
+        assert_eq!(evidence.verify(&rotated).unwrap(), Some(&b"metadata"[..]));
+
+        // but only against the verifying key of the sender
+        let eve = OwnedVid::bind(alice.identifier(), alice.endpoint().clone());
+        assert!(evidence.verify(eve.vid()).is_err());
+
+        // there is no evidence for a tampered message
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(Evidence::new(evidence.sender().clone(), &tampered).is_err());
+        assert!(store.create_evidence(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_seal_open_multicast() {
+        let alice_store = Store::new();
+        let alice = new_vid();
+        alice_store.add_private_vid(alice.clone()).unwrap();
+
+        let receivers = [new_vid(), new_vid(), new_vid()];
+        for vid in &receivers {
+            alice_store.add_verified_vid(vid.clone()).unwrap();
+        }
+
+        let message = b"hello group";
+        let sealed = alice_store
+            .seal_multicast(
+                alice.identifier(),
+                receivers.iter().map(|vid| vid.identifier()),
+                Some(b"group header"),
+                message,
+            )
+            .unwrap();
+
+        for vid in &receivers {
+            let store = Store::new();
+            store.add_private_vid(vid.clone()).unwrap();
+            store.add_verified_vid(alice.clone()).unwrap();
+
+            let ReceivedTspMessage::MulticastMessage {
+                sender,
+                receiver,
+                nonconfidential_data,
+                message: received_message,
+            } = store.open_message(&mut sealed.clone()).unwrap()
+            else {
+                panic!("unexpected message type");
+            };
+
+            assert_eq!(sender, alice.identifier());
+            assert_eq!(receiver, vid.identifier());
+            assert_eq!(nonconfidential_data.unwrap(), b"group header");
+            assert_eq!(received_message, message);
+        }
+
+        // someone who is not in the group cannot open the message
+        let outsider = new_vid();
+        let store = Store::new();
+        store.add_private_vid(outsider).unwrap();
+        store.add_verified_vid(alice.clone()).unwrap();
+
+        assert!(matches!(
+            store.open_message(&mut sealed.clone()),
+            Err(Error::Crypto(CryptoError::UnexpectedRecipient))
+        ));
+    }
 }
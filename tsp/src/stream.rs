@@ -0,0 +1,251 @@
+use crate::{
+    definitions::{Digest, ReceivedTspMessage, TSPStream},
+    error::Error,
+};
+use std::{
+    collections::BTreeMap,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// The maximum amount of payload data in a single TSP message of a stream
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The maximum number of chunks that can arrive ahead of the chunk we are waiting for
+const MAX_PENDING_CHUNKS: usize = 64;
+
+/// Read from `reader` until `buffer` is full or the end of the input is reached
+pub(crate) async fn read_chunk(
+    reader: &mut (impl AsyncRead + Unpin),
+    buffer: &mut [u8],
+) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Reassembles a payload that was sent as a sequence of TSP messages (see
+/// [crate::AsyncStore::send_stream]) and exposes it as an [AsyncRead].
+///
+/// The first stream chunk that is received determines the stream that is read; other messages
+/// are skipped. Chunks that arrive out of order are buffered; reading fails if a chunk is
+/// duplicated or follows the last chunk, or if the messages end before the last chunk of the
+/// stream was received.
+pub struct PayloadReader {
+    messages: TSPStream<ReceivedTspMessage, Error>,
+    sender: Option<String>,
+    stream_id: Option<Digest>,
+    next_index: u64,
+    last_index: Option<u64>,
+    pending: BTreeMap<u64, (Vec<u8>, bool)>,
+    chunk: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl PayloadReader {
+    /// Read the first stream that is found in `messages`
+    pub fn new(messages: TSPStream<ReceivedTspMessage, Error>) -> Self {
+        PayloadReader {
+            messages,
+            sender: None,
+            stream_id: None,
+            next_index: 0,
+            last_index: None,
+            pending: BTreeMap::new(),
+            chunk: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// The sender of the stream, once its first chunk has been received
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_deref()
+    }
+
+    /// The identifier of the stream, once its first chunk has been received
+    pub fn stream_id(&self) -> Option<&Digest> {
+        self.stream_id.as_ref()
+    }
+
+    /// Store a received chunk of this stream until it can be read
+    fn accept_chunk(
+        &mut self,
+        sender: String,
+        stream_id: Digest,
+        index: u64,
+        last: bool,
+        data: Vec<u8>,
+    ) -> io::Result<()> {
+        let duplicate = index < self.next_index || self.pending.contains_key(&index);
+
+        // there is only one last chunk, and no chunk can follow it
+        let after_last = self.last_index.is_some_and(|last_index| index > last_index)
+            || (last && self.last_index.is_some())
+            || (last && self.pending.range(index..).next().is_some());
+
+        if duplicate || after_last {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid chunk {index} in stream"),
+            ));
+        }
+
+        if self.pending.len() >= MAX_PENDING_CHUNKS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many chunks received out of order",
+            ));
+        }
+
+        self.sender = Some(sender);
+        self.stream_id = Some(stream_id);
+        self.pending.insert(index, (data, last));
+
+        if last {
+            self.last_index = Some(index);
+        }
+
+        Ok(())
+    }
+}
+
+impl AsyncRead for PayloadReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.position < this.chunk.len() {
+                let n = buf.remaining().min(this.chunk.len() - this.position);
+                buf.put_slice(&this.chunk[this.position..this.position + n]);
+                this.position += n;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+
+            if let Some((data, last)) = this.pending.remove(&this.next_index) {
+                this.next_index += 1;
+                this.chunk = data;
+                this.position = 0;
+                this.finished = last;
+
+                continue;
+            }
+
+            let message = match ready!(this.messages.as_mut().poll_next(cx)) {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                None => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+            };
+
+            let ReceivedTspMessage::StreamChunk {
+                sender,
+                stream_id,
+                index,
+                last,
+                data,
+            } = message
+            else {
+                continue;
+            };
+
+            // skip chunks that belong to a different stream
+            if this.stream_id.is_some_and(|id| id != stream_id)
+                || this.sender.as_ref().is_some_and(|s| *s != sender)
+            {
+                continue;
+            }
+
+            this.accept_chunk(sender, stream_id, index, last, data)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PayloadReader;
+    use crate::{definitions::ReceivedTspMessage, Error};
+    use tokio::io::AsyncReadExt;
+
+    fn chunk(index: u64, last: bool, data: &[u8]) -> Result<ReceivedTspMessage, Error> {
+        Ok(ReceivedTspMessage::StreamChunk {
+            sender: "alice".to_string(),
+            stream_id: [1; 32],
+            index,
+            last,
+            data: data.to_vec(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_reassemble_out_of_order() {
+        let messages = vec![
+            chunk(1, false, b"beautiful "),
+            Ok(ReceivedTspMessage::AcceptRelationship {
+                sender: "bob".to_string(),
+            }),
+            chunk(0, false, b"hello "),
+            chunk(2, true, b"world"),
+        ];
+
+        let mut reader = PayloadReader::new(Box::pin(futures::stream::iter(messages)));
+        let mut payload = String::new();
+        reader.read_to_string(&mut payload).await.unwrap();
+
+        assert_eq!(payload, "hello beautiful world");
+        assert_eq!(reader.sender(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_stream() {
+        let messages = vec![chunk(0, false, b"hello "), chunk(0, false, b"hello ")];
+
+        let mut reader = PayloadReader::new(Box::pin(futures::stream::iter(messages)));
+        let mut payload = Vec::new();
+        assert!(reader.read_to_end(&mut payload).await.is_err());
+
+        let messages = vec![chunk(0, false, b"hello ")];
+
+        let mut reader = PayloadReader::new(Box::pin(futures::stream::iter(messages)));
+        let mut payload = Vec::new();
+        assert!(reader.read_to_end(&mut payload).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chunks_after_last() {
+        let streams = [
+            // a chunk after the last chunk
+            vec![chunk(1, true, b"world"), chunk(2, false, b"!")],
+            // the last chunk before a chunk that follows it
+            vec![chunk(2, false, b"!"), chunk(1, true, b"world")],
+            // a second last chunk
+            vec![chunk(2, true, b"world"), chunk(1, true, b"world")],
+        ];
+
+        for messages in streams {
+            let mut reader = PayloadReader::new(Box::pin(futures::stream::iter(messages)));
+            let mut payload = Vec::new();
+            let error = reader.read_to_end(&mut payload).await.unwrap_err();
+
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -181,6 +181,15 @@ async fn test_nested_mode() {
         .verify_vid(nested_alice_vid.identifier())
         .await
         .unwrap();
+    bob_db
+        .set_parent_for_vid(nested_alice_vid.identifier(), Some(alice_vid.identifier()))
+        .unwrap();
+    bob_db
+        .set_relation_for_vid(
+            nested_bob_vid.identifier(),
+            Some(nested_alice_vid.identifier()),
+        )
+        .unwrap();
 
     // send a message using inner vid
     alice_db
@@ -520,3 +529,339 @@ async fn test_relation_forming() {
 
     assert_eq!(sender, "did:web:did.tsp-test.org:user:bob");
 }
+
+#[tokio::test]
+#[serial_test::serial(tcp)]
+async fn test_streamed_payload() {
+    crate::transport::tcp::start_broadcast_server("127.0.0.1:1337")
+        .await
+        .unwrap();
+
+    let alice_vid = OwnedVid::from_file("../examples/test/alice.json")
+        .await
+        .unwrap();
+    let bob_vid = OwnedVid::from_file("../examples/test/bob.json")
+        .await
+        .unwrap();
+
+    // bob database
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob_vid.clone()).unwrap();
+    bob_db.add_verified_vid(alice_vid.clone()).unwrap();
+
+    let mut bobs_stream = bob_db.receive_stream(bob_vid.identifier()).await.unwrap();
+
+    // alice database
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice_vid.clone()).unwrap();
+    alice_db.add_verified_vid(bob_vid.clone()).unwrap();
+
+    // a payload that does not fit in a single chunk
+    let payload = (0..200_000u32).map(|i| i as u8).collect::<Vec<u8>>();
+
+    let stream_id = alice_db
+        .send_stream(
+            alice_vid.identifier(),
+            bob_vid.identifier(),
+            None,
+            payload.as_slice(),
+        )
+        .await
+        .unwrap();
+
+    let mut received = Vec::new();
+    tokio::io::AsyncReadExt::read_to_end(&mut bobs_stream, &mut received)
+        .await
+        .unwrap();
+
+    assert_eq!(received, payload);
+    assert_eq!(bobs_stream.stream_id(), Some(&stream_id));
+    assert_eq!(bobs_stream.sender(), Some(alice_vid.identifier()));
+}
+
+#[tokio::test]
+#[serial_test::serial(tcp)]
+async fn attack_replay() {
+    crate::transport::tcp::start_broadcast_server("127.0.0.1:1337")
+        .await
+        .unwrap();
+
+    let alice_vid = OwnedVid::from_file("../examples/test/alice.json")
+        .await
+        .unwrap();
+    let bob_vid = OwnedVid::from_file("../examples/test/bob.json")
+        .await
+        .unwrap();
+
+    // bob database
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob_vid.clone()).unwrap();
+    bob_db.add_verified_vid(alice_vid.clone()).unwrap();
+
+    let mut bobs_messages = bob_db.receive(bob_vid.identifier()).await.unwrap();
+
+    // alice database
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice_vid.clone()).unwrap();
+    alice_db.add_verified_vid(bob_vid.clone()).unwrap();
+
+    let sealed = alice_db
+        .send_fresh(
+            alice_vid.identifier(),
+            bob_vid.identifier(),
+            None,
+            b"hello world",
+        )
+        .await
+        .unwrap();
+
+    assert!(bobs_messages.next().await.unwrap().is_ok());
+
+    // an attacker replays the message
+    crate::transport::send_message(bob_vid.endpoint(), &sealed)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        bobs_messages.next().await.unwrap(),
+        Err(crate::Error::Replay(_))
+    ));
+
+    // a stale message is rejected as well
+    let stale = crate::crypto::seal(
+        &alice_vid,
+        &bob_vid,
+        None,
+        super::Payload::FreshContent {
+            message_id: [1; 32],
+            timestamp: crate::freshness::now() - crate::freshness::MAX_MESSAGE_AGE - 1,
+            data: b"hello world",
+        },
+    )
+    .unwrap();
+    crate::transport::send_message(bob_vid.endpoint(), &stale)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        bobs_messages.next().await.unwrap(),
+        Err(crate::Error::Replay(_))
+    ));
+}
+
+#[tokio::test]
+#[serial_test::serial(tcp)]
+async fn test_receipt() {
+    crate::transport::tcp::start_broadcast_server("127.0.0.1:1337")
+        .await
+        .unwrap();
+
+    let alice_vid = OwnedVid::from_file("../examples/test/alice.json")
+        .await
+        .unwrap();
+    let bob_vid = OwnedVid::from_file("../examples/test/bob.json")
+        .await
+        .unwrap();
+
+    // bob database
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob_vid.clone()).unwrap();
+    bob_db.add_verified_vid(alice_vid.clone()).unwrap();
+
+    let mut bobs_messages = bob_db.receive(bob_vid.identifier()).await.unwrap();
+
+    // alice database
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice_vid.clone()).unwrap();
+    alice_db.add_verified_vid(bob_vid.clone()).unwrap();
+
+    let mut alices_messages = alice_db.receive(alice_vid.identifier()).await.unwrap();
+
+    let receipt = alice_db
+        .send_with_receipt(
+            alice_vid.identifier(),
+            bob_vid.identifier(),
+            None,
+            b"hello world",
+        )
+        .await
+        .unwrap();
+    let digest = *receipt.digest();
+
+    // bob receives the message, which sends the receipt
+    let crate::definitions::ReceivedTspMessage::GenericMessage {
+        receipt_requested, ..
+    } = bobs_messages.next().await.unwrap().unwrap()
+    else {
+        panic!("bob did not receive a generic message")
+    };
+    assert_eq!(receipt_requested, Some(digest));
+
+    // the broadcast server also delivers alice's own message to her, skip it
+    let next_receipt = async {
+        loop {
+            if let Some(Ok(message)) = alices_messages.next().await {
+                break message;
+            }
+        }
+    };
+
+    let (received, result) = tokio::join!(
+        next_receipt,
+        receipt.wait(std::time::Duration::from_secs(5))
+    );
+
+    let crate::definitions::ReceivedTspMessage::Receipt {
+        sender,
+        message_digest,
+    } = received
+    else {
+        panic!("alice did not receive a receipt")
+    };
+    assert_eq!(sender, bob_vid.identifier());
+    assert_eq!(message_digest, digest);
+    assert!(result.is_ok());
+
+    // without a receiver, no receipt arrives
+    let receipt = alice_db
+        .send_with_receipt(
+            alice_vid.identifier(),
+            bob_vid.identifier(),
+            None,
+            b"hello world",
+        )
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        receipt.wait(std::time::Duration::from_millis(100)).await,
+        Err(crate::Error::MissingReceipt)
+    ));
+}
+
+/// Regenerates the seed corpus of the fuzz targets in `fuzz/corpus`, run this after changing
+/// the wire format using `cargo test -p tsp -- --ignored generate_fuzz_corpus`
+#[tokio::test]
+#[ignore]
+async fn generate_fuzz_corpus() {
+    use crate::cesr::{encode_payload_vec, Nonce, Payload};
+
+    let store = crate::Store::new();
+    for path in ["../examples/test/alice.json", "../examples/test/bob.json"] {
+        store
+            .add_private_vid(OwnedVid::from_file(path).await.unwrap())
+            .unwrap();
+    }
+
+    let alice = "did:web:did.tsp-test.org:user:alice";
+    let bob = "did:web:did.tsp-test.org:user:bob";
+    let extra = Some(b"extra data".as_slice());
+    let data = b"hello world".as_slice();
+
+    let write = |targets: &[&str], name: &str, contents: &[u8]| {
+        for target in targets {
+            let dir = format!("{}/../fuzz/corpus/{target}", env!("CARGO_MANIFEST_DIR"));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(format!("{dir}/{name}"), contents).unwrap();
+        }
+    };
+
+    let (_, generic_message) = store.seal_message(alice, bob, extra, data).unwrap();
+    let (_, signed_message) = store.sign_message(alice, bob, extra, data).unwrap();
+    let (_, acknowledged_message, digest) = store
+        .seal_message_with_receipt(alice, bob, extra, data)
+        .unwrap();
+    let (_, threaded_message, _) = store
+        .seal_threaded_message(alice, bob, extra, digest, Some(digest), data)
+        .unwrap();
+
+    let messages = [
+        ("generic_message", generic_message.clone()),
+        ("signed_message", signed_message.clone()),
+        ("acknowledged_message", acknowledged_message),
+        ("threaded_message", threaded_message),
+        (
+            "compressed_message",
+            store
+                .seal_compressed_message(alice, bob, extra, &data.repeat(16))
+                .unwrap()
+                .1,
+        ),
+        (
+            "fresh_message",
+            store.seal_fresh_message(alice, bob, extra, data).unwrap().1,
+        ),
+        (
+            "receipt_message",
+            store.seal_receipt(bob, alice, digest).unwrap().1,
+        ),
+    ];
+
+    for (name, message) in messages {
+        write(
+            &[
+                "decode_envelope",
+                "decode_envelope_mut",
+                "open_message",
+                "inspect",
+            ],
+            name,
+            &message,
+        );
+    }
+
+    let payloads: [(&str, Payload<&[u8], &[u8]>); 8] = [
+        ("generic_payload", Payload::GenericMessage(data)),
+        (
+            "fresh_payload",
+            Payload::FreshMessage {
+                message_id: &[1; 32],
+                timestamp: 1_700_000_000,
+                data,
+            },
+        ),
+        (
+            "threaded_payload",
+            Payload::ThreadedMessage {
+                nonce: Nonce::generate(|bytes| bytes.fill(2)),
+                thread_id: &[3; 32],
+                reply_to: Some(&[4; 32]),
+                data,
+            },
+        ),
+        (
+            "nested_payload",
+            Payload::NestedMessage(signed_message.as_slice()),
+        ),
+        (
+            "routed_payload",
+            Payload::RoutedMessage(vec![bob.as_bytes()], generic_message.as_slice()),
+        ),
+        (
+            "stream_chunk_payload",
+            Payload::StreamChunk {
+                stream_id: &[5; 32],
+                index: 0,
+                last: true,
+                data,
+            },
+        ),
+        (
+            "relation_proposal_payload",
+            Payload::DirectRelationProposal {
+                nonce: Nonce::generate(|bytes| bytes.fill(6)),
+                hops: vec![],
+                ratchet_key: None,
+            },
+        ),
+        ("receipt_payload", Payload::Receipt { reply: &[7; 32] }),
+    ];
+
+    for (name, payload) in payloads {
+        write(
+            &["decode_payload", "inspect"],
+            name,
+            &encode_payload_vec(payload).unwrap(),
+        );
+    }
+}
@@ -13,11 +13,22 @@ pub(crate) const SCHEME_WS: &str = "ws";
 pub(crate) const SCHEME_WSS: &str = "wss";
 
 pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
+    post(tsp_message, "application/octet-stream", url).await
+}
+
+/// Send a message in the CESR text domain as text
+#[cfg(feature = "cesr-t")]
+pub(crate) async fn send_text_message(tsp_message: &str, url: &Url) -> Result<(), TransportError> {
+    post(tsp_message.as_bytes(), "text/plain", url).await
+}
+
+async fn post(tsp_message: &[u8], content_type: &str, url: &Url) -> Result<(), TransportError> {
     let client = reqwest::Client::new();
     let url = url.clone();
 
     client
         .post(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, content_type)
         .body(tsp_message.to_vec())
         .send()
         .await
@@ -51,6 +62,10 @@ pub(crate) async fn receive_messages(
                 tokio_tungstenite::tungstenite::Message::Binary(b) => {
                     yield Ok(BytesMut::from(&b[..]));
                 }
+                #[cfg(feature = "cesr-t")]
+                tokio_tungstenite::tungstenite::Message::Text(t) => {
+                    yield Ok(BytesMut::from(t.as_bytes()));
+                }
                 m => {
                     yield Err(TransportError::InvalidMessageReceived(
                         m
@@ -20,6 +20,19 @@ pub async fn send_message(transport: &Url, tsp_message: &[u8]) -> Result<(), Tra
     }
 }
 
+/// Send a TSP message that is encoded in the CESR text domain; over HTTP, it is sent as text
+#[cfg(feature = "cesr-t")]
+pub async fn send_text_message(transport: &Url, tsp_message: &str) -> Result<(), TransportError> {
+    match transport.scheme() {
+        tcp::SCHEME => tcp::send_message(tsp_message.as_bytes(), transport).await,
+        http::SCHEME_HTTP => http::send_text_message(tsp_message, transport).await,
+        http::SCHEME_HTTPS => http::send_text_message(tsp_message, transport).await,
+        _ => Err(TransportError::InvalidTransportScheme(
+            transport.scheme().to_string(),
+        )),
+    }
+}
+
 pub async fn receive_messages(
     transport: &Url,
 ) -> Result<TSPStream<BytesMut, TransportError>, TransportError> {
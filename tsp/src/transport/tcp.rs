@@ -9,7 +9,7 @@ use tokio::{
 };
 use tokio_util::{
     bytes::BytesMut,
-    codec::{BytesCodec, Framed},
+    codec::{Decoder, Encoder, Framed},
 };
 use url::Url;
 
@@ -38,6 +38,43 @@ pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), Tr
     Ok(())
 }
 
+/// Splits a TCP stream into TSP messages, using the CESR encoding to find the message boundaries.
+/// With the `cesr-t` feature, messages in the CESR text domain are recognized as well.
+/// Data that cannot be recognized as a TSP message is an error, as the stream cannot be
+/// split any further.
+#[derive(Debug, Default)]
+struct TspCodec;
+
+impl Decoder for TspCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, io::Error> {
+        let length = match src.first() {
+            None => return Ok(None),
+            // count codes, which every message starts with, start with a dash in the text domain
+            #[cfg(feature = "cesr-t")]
+            Some(b'-') => crate::cesr::text_message_length(src),
+            Some(_) => crate::cesr::message_length(src),
+        };
+
+        match length.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+            Some(length) => Ok(Some(src.split_to(length))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<BytesMut> for TspCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: BytesMut, dst: &mut BytesMut) -> Result<(), io::Error> {
+        dst.extend_from_slice(&item);
+
+        Ok(())
+    }
+}
+
 pub(crate) async fn receive_messages(
     address: &Url,
 ) -> Result<TSPStream<BytesMut, TransportError>, TransportError> {
@@ -52,7 +89,7 @@ pub(crate) async fn receive_messages(
     let stream = tokio::net::TcpStream::connect(address)
         .await
         .map_err(|e| TransportError::Connection(address.to_string(), e))?;
-    let mut messages = Framed::new(stream, BytesCodec::new());
+    let mut messages = Framed::new(stream, TspCodec);
 
     Ok(Box::pin(stream! {
         while let Some(m) = messages.next().await {
@@ -107,7 +144,7 @@ struct Shared {
 }
 
 struct Peer {
-    messages: Framed<TcpStream, BytesCodec>,
+    messages: Framed<TcpStream, TspCodec>,
     rx: Rx,
 }
 
@@ -130,7 +167,7 @@ impl Shared {
 impl Peer {
     async fn new(
         state: Arc<Mutex<Shared>>,
-        messages: Framed<TcpStream, BytesCodec>,
+        messages: Framed<TcpStream, TspCodec>,
     ) -> io::Result<Peer> {
         let addr = messages.get_ref().peer_addr()?;
         let (tx, rx) = mpsc::unbounded_channel();
@@ -150,7 +187,7 @@ async fn process(
 
     tracing::info!("{} connected", peer_id);
 
-    let messages = Framed::new(stream, BytesCodec::new());
+    let messages = Framed::new(stream, TspCodec);
     let mut peer = Peer::new(state.clone(), messages)
         .await
         .map_err(|e| TransportError::Connection(addr.to_string(), e))?;
@@ -46,6 +46,27 @@ pub(crate) mod serde_key_data {
     }
 }
 
+pub(crate) mod serde_key_bytes {
+    use base64ct::{Base64UrlUnpadded, Encoding};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(key: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let key = Base64UrlUnpadded::encode_string(key);
+        serializer.serialize_str(&key)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let encoded: &str = Deserialize::deserialize(deserializer)?;
+        Base64UrlUnpadded::decode_vec(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
 pub(crate) mod serde_sigkey {
     use super::Ed;
     use base64ct::{Base64UrlUnpadded, Encoding};
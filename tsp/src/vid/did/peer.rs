@@ -1,4 +1,8 @@
-use crate::{definitions::VerifiedVid, vid::error::VidError, Vid};
+use crate::{
+    definitions::{CipherSuite, VerifiedVid},
+    vid::error::VidError,
+    Vid,
+};
 use base64ct::{Base64UrlUnpadded, Encoding};
 use serde_json::json;
 use url::Url;
@@ -77,7 +81,7 @@ pub(crate) fn verify_did_peer(parts: &[&str]) -> Result<Vid, VidError> {
                     ));
                 }
 
-                public_enckey = enckey_bytes[2..].try_into().ok();
+                public_enckey = (enckey_bytes.len() == 34).then(|| enckey_bytes[2..].to_vec());
             }
             // Authentication (Verification) + base58 multibase prefix
             "Vz" => {
@@ -127,6 +131,7 @@ pub(crate) fn verify_did_peer(parts: &[&str]) -> Result<Vid, VidError> {
             transport,
             public_sigkey,
             public_enckey,
+            cipher_suite: CipherSuite::default(),
         }),
         (None, _, _) => Err(VidError::ResolveVid("missing verification key in did:peer")),
         (_, None, _) => Err(VidError::ResolveVid("missing encryption key in did:peer")),
@@ -155,7 +160,8 @@ mod test {
             id: Default::default(),
             transport: Url::parse("tcp://127.0.0.1:1337").unwrap(),
             public_sigkey: sigkey.verifying_key(),
-            public_enckey: public_enckey.to_bytes().into(),
+            public_enckey: public_enckey.to_bytes().to_vec(),
+            cipher_suite: Default::default(),
         };
 
         vid.id = encode_did_peer(&vid);
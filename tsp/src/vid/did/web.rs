@@ -1,4 +1,4 @@
-use crate::definitions::VerifiedVid;
+use crate::definitions::{CipherSuite, VerifiedVid};
 use base64ct::{Base64UrlUnpadded, Encoding};
 use serde::Deserialize;
 use serde_json::json;
@@ -50,7 +50,9 @@ pub struct PublicKeyJwk {
     pub kty: String,
     #[serde(rename = "use")]
     pub usage: String,
+    pub alg: Option<String>,
     pub x: String,
+    pub y: Option<String>,
 }
 
 pub fn resolve_url(parts: &[&str]) -> Result<Url, VidError> {
@@ -65,21 +67,25 @@ pub fn resolve_url(parts: &[&str]) -> Result<Url, VidError> {
     .map_err(|_| VidError::InvalidVid(parts.join(":")))
 }
 
+fn find_first_method<'a>(
+    did_document: &'a DidDocument,
+    method: &[String],
+) -> Option<&'a VerificationMethod> {
+    method.iter().next().and_then(|id| {
+        did_document
+            .verification_method
+            .iter()
+            .find(|item| &item.id == id)
+    })
+}
+
 pub fn find_first_key(
     did_document: &DidDocument,
     method: &[String],
     curve: &str,
     usage: &str,
 ) -> Option<[u8; 32]> {
-    method
-        .iter()
-        .next()
-        .and_then(|id| {
-            did_document
-                .verification_method
-                .iter()
-                .find(|item| &item.id == id)
-        })
+    find_first_method(did_document, method)
         .and_then(|method| {
             if method.public_key_jwk.crv == curve && method.public_key_jwk.usage == usage {
                 Base64UrlUnpadded::decode_vec(&method.public_key_jwk.x).ok()
@@ -90,6 +96,52 @@ pub fn find_first_key(
         .and_then(|key| <[u8; 32]>::try_from(key).ok())
 }
 
+/// Find the first key agreement key and the cipher suite it should be used with; if the
+/// key does not specify an algorithm, the default cipher suite for its curve is used
+pub fn find_encryption_key(did_document: &DidDocument) -> Option<(Vec<u8>, CipherSuite)> {
+    let jwk = &find_first_method(did_document, &did_document.key_agreement)?.public_key_jwk;
+
+    if jwk.usage != "enc" {
+        return None;
+    }
+
+    let cipher_suite = match &jwk.alg {
+        Some(alg) => CipherSuite::from_name(alg)?,
+        None => CipherSuite::ALL
+            .iter()
+            .copied()
+            .find(|suite| suite.curve() == jwk.crv)?,
+    };
+
+    if cipher_suite.curve() != jwk.crv {
+        return None;
+    }
+
+    let x = Base64UrlUnpadded::decode_vec(&jwk.x).ok()?;
+    let key = match (cipher_suite, jwk.kty.as_str(), &jwk.y) {
+        (CipherSuite::X25519ChaCha20Poly1305 | CipherSuite::X25519Aes128Gcm, "OKP", None)
+            if x.len() == 32 =>
+        {
+            x
+        }
+        // elliptic curve keys are used as uncompressed SEC1 points
+        (CipherSuite::P256Aes128Gcm, "EC", Some(y)) if x.len() == 32 => {
+            let y = Base64UrlUnpadded::decode_vec(y).ok()?;
+            if y.len() != 32 {
+                return None;
+            }
+
+            [&[0x04], x.as_slice(), y.as_slice()].concat()
+        }
+        // the X25519 public key, followed by the ML-KEM-768 public key
+        #[cfg(feature = "pq")]
+        (CipherSuite::X25519MlKem768ChaCha20Poly1305, "OKP", None) if x.len() == 32 + 1184 => x,
+        _ => return None,
+    };
+
+    Some((key, cipher_suite))
+}
+
 pub fn resolve_document(did_document: DidDocument, target_id: &str) -> Result<Vid, VidError> {
     if did_document.id != target_id {
         return Err(VidError::ResolveVid("Invalid id specified in DID document"));
@@ -107,9 +159,7 @@ pub fn resolve_document(did_document: DidDocument, target_id: &str) -> Result<Vi
         ));
     };
 
-    let Some(public_enckey) =
-        find_first_key(&did_document, &did_document.key_agreement, "X25519", "enc")
-    else {
+    let Some((public_enckey, cipher_suite)) = find_encryption_key(&did_document) else {
         return Err(VidError::ResolveVid(
             "No valid encryption key found in DID document",
         ));
@@ -135,9 +185,43 @@ pub fn resolve_document(did_document: DidDocument, target_id: &str) -> Result<Vi
         transport,
         public_sigkey,
         public_enckey,
+        cipher_suite,
     })
 }
 
+/// The JSON Web Key of the encryption key of a VID
+fn encryption_key_jwk(vid: &Vid) -> serde_json::Value {
+    let cipher_suite = vid.cipher_suite();
+    let key = vid.encryption_key();
+
+    match cipher_suite {
+        CipherSuite::X25519ChaCha20Poly1305 | CipherSuite::X25519Aes128Gcm => json!({
+            "kty": "OKP",
+            "crv": cipher_suite.curve(),
+            "use": "enc",
+            "alg": cipher_suite.name(),
+            "x": Base64UrlUnpadded::encode_string(key),
+        }),
+        // skip the SEC1 tag of the uncompressed point
+        CipherSuite::P256Aes128Gcm => json!({
+            "kty": "EC",
+            "crv": cipher_suite.curve(),
+            "use": "enc",
+            "alg": cipher_suite.name(),
+            "x": Base64UrlUnpadded::encode_string(key.get(1..33).unwrap_or_default()),
+            "y": Base64UrlUnpadded::encode_string(key.get(33..).unwrap_or_default()),
+        }),
+        #[cfg(feature = "pq")]
+        CipherSuite::X25519MlKem768ChaCha20Poly1305 => json!({
+            "kty": "OKP",
+            "crv": cipher_suite.curve(),
+            "use": "enc",
+            "alg": cipher_suite.name(),
+            "x": Base64UrlUnpadded::encode_string(key),
+        }),
+    }
+}
+
 pub fn vid_to_did_document(vid: &Vid) -> serde_json::Value {
     let id = vid.identifier();
 
@@ -163,12 +247,7 @@ pub fn vid_to_did_document(vid: &Vid) -> serde_json::Value {
                 "id": format!("{id}#encryption-key"),
                 "type": "JsonWebKey2020",
                 "controller": format!("{id}"),
-                "publicKeyJwk": {
-                    "kty": "OKP",
-                    "crv": "X25519",
-                    "use": "enc",
-                    "x": Base64UrlUnpadded::encode_string(vid.encryption_key()),
-                }
+                "publicKeyJwk": encryption_key_jwk(vid),
             },
         ],
         "authentication": [
@@ -202,10 +281,11 @@ pub fn create_did_web(
 mod tests {
     use super::resolve_url;
     use crate::{
-        definitions::VerifiedVid,
+        definitions::{CipherSuite, VerifiedVid},
         vid::{
-            did::web::{resolve_document, DidDocument},
+            did::web::{resolve_document, vid_to_did_document, DidDocument},
             error::VidError,
+            OwnedVid,
         },
     };
     use std::fs;
@@ -259,4 +339,30 @@ mod tests {
             "did:web:did.tsp-test.org:user:bob"
         );
     }
+
+    #[test]
+    fn test_cipher_suite_document() {
+        let alice = resolve_document(
+            serde_json::from_str(&fs::read_to_string("../examples/test/alice-did.json").unwrap())
+                .unwrap(),
+            "did:web:did.tsp-test.org:user:alice",
+        )
+        .unwrap();
+        assert_eq!(alice.cipher_suite(), CipherSuite::X25519ChaCha20Poly1305);
+
+        for &cipher_suite in CipherSuite::ALL {
+            let vid = OwnedVid::bind_with_cipher_suite(
+                "did:web:did.tsp-test.org:user:carol",
+                Url::parse("tcp://127.0.0.1:1337").unwrap(),
+                cipher_suite,
+            );
+
+            let did_doc: DidDocument =
+                serde_json::from_value(vid_to_did_document(vid.vid())).unwrap();
+            let resolved = resolve_document(did_doc, vid.identifier()).unwrap();
+
+            assert_eq!(resolved.cipher_suite(), cipher_suite);
+            assert_eq!(resolved.encryption_key(), vid.encryption_key());
+        }
+    }
 }
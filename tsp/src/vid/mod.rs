@@ -1,6 +1,5 @@
-use crate::definitions::{KeyData, PrivateVid, VerifiedVid};
+use crate::definitions::{CipherSuite, KeyData, PrivateVid, VerifiedVid};
 use ed25519_dalek::{self as Ed};
-use hpke::{kem::X25519HkdfSha256 as KemType, Kem, Serializable};
 use rand::rngs::OsRng;
 use std::sync::Arc;
 
@@ -19,7 +18,7 @@ pub mod error;
 pub mod resolve;
 
 #[cfg(feature = "serialize")]
-use deserialize::{serde_key_data, serde_public_sigkey, serde_sigkey};
+use deserialize::{serde_key_bytes, serde_key_data, serde_public_sigkey, serde_sigkey};
 
 #[cfg(feature = "resolve")]
 pub use did::web::{create_did_web, vid_to_did_document};
@@ -43,8 +42,10 @@ pub struct Vid {
     transport: Url,
     #[cfg_attr(feature = "serialize", serde(with = "serde_public_sigkey"))]
     public_sigkey: Ed::VerifyingKey,
-    #[cfg_attr(feature = "serialize", serde(with = "serde_key_data"))]
-    public_enckey: KeyData,
+    #[cfg_attr(feature = "serialize", serde(with = "serde_key_bytes"))]
+    public_enckey: Vec<u8>,
+    #[cfg_attr(feature = "serialize", serde(default))]
+    cipher_suite: CipherSuite,
 }
 
 /// A OwnedVid represents the 'owner' of a particular Vid
@@ -80,7 +81,8 @@ impl Vid {
             id: vid.identifier().to_string(),
             transport: vid.endpoint().clone(),
             public_sigkey: Ed::VerifyingKey::from_bytes(vid.verifying_key()).unwrap(),
-            public_enckey: *vid.encryption_key(),
+            public_enckey: vid.encryption_key().to_vec(),
+            cipher_suite: vid.cipher_suite(),
         }
     }
 }
@@ -92,7 +94,8 @@ impl OwnedVid {
                 id: vid.identifier().to_string(),
                 transport: vid.endpoint().clone(),
                 public_sigkey: Ed::VerifyingKey::from_bytes(vid.verifying_key()).unwrap(),
-                public_enckey: *vid.encryption_key(),
+                public_enckey: vid.encryption_key().to_vec(),
+                cipher_suite: vid.cipher_suite(),
             },
             sigkey: Ed::SigningKey::from_bytes(vid.signing_key()),
             enckey: *vid.decryption_key(),
@@ -113,9 +116,13 @@ impl VerifiedVid for Vid {
         self.public_sigkey.as_bytes()
     }
 
-    fn encryption_key(&self) -> &KeyData {
+    fn encryption_key(&self) -> &[u8] {
         &self.public_enckey
     }
+
+    fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
 }
 
 impl VerifiedVid for OwnedVid {
@@ -131,9 +138,13 @@ impl VerifiedVid for OwnedVid {
         self.vid.verifying_key()
     }
 
-    fn encryption_key(&self) -> &KeyData {
+    fn encryption_key(&self) -> &[u8] {
         self.vid.encryption_key()
     }
+
+    fn cipher_suite(&self) -> CipherSuite {
+        self.vid.cipher_suite()
+    }
 }
 
 impl PrivateVid for OwnedVid {
@@ -153,31 +164,44 @@ impl AsRef<[u8]> for Vid {
 
 impl OwnedVid {
     pub fn bind(id: impl Into<String>, transport: url::Url) -> Self {
+        Self::bind_with_cipher_suite(id, transport, CipherSuite::default())
+    }
+
+    /// Create a new identity that receives messages encrypted using the specified cipher suite
+    pub fn bind_with_cipher_suite(
+        id: impl Into<String>,
+        transport: url::Url,
+        cipher_suite: CipherSuite,
+    ) -> Self {
         let sigkey = Ed::SigningKey::generate(&mut OsRng);
-        let (enckey, public_enckey) = KemType::gen_keypair(&mut OsRng);
+        let (enckey, public_enckey) = crate::crypto::gen_encryption_keypair(cipher_suite);
 
         Self {
             vid: Vid {
                 id: id.into(),
                 transport,
                 public_sigkey: sigkey.verifying_key(),
-                public_enckey: public_enckey.to_bytes().into(),
+                public_enckey,
+                cipher_suite,
             },
             sigkey,
-            enckey: enckey.to_bytes().into(),
+            enckey,
         }
     }
 
+    /// Create a new `did:peer` identity; since a `did:peer` cannot advertise a cipher
+    /// suite, it always uses the default cipher suite
     #[cfg(feature = "resolve")]
     pub fn new_did_peer(transport: Url) -> OwnedVid {
         let sigkey = Ed::SigningKey::generate(&mut OsRng);
-        let (enckey, public_enckey) = KemType::gen_keypair(&mut OsRng);
+        let (enckey, public_enckey) = crate::crypto::gen_encryption_keypair(CipherSuite::default());
 
         let mut vid = Vid {
             id: Default::default(),
             transport,
             public_sigkey: sigkey.verifying_key(),
-            public_enckey: public_enckey.to_bytes().into(),
+            public_enckey,
+            cipher_suite: CipherSuite::default(),
         };
 
         vid.id = crate::vid::did::peer::encode_did_peer(&vid);
@@ -185,7 +209,7 @@ impl OwnedVid {
         Self {
             vid,
             sigkey,
-            enckey: enckey.to_bytes().into(),
+            enckey,
         }
     }
 